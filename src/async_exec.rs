@@ -0,0 +1,197 @@
+use crate::{hook, CommandExec, CommandSpec, Context, ExecError, Pipeline};
+use std::future::Future;
+use std::io::Write;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Asynchronous counterpart of `Exec` (requires the `tokio` feature)
+pub trait AsyncExec {
+    /// Runs a command in the provided context
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    fn exec<'a>(
+        &'a mut self,
+        command: &'a str,
+        args: &'a [&'a str],
+        context: Option<&'a Context>,
+    ) -> impl Future<Output = Result<String, ExecError>> + Send + 'a;
+
+    /// Runs several commands piping stdout of one command into stdin of the next
+    ///
+    /// * `commands` - a vector of tuples of arrays of string containing the command and arguments, and contexts
+    ///
+    fn exec_piped<'a>(
+        &'a mut self,
+        commands: &'a [(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> impl Future<Output = Result<String, ExecError>> + Send + 'a;
+}
+
+/// Executor running commands with `tokio::process` (requires the `tokio` feature)
+///
+/// The commands are wrapped according to the configuration of the `CommandExec`, e.g. for
+/// escalation and ssh. Dropping the returned future kills the commands started so far, so that
+/// cancellation does not leave processes behind. The retry policy waits between the attempts
+/// without blocking; detecting the platform for a resolver blocks the calling thread.
+#[derive(Default, Clone)]
+pub struct AsyncCommandExec {
+    exec: CommandExec,
+}
+
+impl AsyncCommandExec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the commands piped into each other
+    ///
+    /// * `specs` - the commands to be executed
+    ///
+    pub async fn exec_specs_piped(&mut self, specs: &[CommandSpec]) -> Result<String, ExecError> {
+        self.run_specs(specs, None).await
+    }
+
+    /// Runs the pipeline, writing its input to stdin of the first command
+    ///
+    /// * `pipeline` - the commands piped into each other
+    ///
+    pub async fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        self.run_specs(pipeline.stages(), pipeline.get_input())
+            .await
+    }
+
+    async fn run_specs(
+        &mut self,
+        specs: &[CommandSpec],
+        input: Option<&[u8]>,
+    ) -> Result<String, ExecError> {
+        let specs = self.exec.resolve(specs)?;
+        let start = std::time::Instant::now();
+        let result = self.run_retried(&specs, input).await;
+
+        self.exec.after_hooks_with(&specs, start, result, |stdout| {
+            hook::output(stdout.clone(), String::new(), 0)
+        })
+    }
+
+    async fn run_retried(
+        &mut self,
+        specs: &[CommandSpec],
+        input: Option<&[u8]>,
+    ) -> Result<String, ExecError> {
+        let policy = match self.exec.retry.clone() {
+            Some(policy) => policy,
+            None => return self.run_resolved(specs, input).await,
+        };
+        let mut waited = Duration::ZERO;
+        let mut previous = Duration::ZERO;
+        let mut attempt = 0;
+
+        loop {
+            let error = match self.run_resolved(specs, input).await {
+                Ok(output) => return Ok(output),
+                Err(e) => e,
+            };
+            let delay = match policy.next_delay(attempt, previous, waited, &error) {
+                Some(delay) => delay,
+                None => return Err(error),
+            };
+
+            tokio::time::sleep(delay).await;
+            waited += delay;
+            previous = delay;
+            attempt += 1;
+        }
+    }
+
+    async fn run_resolved(
+        &mut self,
+        specs: &[CommandSpec],
+        input: Option<&[u8]>,
+    ) -> Result<String, ExecError> {
+        let mut children = Vec::new();
+        let mut stdout: Option<Stdio> = None;
+        let mut writer = None;
+
+        for (index, spec) in specs.iter().enumerate() {
+            let mut com = self.exec.command(spec)?;
+
+            if index > 0 && spec.get_stdin().is_none() {
+                // the output of the preceding command may have been redirected to a file
+                com.stdin(stdout.take().unwrap_or_else(Stdio::null));
+            }
+
+            if let (0, Some(input), None) = (index, input, spec.get_stdin()) {
+                // a blocking pipe fed by a thread needs no reactor, unlike the stdin of tokio
+                let (reader, mut pipe) = std::io::pipe()?;
+                let input = input.to_vec();
+
+                com.stdin(reader);
+                writer = Some(std::thread::spawn(move || pipe.write_all(&input)));
+            }
+
+            CommandExec::pipe_output(spec, &mut com, false);
+
+            let mut com = tokio::process::Command::from(com);
+            let mut child = com.kill_on_drop(true).spawn()?;
+
+            if index + 1 < specs.len() {
+                stdout = child.stdout.take().map(TryInto::try_into).transpose()?;
+            }
+
+            children.push(child);
+        }
+
+        let last = children.pop().ok_or(ExecError::Chaining)?;
+        let output = last.wait_with_output().await?;
+
+        for child in &mut children {
+            child.wait().await?;
+        }
+
+        if let Some(writer) = writer {
+            match writer.join() {
+                // the command may exit without reading all of its input
+                Ok(Err(e)) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        let output = self.exec.check_status(specs.last(), output)?;
+
+        self.exec.text(output)
+    }
+
+    async fn run_piped(
+        &mut self,
+        commands: Vec<(&str, &[&str], Option<&Context>)>,
+    ) -> Result<String, ExecError> {
+        self.exec_specs_piped(&CommandExec::specs(&commands)).await
+    }
+}
+
+impl From<CommandExec> for AsyncCommandExec {
+    fn from(exec: CommandExec) -> Self {
+        AsyncCommandExec { exec }
+    }
+}
+
+impl AsyncExec for AsyncCommandExec {
+    fn exec<'a>(
+        &'a mut self,
+        command: &'a str,
+        args: &'a [&'a str],
+        context: Option<&'a Context>,
+    ) -> impl Future<Output = Result<String, ExecError>> + Send + 'a {
+        self.run_piped(vec![(command, args, context)])
+    }
+
+    fn exec_piped<'a>(
+        &'a mut self,
+        commands: &'a [(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> impl Future<Output = Result<String, ExecError>> + Send + 'a {
+        self.run_piped(commands.to_vec())
+    }
+}
@@ -0,0 +1,81 @@
+#[cfg(feature = "tokio")]
+use crate::AsyncCommandExec;
+use crate::{CommandExec, Exec, ExecError, Pipeline};
+
+/// Process API the commands are spawned with
+///
+/// Both backends wrap the commands in the same way, following the configuration of the
+/// `CommandExec` (escalation, ssh, resolvers, retry policy, ...), so that the backend can be
+/// chosen per call. The backends run pipelines; single commands and commands piped into each
+/// other are run as pipelines, e.g. `Pipeline::from(spec)`.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Backend {
+    /// Blocking `std::process`
+    #[default]
+    StdProcess,
+    /// Asynchronous `tokio::process`
+    ///
+    /// Requires the `tokio` feature; without it, executions fail with `ExecError::Execution`.
+    TokioProcess,
+}
+
+impl CommandExec {
+    /// Runs the pipeline with the backend chosen for the call, blocking the calling thread
+    ///
+    /// E.g. a command line tool runs the pipeline with `Backend::StdProcess`, while a server runs
+    /// the same code path with `Backend::TokioProcess` and `run_with_async`. With
+    /// `Backend::TokioProcess`, the pipeline runs on a runtime created for the call; within a
+    /// tokio runtime, use `run_with_async` instead.
+    ///
+    /// * `backend` - the process API used for spawning the commands
+    /// * `pipeline` - the commands piped into each other
+    ///
+    pub fn run_with(&mut self, backend: Backend, pipeline: &Pipeline) -> Result<String, ExecError> {
+        match backend {
+            Backend::StdProcess => self.run(pipeline),
+            #[cfg(feature = "tokio")]
+            Backend::TokioProcess => {
+                if tokio::runtime::Handle::try_current().is_ok() {
+                    return Err(ExecError::Execution(
+                        "the blocking tokio backend cannot run within a tokio runtime".into(),
+                    ));
+                }
+
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?
+                    .block_on(self.run_with_async(backend, pipeline))
+            }
+            #[cfg(not(feature = "tokio"))]
+            Backend::TokioProcess => Err(tokio_disabled()),
+        }
+    }
+
+    /// Runs the pipeline with the backend chosen for the call
+    ///
+    /// With `Backend::StdProcess`, the commands run while the future is polled the first time,
+    /// blocking the thread of the executor; `Backend::TokioProcess` has to run within a tokio
+    /// runtime.
+    ///
+    /// * `backend` - the process API used for spawning the commands
+    /// * `pipeline` - the commands piped into each other
+    ///
+    pub async fn run_with_async(
+        &mut self,
+        backend: Backend,
+        pipeline: &Pipeline,
+    ) -> Result<String, ExecError> {
+        match backend {
+            Backend::StdProcess => self.run(pipeline),
+            #[cfg(feature = "tokio")]
+            Backend::TokioProcess => AsyncCommandExec::from(self.clone()).run(pipeline).await,
+            #[cfg(not(feature = "tokio"))]
+            Backend::TokioProcess => Err(tokio_disabled()),
+        }
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+fn tokio_disabled() -> ExecError {
+    ExecError::Execution("the tokio backend requires the tokio feature".into())
+}
@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Token aborting executions from another thread
+///
+/// Clones share the state; cancelling one of them cancels all executions of executors holding a
+/// clone. The children of a cancelled execution are killed and the execution fails with
+/// `ExecError::Cancelled`. Executions started after the cancellation fail right away.
+#[derive(Debug, Default, Clone)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the running and all further executions
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks whether the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
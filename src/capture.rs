@@ -0,0 +1,281 @@
+use crate::{hook, CommandExec, CommandSpec, Context, ExecError};
+use std::io::{BufRead, BufReader, Read};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Chunk of output or termination of a command, stamped with the time since the command was spawned
+#[derive(Debug, PartialEq, Clone)]
+pub enum OutputEvent {
+    Stdout {
+        elapsed: Duration,
+        data: Vec<u8>,
+    },
+    Stderr {
+        elapsed: Duration,
+        data: Vec<u8>,
+    },
+    Exit {
+        elapsed: Duration,
+        code: Option<i32>,
+    },
+}
+
+impl OutputEvent {
+    /// Time between spawning the command and the event
+    pub fn elapsed(&self) -> Duration {
+        match self {
+            OutputEvent::Stdout { elapsed, .. }
+            | OutputEvent::Stderr { elapsed, .. }
+            | OutputEvent::Exit { elapsed, .. } => *elapsed,
+        }
+    }
+}
+
+impl CommandExec {
+    /// Runs the command and records stdout and stderr in the order they were written
+    ///
+    /// Both streams are read concurrently; every chunk becomes an event with a timestamp. The last
+    /// event is always `OutputEvent::Exit`. A non-zero exit code is not treated as an error, so
+    /// that the output of failing commands can be inspected. The timeout and the cancellation token
+    /// apply.
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    pub fn exec_events(&mut self, spec: &CommandSpec) -> Result<Vec<OutputEvent>, ExecError> {
+        self.hooked(spec, CommandExec::run_events, |events| {
+            let mut output = hook::output(String::new(), String::new(), -1);
+
+            for event in events {
+                match event {
+                    OutputEvent::Stdout { data, .. } => {
+                        output.stdout.push_str(&String::from_utf8_lossy(data))
+                    }
+                    OutputEvent::Stderr { data, .. } => {
+                        output.stderr.push_str(&String::from_utf8_lossy(data))
+                    }
+                    OutputEvent::Exit { code, .. } => output.status = code.unwrap_or(-1),
+                }
+            }
+
+            output
+        })
+    }
+
+    fn run_events(&mut self, spec: &CommandSpec) -> Result<Vec<OutputEvent>, ExecError> {
+        let mut com = self.command(spec)?;
+        let mut tree = self.process_tree()?;
+        let start = Instant::now();
+
+        CommandExec::pipe_output(spec, &mut com, true);
+        tree.prepare(spec, &mut com);
+        tree.push(com.spawn()?)?;
+
+        let child = tree.last_mut().ok_or(ExecError::Chaining)?;
+        let (tx, rx) = mpsc::channel();
+        // streams redirected to files are not piped
+        let readers = [
+            child.stdout.take().map(|stdout| {
+                read_chunks(stdout, tx.clone(), start, |elapsed, data| {
+                    OutputEvent::Stdout { elapsed, data }
+                })
+            }),
+            child.stderr.take().map(|stderr| {
+                read_chunks(stderr, tx, start, |elapsed, data| OutputEvent::Stderr {
+                    elapsed,
+                    data,
+                })
+            }),
+        ];
+        let mut events = Vec::new();
+
+        loop {
+            match rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(event) => events.push(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            tree.check_interrupted(start, self.timeout, self.cancellation.as_ref())?;
+        }
+
+        for reader in readers.into_iter().flatten() {
+            reader.join().map_err(|_| ExecError::Chaining)??;
+        }
+
+        let status = tree.wait_interruptible(start, self.timeout, self.cancellation.as_ref())?;
+
+        events.push(OutputEvent::Exit {
+            elapsed: start.elapsed(),
+            code: status.code(),
+        });
+        Ok(events)
+    }
+}
+
+/// Line of output of a command, as sent by the reading threads
+enum Line {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+}
+
+impl CommandExec {
+    /// Runs a command and passes every line of its output to the callbacks as it is written
+    ///
+    /// The callbacks are invoked on the calling thread; the line breaks are removed. stdout is
+    /// returned as well, once the command finished.
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    /// * `on_stdout` - receives the lines written to stdout
+    /// * `on_stderr` - receives the lines written to stderr
+    ///
+    pub fn exec_with_callback(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+        on_stdout: impl FnMut(&str),
+        on_stderr: impl FnMut(&str),
+    ) -> Result<String, ExecError> {
+        let spec = CommandSpec::new(command).args(args);
+        let spec = match context {
+            Some(context) => spec.context(context.clone()),
+            None => spec,
+        };
+
+        self.exec_spec_with_callback(&spec, on_stdout, on_stderr)
+    }
+
+    /// Runs the spec and passes every line of its output to the callbacks as it is written
+    ///
+    /// The lines passed to the callbacks are converted lossily; the returned stdout is decoded as
+    /// a whole according to the decoding of the executor. The timeout and the cancellation token
+    /// apply.
+    ///
+    /// * `spec` - the command to be executed
+    /// * `on_stdout` - receives the lines written to stdout
+    /// * `on_stderr` - receives the lines written to stderr
+    ///
+    pub fn exec_spec_with_callback(
+        &mut self,
+        spec: &CommandSpec,
+        on_stdout: impl FnMut(&str),
+        on_stderr: impl FnMut(&str),
+    ) -> Result<String, ExecError> {
+        self.hooked(
+            spec,
+            |exec, spec| exec.run_with_callback(spec, on_stdout, on_stderr),
+            |stdout| hook::output(stdout.clone(), String::new(), 0),
+        )
+    }
+
+    fn run_with_callback(
+        &mut self,
+        spec: &CommandSpec,
+        mut on_stdout: impl FnMut(&str),
+        mut on_stderr: impl FnMut(&str),
+    ) -> Result<String, ExecError> {
+        let mut com = self.command(spec)?;
+        let mut tree = self.process_tree()?;
+        let start = Instant::now();
+
+        CommandExec::pipe_output(spec, &mut com, true);
+        tree.prepare(spec, &mut com);
+        tree.push(com.spawn()?)?;
+
+        let child = tree.last_mut().ok_or(ExecError::Chaining)?;
+        let (tx, rx) = mpsc::channel();
+        // streams redirected to files are not piped
+        let readers = [
+            child
+                .stdout
+                .take()
+                .map(|stdout| read_lines(stdout, tx.clone(), Line::Stdout)),
+            child
+                .stderr
+                .take()
+                .map(|stderr| read_lines(stderr, tx, Line::Stderr)),
+        ];
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let callback_line = |line: &[u8]| {
+            String::from_utf8_lossy(line)
+                .trim_end_matches(['\r', '\n'])
+                .to_string()
+        };
+
+        loop {
+            match rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(Line::Stdout(line)) => {
+                    on_stdout(&callback_line(&line));
+                    stdout.extend(line);
+                }
+                Ok(Line::Stderr(line)) => {
+                    on_stderr(&callback_line(&line));
+                    stderr.extend(line);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            tree.check_interrupted(start, self.timeout, self.cancellation.as_ref())?;
+        }
+
+        for reader in readers.into_iter().flatten() {
+            reader.join().map_err(|_| ExecError::Chaining)??;
+        }
+
+        let output = std::process::Output {
+            status: tree.wait_interruptible(start, self.timeout, self.cancellation.as_ref())?,
+            stdout,
+            stderr,
+        };
+        let stdout = self.check_status(Some(spec), output)?;
+
+        self.text(stdout)
+    }
+}
+
+fn read_lines<R: Read + Send + 'static>(
+    reader: R,
+    tx: mpsc::Sender<Line>,
+    line: fn(Vec<u8>) -> Line,
+) -> std::thread::JoinHandle<Result<(), ExecError>> {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+
+        loop {
+            let mut buffer = Vec::new();
+
+            if reader.read_until(b'\n', &mut buffer)? == 0 {
+                return Ok(());
+            }
+
+            // the receiver disappears if the caller panicked or the command was interrupted
+            let _ = tx.send(line(buffer));
+        }
+    })
+}
+
+fn read_chunks<R: Read + Send + 'static>(
+    mut reader: R,
+    tx: mpsc::Sender<OutputEvent>,
+    start: Instant,
+    event: fn(Duration, Vec<u8>) -> OutputEvent,
+) -> std::thread::JoinHandle<Result<(), ExecError>> {
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 8192];
+
+        loop {
+            let len = reader.read(&mut buffer)?;
+
+            if len == 0 {
+                return Ok(());
+            }
+
+            // the receiver disappears if the caller panicked or the command was interrupted
+            let _ = tx.send(event(start.elapsed(), buffer[..len].to_vec()));
+        }
+    })
+}
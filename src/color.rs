@@ -0,0 +1,27 @@
+/// Color behavior requested from the executed commands
+///
+/// Commands usually decide about colored output by checking whether stdout is a terminal, which
+/// it never is when the output is captured. The modes set the environment variables honored by
+/// most tools to override this decision. To run a command on a terminal instead, use
+/// `CommandExec::exec_tty`; `CommandExec::exec_tty_report` also tells whether the command saw it.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ColorMode {
+    /// Leave the decision to the command
+    #[default]
+    Auto,
+    /// Force colored output (`CLICOLOR_FORCE`, `FORCE_COLOR`)
+    Always,
+    /// Suppress colored output (`NO_COLOR`, `CLICOLOR`)
+    Never,
+}
+
+impl ColorMode {
+    /// Environment variables implementing the mode
+    pub fn env(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            ColorMode::Auto => &[],
+            ColorMode::Always => &[("CLICOLOR_FORCE", "1"), ("FORCE_COLOR", "1")],
+            ColorMode::Never => &[("NO_COLOR", "1"), ("CLICOLOR", "0")],
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Prefixes the command line with a shell changing into the directory
+///
+/// The directory is passed as a positional parameter, so that it does not need to be quoted for
+/// the shell; the command is only executed if the directory could be entered.
+pub(crate) fn wrap(dir: &Path, argv: Vec<OsString>) -> Vec<OsString> {
+    [
+        "sh".into(),
+        "-c".into(),
+        "cd -- \"$1\" && shift && exec \"$@\"".into(),
+        "sh".into(),
+        dir.as_os_str().to_os_string(),
+    ]
+    .into_iter()
+    .chain(argv)
+    .collect()
+}
@@ -0,0 +1,233 @@
+use crate::{hook, CommandExec, CommandSpec, ExecError};
+use std::io::{Read, Write};
+use std::process::{Child, Stdio};
+use std::sync::mpsc;
+
+/// Identifier of a command in a data flow, returned when the command is added
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct FlowId(usize);
+
+struct Node {
+    spec: CommandSpec,
+    inputs: Vec<FlowId>,
+}
+
+/// Outputs of the commands of a data flow whose stdout is not consumed by other commands
+#[derive(Debug)]
+pub struct FlowResult {
+    outputs: Vec<Option<String>>,
+}
+
+impl FlowResult {
+    /// Returns the output of the command; `None` if its output was consumed by other commands
+    pub fn get(&self, id: FlowId) -> Option<&str> {
+        self.outputs.get(id.0).and_then(|output| output.as_deref())
+    }
+}
+
+/// Commands connected by their standard streams, generalizing pipelines to directed acyclic graphs
+///
+/// The output of a command can be consumed by several commands, each of which receives all of it.
+/// A command consuming the output of several commands receives the outputs one after the other,
+/// in the order the inputs were given. As a command can only consume commands added before it,
+/// the flow cannot contain cycles. Outputs are buffered as needed, so that slow consumers do not
+/// block other branches.
+#[derive(Default)]
+pub struct DataFlow {
+    nodes: Vec<Node>,
+}
+
+impl DataFlow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command and returns its identifier
+    ///
+    /// * `spec` - the command to be executed
+    /// * `inputs` - commands whose output is concatenated into stdin of the command
+    ///
+    pub fn add(&mut self, spec: CommandSpec, inputs: &[FlowId]) -> FlowId {
+        assert!(
+            inputs.iter().all(|id| id.0 < self.nodes.len()),
+            "inputs must be added to the flow first"
+        );
+
+        self.nodes.push(Node {
+            spec,
+            inputs: inputs.to_vec(),
+        });
+        FlowId(self.nodes.len() - 1)
+    }
+
+    /// Runs all commands concurrently and returns the outputs of the commands nobody consumes
+    ///
+    /// Fails with the error of the first command, in the order the commands were added, whose
+    /// exit status is not accepted. The stderr of the commands is passed through.
+    ///
+    /// * `exec` - executor whose configuration is used for every command
+    ///
+    pub fn run(&self, exec: &mut CommandExec) -> Result<FlowResult, ExecError> {
+        let specs: Vec<CommandSpec> = self.nodes.iter().map(|node| node.spec.clone()).collect();
+        let specs = exec.resolve(&specs)?;
+        let start = std::time::Instant::now();
+        let result = self.run_resolved(exec, &specs);
+
+        exec.after_hooks_with(&specs, start, result, |result| {
+            hook::output(
+                result
+                    .outputs
+                    .iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect(),
+                String::new(),
+                0,
+            )
+        })
+    }
+
+    fn run_resolved(
+        &self,
+        exec: &CommandExec,
+        specs: &[CommandSpec],
+    ) -> Result<FlowResult, ExecError> {
+        let mut children: Vec<Child> = Vec::new();
+
+        for (node, spec) in self.nodes.iter().zip(specs) {
+            let spawned = exec.command(spec).and_then(|mut com| {
+                if spec.get_stdin().is_none() {
+                    match node.inputs.is_empty() {
+                        true => com.stdin(Stdio::null()),
+                        false => com.stdin(Stdio::piped()),
+                    };
+                }
+
+                CommandExec::pipe_output(spec, &mut com, false);
+                Ok(com.spawn()?)
+            });
+
+            match spawned {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    for child in &mut children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+
+                    return Err(e);
+                }
+            }
+        }
+
+        // one channel per edge, so that every consumer receives its inputs in order
+        let mut senders: Vec<Vec<mpsc::Sender<Vec<u8>>>> =
+            self.nodes.iter().map(|_| Vec::new()).collect();
+        let mut receivers: Vec<Vec<mpsc::Receiver<Vec<u8>>>> = Vec::new();
+
+        for node in &self.nodes {
+            let mut node_receivers = Vec::new();
+
+            for input in &node.inputs {
+                let (tx, rx) = mpsc::channel();
+
+                senders[input.0].push(tx);
+                node_receivers.push(rx);
+            }
+
+            receivers.push(node_receivers);
+        }
+
+        let (statuses, outputs) = std::thread::scope(|scope| {
+            let readers: Vec<_> = children
+                .iter_mut()
+                .zip(senders)
+                .map(|(child, senders)| {
+                    let stdout = child.stdout.take();
+
+                    scope.spawn(move || DataFlow::forward(stdout, senders))
+                })
+                .collect();
+
+            for (child, receivers) in children.iter_mut().zip(receivers) {
+                if let Some(stdin) = child.stdin.take() {
+                    scope.spawn(move || DataFlow::concatenate(stdin, receivers));
+                }
+            }
+
+            let statuses = children
+                .iter_mut()
+                .map(|child| child.wait())
+                .collect::<Result<Vec<_>, _>>();
+            let outputs = readers
+                .into_iter()
+                .map(|reader| reader.join().map_err(|_| ExecError::Chaining)?)
+                .collect::<Result<Vec<_>, _>>();
+
+            (statuses, outputs)
+        });
+        let (statuses, outputs) = (statuses?, outputs?);
+
+        for (spec, status) in specs.iter().zip(statuses) {
+            exec.check_status(
+                Some(spec),
+                std::process::Output {
+                    status,
+                    stdout: Vec::new(),
+                    stderr: Vec::new(),
+                },
+            )?;
+        }
+
+        Ok(FlowResult {
+            outputs: outputs
+                .into_iter()
+                .map(|output| output.map(|output| exec.text(output)).transpose())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Sends the output to all consumers; it is returned if there are none
+    fn forward(
+        stdout: Option<std::process::ChildStdout>,
+        senders: Vec<mpsc::Sender<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>, ExecError> {
+        let Some(mut stdout) = stdout else {
+            // redirected to a file
+            return Ok(None);
+        };
+
+        if senders.is_empty() {
+            let mut output = Vec::new();
+
+            stdout.read_to_end(&mut output)?;
+            return Ok(Some(output));
+        }
+
+        let mut chunk = [0u8; 8192];
+
+        loop {
+            let len = stdout.read(&mut chunk)?;
+
+            if len == 0 {
+                return Ok(None);
+            }
+
+            for sender in &senders {
+                // consumers may exit without reading all of their input
+                let _ = sender.send(chunk[..len].to_vec());
+            }
+        }
+    }
+
+    /// Writes the inputs one after the other; closing stdin once all of them ended
+    fn concatenate(mut stdin: std::process::ChildStdin, receivers: Vec<mpsc::Receiver<Vec<u8>>>) {
+        for receiver in receivers {
+            for chunk in receiver {
+                if stdin.write_all(&chunk).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
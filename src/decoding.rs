@@ -0,0 +1,29 @@
+use crate::ExecError;
+
+/// Conversion of the captured output into strings
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Decoding {
+    /// Output that is not valid UTF-8 fails the execution with `ExecError::Utf8`
+    #[default]
+    Strict,
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`
+    Lossy,
+    /// Every byte is a character of ISO 8859-1 (latin-1), e.g. for legacy tools
+    Latin1,
+}
+
+impl Decoding {
+    /// Converts the output into a string
+    ///
+    /// * `output` - the output of a command
+    ///
+    pub fn decode(&self, output: Vec<u8>) -> Result<String, ExecError> {
+        match self {
+            Decoding::Strict => Ok(String::from_utf8(output)?),
+            // valid output is taken over without copying
+            Decoding::Lossy => Ok(String::from_utf8(output)
+                .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())),
+            Decoding::Latin1 => Ok(output.into_iter().map(char::from).collect()),
+        }
+    }
+}
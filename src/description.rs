@@ -0,0 +1,76 @@
+use crate::{CommandSpec, Context, Pipeline, Quoting};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Serializable description of a command, e.g. read from a config file (requires the `serde` feature)
+///
+/// * `command` - the program to be executed
+/// * `args` - the arguments of the command
+/// * `env` - environment variables set for the command
+/// * `current_dir` - working directory of the command
+/// * `context` - context the command is executed in; the current context if not set
+/// * `quoting` - treatment of the command and the arguments by shells
+///
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CommandDescription {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub current_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub context: Option<Context>,
+    #[serde(default)]
+    pub quoting: Quoting,
+}
+
+/// Serializable description of a pipeline (requires the `serde` feature)
+///
+/// * `stages` - the commands piped into each other
+/// * `input` - text written to stdin of the first command
+///
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PipelineDescription {
+    pub stages: Vec<CommandDescription>,
+    #[serde(default)]
+    pub input: Option<String>,
+}
+
+impl From<CommandDescription> for CommandSpec {
+    fn from(description: CommandDescription) -> Self {
+        let mut spec = CommandSpec::new(description.command)
+            .args(description.args)
+            .quoting(description.quoting);
+
+        for (key, value) in description.env {
+            spec = spec.env(key, value);
+        }
+
+        if let Some(dir) = description.current_dir {
+            spec = spec.current_dir(dir);
+        }
+
+        match description.context {
+            Some(context) => spec.context(context),
+            None => spec,
+        }
+    }
+}
+
+impl From<PipelineDescription> for Pipeline {
+    fn from(description: PipelineDescription) -> Self {
+        let pipeline: Pipeline = description
+            .stages
+            .into_iter()
+            .map(CommandSpec::from)
+            .collect();
+
+        match description.input {
+            Some(input) => pipeline.input(input),
+            None => pipeline,
+        }
+    }
+}
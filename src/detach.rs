@@ -0,0 +1,59 @@
+use crate::{CommandExec, CommandSpec, ExecError};
+use std::path::PathBuf;
+use std::process::Stdio;
+
+/// Destinations of the output of a detached command
+///
+/// * `stdout` - file the standard output is appended to; discarded if not set
+/// * `stderr` - file the standard error is appended to; discarded if not set
+///
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Detach {
+    pub stdout: Option<PathBuf>,
+    pub stderr: Option<PathBuf>,
+}
+
+impl CommandExec {
+    /// Launches the command fully detached from the calling process and returns its process id
+    ///
+    /// The command runs in a new session without a controlling terminal, reads from the null
+    /// device and writes to the given files. Unless the spec sets a working directory, it runs in
+    /// the root directory, so that it does not keep other directories busy. The command is not
+    /// waited for; it is reaped in the background once it terminates.
+    ///
+    /// * `spec` - the command to be launched
+    /// * `detach` - destinations of the output
+    ///
+    pub fn launch_detached(
+        &mut self,
+        spec: &CommandSpec,
+        detach: &Detach,
+    ) -> Result<u32, ExecError> {
+        let spec = self.resolve_one(spec)?;
+        let spec = match spec.get_current_dir() {
+            Some(_) => spec,
+            None => spec.current_dir("/"),
+        };
+        let mut com = self.command(&spec.new_session(true))?;
+        let output = |path: &Option<PathBuf>| -> Result<Stdio, ExecError> {
+            Ok(match path {
+                Some(path) => std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?
+                    .into(),
+                None => Stdio::null(),
+            })
+        };
+
+        com.stdin(Stdio::null())
+            .stdout(output(&detach.stdout)?)
+            .stderr(output(&detach.stderr)?);
+
+        let mut child = com.spawn()?;
+        let pid = child.id();
+
+        std::thread::spawn(move || child.wait());
+        Ok(pid)
+    }
+}
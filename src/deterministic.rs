@@ -0,0 +1,163 @@
+use crate::{Context, Exec, ExecError, ExecOutput, Pipeline};
+use std::ffi::OsString;
+
+const UNSET: &[&str] = &[
+    "LANG",
+    "LANGUAGE",
+    "LC_CTYPE",
+    "LC_NUMERIC",
+    "LC_TIME",
+    "LC_COLLATE",
+    "LC_MONETARY",
+    "LC_MESSAGES",
+    "LC_PAPER",
+    "LC_NAME",
+    "LC_ADDRESS",
+    "LC_TELEPHONE",
+    "LC_MEASUREMENT",
+    "LC_IDENTIFICATION",
+];
+const SET: &[&str] = &["LC_ALL=C", "TZ=UTC", "COLUMNS=200"];
+
+/// Executor running every command in a fixed, locale-independent environment
+///
+/// Commands are prefixed with `env`, which removes the locale variables and sets `LC_ALL=C`,
+/// `TZ=UTC` and `COLUMNS=200`. As the environment is set up by the command line itself, this
+/// works the same way for local, sudo and remote contexts, so that the output can be parsed
+/// reliably everywhere.
+pub struct DeterministicExec<E: Exec> {
+    inner: E,
+}
+
+impl<E: Exec> DeterministicExec<E> {
+    /// Wraps an executor
+    pub fn new(inner: E) -> Self {
+        DeterministicExec { inner }
+    }
+
+    /// Returns the wrapped executor
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    /// Arguments of `env` preceding the command
+    fn prefix() -> impl Iterator<Item = &'static str> {
+        UNSET
+            .iter()
+            .flat_map(|var| ["-u", var])
+            .chain(SET.iter().copied())
+    }
+
+    fn wrap<'a>(command: &'a str, args: &[&'a str]) -> Vec<&'a str> {
+        let mut argv: Vec<&'a str> = DeterministicExec::<E>::prefix().collect();
+
+        argv.push(command);
+        argv.extend(args);
+        argv
+    }
+
+    fn wrap_all<'a>(
+        commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> Vec<Vec<&'a str>> {
+        commands
+            .iter()
+            .map(|(command, args, _)| DeterministicExec::<E>::wrap(command, args))
+            .collect()
+    }
+
+    /// Pairs the wrapped command lines with the contexts of the commands
+    fn commands<'a>(
+        commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+        wrapped: &'a [Vec<&'a str>],
+    ) -> Vec<(&'a str, &'a [&'a str], Option<&'a Context>)> {
+        commands
+            .iter()
+            .zip(wrapped)
+            .map(|((_, _, context), args)| ("env", args.as_slice(), *context))
+            .collect()
+    }
+}
+
+impl<E: Exec> Exec for DeterministicExec<E> {
+    fn exec<'a>(
+        &mut self,
+        command: &str,
+        args: &[&'a str],
+        context: Option<&'a Context>,
+    ) -> Result<String, ExecError> {
+        self.inner
+            .exec("env", &DeterministicExec::<E>::wrap(command, args), context)
+    }
+
+    fn exec_piped<'a>(
+        &mut self,
+        commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> Result<String, ExecError> {
+        let wrapped = DeterministicExec::<E>::wrap_all(commands);
+
+        self.inner
+            .exec_piped(&DeterministicExec::<E>::commands(commands, &wrapped))
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        let wrapped: Pipeline = pipeline
+            .stages()
+            .iter()
+            .map(|spec| {
+                let args = DeterministicExec::<E>::prefix()
+                    .map(OsString::from)
+                    .chain(std::iter::once(spec.get_program().to_os_string()))
+                    .chain(spec.get_args().iter().cloned())
+                    .collect();
+
+                spec.clone().command_line("env".into(), args)
+            })
+            .collect();
+        let pipeline = match pipeline.get_input() {
+            Some(input) => wrapped.input(input),
+            None => wrapped,
+        };
+
+        self.inner.run(&pipeline)
+    }
+
+    fn exec_bytes<'a>(
+        &mut self,
+        command: &str,
+        args: &[&'a str],
+        context: Option<&'a Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        self.inner
+            .exec_bytes("env", &DeterministicExec::<E>::wrap(command, args), context)
+    }
+
+    fn exec_piped_bytes<'a>(
+        &mut self,
+        commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        let wrapped = DeterministicExec::<E>::wrap_all(commands);
+
+        self.inner
+            .exec_piped_bytes(&DeterministicExec::<E>::commands(commands, &wrapped))
+    }
+
+    fn exec_full<'a>(
+        &mut self,
+        command: &str,
+        args: &[&'a str],
+        context: Option<&'a Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        self.inner
+            .exec_full("env", &DeterministicExec::<E>::wrap(command, args), context)
+    }
+
+    fn exec_piped_full<'a>(
+        &mut self,
+        commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        let wrapped = DeterministicExec::<E>::wrap_all(commands);
+
+        self.inner
+            .exec_piped_full(&DeterministicExec::<E>::commands(commands, &wrapped))
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{quote, CommandExec, CommandSpec, Context, Exec, ExecError, ExecOutput, Pipeline};
+use std::time::Duration;
+
+/// Executor returning the command lines that would be run instead of running them
+///
+/// The command lines are rendered by the wrapped `CommandExec`, including wrappers such as `sudo`
+/// or `ssh`, and returned as output; the stages of a pipeline are separated by `|`. All rendered
+/// command lines are recorded. Resolvers are not applied, as they execute commands.
+#[derive(Default, Clone)]
+pub struct DryRunExec {
+    exec: CommandExec,
+    commands: Vec<String>,
+}
+
+impl From<CommandExec> for DryRunExec {
+    fn from(exec: CommandExec) -> Self {
+        DryRunExec {
+            exec,
+            commands: Vec::new(),
+        }
+    }
+}
+
+impl DryRunExec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the command lines rendered so far, one per command or pipeline
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    fn render(&mut self, specs: &[CommandSpec]) -> Result<String, ExecError> {
+        let line = specs
+            .iter()
+            .map(|spec| self.exec.render(spec))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(" | ");
+
+        self.commands.push(line.clone());
+        Ok(line + "\n")
+    }
+
+    fn render_full(&mut self, specs: &[CommandSpec]) -> Result<ExecOutput, ExecError> {
+        Ok(ExecOutput {
+            stdout: self.render(specs)?,
+            stderr: String::new(),
+            status: 0,
+            duration: Duration::ZERO,
+        })
+    }
+}
+
+impl CommandExec {
+    /// Renders the command line that is run for the spec as POSIX shell command
+    ///
+    /// The command line includes the wrappers of the context as well as the environment, the
+    /// working directory and the redirections set up by the calling process. The configurators of
+    /// the spec are not run and no files are opened.
+    ///
+    /// * `spec` - the command to be rendered
+    ///
+    pub fn render(&self, spec: &CommandSpec) -> Result<String, ExecError> {
+        let local = matches!(spec.get_context(), None | Some(Context::Local { .. }));
+        // redirections in other contexts are part of the command line; local ones are rendered below
+        let com = self.command_line(spec)?;
+        let mut parts: Vec<String> = Vec::new();
+
+        if com.get_envs().len() > 0 {
+            parts.push("env".into());
+
+            for (key, value) in com.get_envs() {
+                let key = key.to_string_lossy();
+
+                match value {
+                    Some(value) => parts.push(quote::quote(&format!(
+                        "{}={}",
+                        key,
+                        value.to_string_lossy()
+                    ))),
+                    None => parts.extend(["-u".into(), quote::quote(&key)]),
+                }
+            }
+        }
+
+        parts.extend(
+            std::iter::once(com.get_program())
+                .chain(com.get_args())
+                .map(|part| quote::quote(&part.to_string_lossy())),
+        );
+
+        let mut line = parts.join(" ");
+
+        if let Some(dir) = com.get_current_dir() {
+            line = format!("cd {} && {}", quote::quote(&dir.to_string_lossy()), line);
+        }
+
+        if local {
+            if let Some(stdin) = spec.get_stdin() {
+                line.push_str(&format!(" < {}", quote::quote(&stdin.to_string_lossy())));
+            }
+
+            if let Some(stdout) = spec.get_stdout() {
+                line.push_str(&stdout.shell(1)?);
+            }
+
+            if let Some(stderr) = spec.get_stderr() {
+                line.push_str(&stderr.shell(2)?);
+            }
+        }
+
+        Ok(line)
+    }
+}
+
+impl Exec for DryRunExec {
+    fn exec(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        self.render(&CommandExec::specs(&[(command, args, context)]))
+    }
+
+    fn exec_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        self.render(&CommandExec::specs(commands))
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        self.render(pipeline.stages())
+    }
+
+    fn exec_bytes(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        Ok(self.exec(command, args, context)?.into_bytes())
+    }
+
+    fn exec_piped_bytes(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        Ok(self.exec_piped(commands)?.into_bytes())
+    }
+
+    fn exec_full(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        self.render_full(&CommandExec::specs(&[(command, args, context)]))
+    }
+
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        self.render_full(&CommandExec::specs(commands))
+    }
+}
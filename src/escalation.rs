@@ -0,0 +1,218 @@
+use crate::{quote, ExecError};
+use std::ffi::{OsStr, OsString};
+
+/// Tool used to run a command as a different user
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Escalation {
+    /// `sudo -nu <user> -- <command>`
+    Sudo,
+    /// `doas -n -u <user> -- <command>`
+    Doas,
+    /// `su <user> -c '<command>'`
+    Su,
+    /// `pkexec --user <user> <command>`
+    Pkexec,
+    /// `runas /user:<user> "<command>"` (Windows)
+    Runas,
+    /// `powershell -Command Start-Process <command> -Credential <user> ...` (Windows)
+    ///
+    /// PowerShell prompts for the password of the user; hence, it is not started with
+    /// `-NonInteractive`. Unlike with `runas`, the exit status of the command is passed on.
+    PowerShell,
+    /// `unshare --user --map-user=<user> --map-group=<primary group> -- <command>` (Linux)
+    ///
+    /// Runs the command in a new user namespace, in which the calling user is mapped to the
+    /// requested user. No privileges are required, but the command only appears to run as the
+    /// user; towards the rest of the system, it still acts as the calling user.
+    UserNamespace,
+}
+
+impl Default for Escalation {
+    fn default() -> Self {
+        Escalation::platform_default()
+    }
+}
+
+impl Escalation {
+    /// Returns the usual escalation tool of the platform
+    pub fn platform_default() -> Self {
+        if cfg!(windows) {
+            Escalation::Runas
+        } else {
+            Escalation::Sudo
+        }
+    }
+
+    /// Returns the first escalation tool found in the search path
+    pub fn detect() -> Option<Self> {
+        let candidates: &[Escalation] = if cfg!(windows) {
+            &[Escalation::Runas, Escalation::PowerShell]
+        } else {
+            &[
+                Escalation::Sudo,
+                Escalation::Doas,
+                Escalation::Pkexec,
+                Escalation::Su,
+            ]
+        };
+
+        candidates
+            .iter()
+            .find(|candidate| candidate.is_available())
+            .copied()
+    }
+
+    /// Name of the program implementing the escalation
+    pub fn program(&self) -> &'static str {
+        match self {
+            Escalation::Sudo => "sudo",
+            Escalation::Doas => "doas",
+            Escalation::Su => "su",
+            Escalation::Pkexec => "pkexec",
+            Escalation::Runas => "runas",
+            Escalation::PowerShell => "powershell",
+            Escalation::UserNamespace => "unshare",
+        }
+    }
+
+    /// Checks whether the program is found in the search path
+    pub fn is_available(&self) -> bool {
+        let program = match cfg!(windows) {
+            true => format!("{}.exe", self.program()),
+            false => self.program().to_string(),
+        };
+
+        std::env::var_os("PATH")
+            .map(|path| std::env::split_paths(&path).any(|dir| dir.join(&program).is_file()))
+            .unwrap_or(false)
+    }
+
+    /// Returns the full command line (program first) running the command as the given user
+    ///
+    /// * `user` - name of the user who will execute the command
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    ///
+    pub fn wrap(
+        &self,
+        user: &str,
+        command: &OsStr,
+        args: &[&OsStr],
+    ) -> Result<Vec<OsString>, ExecError> {
+        let mut argv: Vec<OsString> = match self {
+            Escalation::Sudo => vec!["sudo".into(), "-nu".into(), user.into(), "--".into()],
+            Escalation::Doas => vec![
+                "doas".into(),
+                "-n".into(),
+                "-u".into(),
+                user.into(),
+                "--".into(),
+            ],
+            Escalation::Pkexec => vec!["pkexec".into(), "--user".into(), user.into()],
+            Escalation::UserNamespace => {
+                let mut argv: Vec<OsString> = vec![
+                    "unshare".into(),
+                    "--user".into(),
+                    format!("--map-user={}", user).into(),
+                ];
+
+                #[cfg(unix)]
+                if let Some(user) = users::get_user_by_name(user) {
+                    argv.push(format!("--map-group={}", user.primary_group_id()).into());
+                }
+
+                argv.push("--".into());
+                argv
+            }
+            Escalation::Su => {
+                // su hands a single command line to the shell of the user
+                let line = quote::join_os(std::iter::once(command).chain(args.iter().copied()))?;
+
+                return Ok(vec!["su".into(), user.into(), "-c".into(), line.into()]);
+            }
+            Escalation::Runas => {
+                // runas expects the program and its arguments as a single string
+                let line =
+                    quote::join_windows_os(std::iter::once(command).chain(args.iter().copied()))?;
+
+                return Ok(vec![
+                    "runas".into(),
+                    format!("/user:{}", user).into(),
+                    line.into(),
+                ]);
+            }
+            Escalation::PowerShell => {
+                let program = command
+                    .to_str()
+                    .ok_or_else(|| ExecError::NonUtf8Argument(command.to_os_string()))?;
+                let mut script = format!(
+                    "$process = Start-Process -FilePath {} -Credential {} -Wait -NoNewWindow -PassThru",
+                    quote::quote_powershell(program),
+                    quote::quote_powershell(user)
+                );
+
+                // Start-Process hands the arguments to the command as a single command line
+                if !args.is_empty() {
+                    script.push_str(&format!(
+                        " -ArgumentList {}",
+                        quote::quote_powershell(&quote::join_windows_os(args.iter().copied())?)
+                    ));
+                }
+
+                script.push_str("; exit $process.ExitCode");
+
+                return Ok(vec![
+                    "powershell".into(),
+                    "-NoProfile".into(),
+                    "-Command".into(),
+                    script.into(),
+                ]);
+            }
+        };
+
+        argv.push(command.to_os_string());
+        argv.extend(args.iter().map(|arg| arg.to_os_string()));
+        Ok(argv)
+    }
+}
+
+/// Runs the command as the user by switching its ids right before the command is executed (unix)
+///
+/// Requires the calling process to run as root. Like a login, the supplementary groups of the
+/// user are set and `HOME`, `USER` and `LOGNAME` refer to the user.
+#[cfg(unix)]
+pub(crate) fn switch_user(com: &mut std::process::Command, user: &str) -> Result<(), ExecError> {
+    use users::os::unix::UserExt;
+
+    let entry =
+        users::get_user_by_name(user).ok_or_else(|| ExecError::UnknownUser(user.to_string()))?;
+    let uid = entry.uid();
+    let gid = entry.primary_group_id();
+    // looking up the groups is not async-signal-safe, so it cannot be done in the child
+    let groups: Vec<libc::gid_t> = users::get_user_groups(user, gid)
+        .unwrap_or_default()
+        .iter()
+        .map(|group| group.gid())
+        .collect();
+
+    com.env("HOME", entry.home_dir())
+        .env("USER", user)
+        .env("LOGNAME", user);
+
+    // SAFETY: setgroups, setgid and setuid are async-signal-safe and the closure does not allocate
+    unsafe {
+        std::os::unix::process::CommandExt::pre_exec(com, move || {
+            // the groups must be changed while the process is still privileged
+            if libc::setgroups(groups.len() as _, groups.as_ptr()) == -1
+                || libc::setgid(gid) == -1
+                || libc::setuid(uid) == -1
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(())
+        })
+    };
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+/// Decides which exit statuses of a command are a success
+///
+/// By default, only status 0 is a success. Commands such as `grep` (1 for no matches) or `diff`
+/// (1 for differing files) report expected outcomes with other statuses.
+#[derive(Clone)]
+pub struct ExitPolicy(Arc<dyn Fn(i32) -> bool + Send + Sync>);
+
+impl Default for ExitPolicy {
+    fn default() -> Self {
+        ExitPolicy::accept_codes(&[0])
+    }
+}
+
+impl ExitPolicy {
+    /// Creates a policy from a closure
+    ///
+    /// * `accepts` - returns whether the status is a success
+    ///
+    pub fn new(accepts: impl Fn(i32) -> bool + Send + Sync + 'static) -> Self {
+        ExitPolicy(Arc::new(accepts))
+    }
+
+    /// Creates a policy accepting the listed statuses
+    ///
+    /// * `codes` - the statuses that are a success
+    ///
+    pub fn accept_codes(codes: &[i32]) -> Self {
+        let codes = codes.to_vec();
+
+        ExitPolicy::new(move |code| codes.contains(&code))
+    }
+
+    /// Checks whether the status is a success
+    pub fn accepts(&self, code: i32) -> bool {
+        (self.0)(code)
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{Context, Exec, ExecError, ExecOutput, Pipeline};
+
+/// Convenience methods for common uses of executors; implemented for all of them
+pub trait ExecExt: Exec {
+    /// Runs a command and checks whether it finished with status code 0
+    ///
+    /// Failures to run the command at all (e.g. a missing program or a broken connection) are
+    /// still errors.
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    fn exec_ok(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<bool, ExecError> {
+        Ok(self.exec_full(command, args, context)?.success())
+    }
+
+    /// Runs a command and returns its output without leading and trailing whitespace
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    fn exec_trimmed(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        Ok(self.exec(command, args, context)?.trim().to_string())
+    }
+
+    /// Runs a command and returns the lines of its output
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    fn exec_lines(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<Vec<String>, ExecError> {
+        Ok(self
+            .exec(command, args, context)?
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Runs a command and returns its output, whatever its exit status
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    fn exec_ignore_status(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        Ok(self.exec_full(command, args, context)?.stdout)
+    }
+
+    /// Runs a command and deserializes its output as JSON (requires the `serde_json` feature)
+    ///
+    /// Output that cannot be deserialized is returned with `ExecError::Parse`.
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    #[cfg(feature = "serde_json")]
+    fn exec_json<T: serde::de::DeserializeOwned>(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<T, ExecError> {
+        let output = self.exec(command, args, context)?;
+
+        serde_json::from_str(&output).map_err(|e| ExecError::Parse(e.to_string(), output))
+    }
+}
+
+impl<E: Exec + ?Sized> ExecExt for E {}
+
+/// Forwards to the borrowed executor, so that borrows can be passed where an executor is taken by value
+impl<T: Exec + ?Sized> Exec for &mut T {
+    fn exec(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        (**self).exec(command, args, context)
+    }
+
+    fn exec_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        (**self).exec_piped(commands)
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        (**self).run(pipeline)
+    }
+
+    fn exec_bytes(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        (**self).exec_bytes(command, args, context)
+    }
+
+    fn exec_piped_bytes(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        (**self).exec_piped_bytes(commands)
+    }
+
+    fn exec_full(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        (**self).exec_full(command, args, context)
+    }
+
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        (**self).exec_piped_full(commands)
+    }
+}
+
+/// Forwards to the boxed executor, e.g. a `Box<dyn Exec>` chosen at runtime
+impl<T: Exec + ?Sized> Exec for Box<T> {
+    fn exec(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        (**self).exec(command, args, context)
+    }
+
+    fn exec_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        (**self).exec_piped(commands)
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        (**self).run(pipeline)
+    }
+
+    fn exec_bytes(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        (**self).exec_bytes(command, args, context)
+    }
+
+    fn exec_piped_bytes(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        (**self).exec_piped_bytes(commands)
+    }
+
+    fn exec_full(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        (**self).exec_full(command, args, context)
+    }
+
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        (**self).exec_piped_full(commands)
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{quote, Context, Exec, ExecError, ExecOutput, Pipeline};
+use regex::Regex;
+use std::time::Duration;
+
+/// Canned response of `FakeExec`
+///
+/// * `stdout` - output of the command
+/// * `stderr` - error output of the command
+/// * `status` - exit status of the command
+///
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FakeResponse {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32,
+}
+
+impl FakeResponse {
+    /// Creates a successful response with the given output
+    ///
+    /// * `stdout` - output of the command
+    ///
+    pub fn new(stdout: &str) -> Self {
+        FakeResponse {
+            stdout: stdout.to_string(),
+            ..FakeResponse::default()
+        }
+    }
+
+    /// Sets the error output of the command
+    ///
+    /// * `stderr` - error output of the command
+    ///
+    pub fn stderr(mut self, stderr: &str) -> Self {
+        self.stderr = stderr.to_string();
+        self
+    }
+
+    /// Sets the exit status of the command
+    ///
+    /// * `status` - exit status; a status other than 0 is an error except for the `exec_full` methods
+    ///
+    pub fn status(mut self, status: i32) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// Executor answering commands with canned responses instead of running them
+///
+/// Rules match the command line, i.e. the quoted program and arguments; the stages of pipelines
+/// are separated by ` | `. The first matching rule in the order of registration provides the
+/// response. Commands matched by no rule fail with `ExecError::UnexpectedCommand`. All command
+/// lines are recorded, matched or not.
+#[derive(Debug, Default, Clone)]
+pub struct FakeExec {
+    rules: Vec<(Regex, FakeResponse)>,
+    invocations: Vec<String>,
+}
+
+impl FakeExec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Responds to command lines matching the regular expression
+    ///
+    /// * `pattern` - regular expression searched for in the command line
+    /// * `response` - the response to matching commands
+    ///
+    pub fn on(mut self, pattern: &str, response: FakeResponse) -> Result<Self, ExecError> {
+        self.rules.push((Regex::new(pattern)?, response));
+        Ok(self)
+    }
+
+    /// Responds to command lines matching the glob pattern
+    ///
+    /// The pattern has to match the whole command line; `*` matches any text, `?` a single
+    /// character. E.g. `systemctl status *` matches any status query of systemctl.
+    ///
+    /// * `pattern` - glob pattern matched against the command line
+    /// * `response` - the response to matching commands
+    ///
+    pub fn on_glob(self, pattern: &str, response: FakeResponse) -> Result<Self, ExecError> {
+        let regex: String = pattern
+            .chars()
+            .map(|c| match c {
+                '*' => ".*".to_string(),
+                '?' => ".".to_string(),
+                c => regex::escape(&c.to_string()),
+            })
+            .collect();
+
+        self.on(&format!("^{}$", regex), response)
+    }
+
+    /// Returns the command lines executed so far, in order
+    pub fn invocations(&self) -> &[String] {
+        &self.invocations
+    }
+
+    /// Asserts that commands matching the regular expressions were executed in the given order
+    ///
+    /// Other commands may have been executed in between.
+    ///
+    /// * `patterns` - regular expressions searched for in the command lines, in order
+    ///
+    pub fn assert_invoked(&self, patterns: &[&str]) {
+        let mut invocations = self.invocations.iter();
+
+        for pattern in patterns {
+            let regex = Regex::new(pattern).expect("invalid pattern");
+
+            assert!(
+                invocations.any(|invocation| regex.is_match(invocation)),
+                "no command matching {:?} in order; commands executed: {:?}",
+                pattern,
+                self.invocations
+            );
+        }
+    }
+
+    fn respond(&mut self, line: String) -> Result<FakeResponse, ExecError> {
+        let response = self
+            .rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(&line))
+            .map(|(_, response)| response.clone());
+
+        self.invocations.push(line.clone());
+        response.ok_or(ExecError::UnexpectedCommand(line))
+    }
+
+    fn text(&mut self, line: String) -> Result<String, ExecError> {
+        let response = self.respond(line)?;
+
+        match response.status {
+            0 => Ok(response.stdout),
+            status => Err(ExecError::TerminationWithError(status, response.stderr)),
+        }
+    }
+
+    fn full(&mut self, line: String) -> Result<ExecOutput, ExecError> {
+        let response = self.respond(line)?;
+
+        Ok(ExecOutput {
+            stdout: response.stdout,
+            stderr: response.stderr,
+            status: response.status,
+            duration: Duration::ZERO,
+        })
+    }
+
+    fn line(commands: &[(&str, &[&str], Option<&Context>)]) -> String {
+        commands
+            .iter()
+            .map(|(command, args, _)| {
+                quote::join(std::iter::once(*command).chain(args.iter().copied()))
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+impl Exec for FakeExec {
+    fn exec(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        self.text(FakeExec::line(&[(command, args, context)]))
+    }
+
+    fn exec_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        self.text(FakeExec::line(commands))
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        let line = pipeline
+            .stages()
+            .iter()
+            .map(|spec| {
+                let argv: Vec<String> = std::iter::once(spec.get_program())
+                    .chain(spec.get_args().iter().map(|arg| arg.as_os_str()))
+                    .map(|part| part.to_string_lossy().into_owned())
+                    .collect();
+
+                quote::join(argv.iter().map(String::as_str))
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+
+        self.text(line)
+    }
+
+    fn exec_full(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        self.full(FakeExec::line(&[(command, args, context)]))
+    }
+
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        self.full(FakeExec::line(commands))
+    }
+}
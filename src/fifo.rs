@@ -0,0 +1,45 @@
+use crate::{quote, ExecError};
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Prefixes the command line with a shell connecting the command to named pipes
+///
+/// The shell creates the pipes in the context of the command, so that they also work on remote
+/// hosts, and removes them once the command terminated.
+pub(crate) fn wrap(
+    input: Option<&Path>,
+    output: Option<&Path>,
+    argv: Vec<OsString>,
+) -> Result<Vec<OsString>, ExecError> {
+    let mut paths = Vec::new();
+    let mut redirections = String::new();
+
+    if let Some(input) = input {
+        let input = quote::quote_os(input.as_os_str())?;
+
+        redirections.push_str(&format!(" < {}", input));
+        paths.push(input);
+    }
+
+    if let Some(output) = output {
+        let output = quote::quote_os(output.as_os_str())?;
+
+        redirections.push_str(&format!(" > {}", output));
+        paths.push(output);
+    }
+
+    let paths = paths.join(" ");
+    // the pipes are removed on exit, so the command is not executed in place of the shell
+    let script = format!(
+        "mkfifo -- {} && trap 'rm -f -- {}' EXIT && \"$@\"{}",
+        paths,
+        paths.replace('\'', "'\\''"),
+        redirections
+    );
+
+    Ok(["sh", "-c", &script, "sh"]
+        .into_iter()
+        .map(OsString::from)
+        .chain(argv)
+        .collect())
+}
@@ -0,0 +1,398 @@
+//! Execution of a command on many contexts
+
+use crate::{
+    parallel, BatchSummary, CommandExec, CommandSpec, Context, Exec, ExecError, ExecOutput,
+    ExecReport, ProgressEvent,
+};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Result of running a command on a single host
+///
+/// * `host` - name of the host (or description of the context)
+/// * `context` - the context the command was executed in
+/// * `result` - report of the execution or the error that prevented it
+///
+#[derive(Debug)]
+pub struct HostResult {
+    pub host: String,
+    pub context: Context,
+    pub result: Result<ExecReport, ExecError>,
+}
+
+/// Results of running a command on many hosts, in the order the contexts were given
+#[derive(Debug)]
+pub struct FleetResult {
+    pub hosts: Vec<HostResult>,
+}
+
+impl CommandExec {
+    /// Runs the command in all contexts with bounded concurrency
+    ///
+    /// Every context uses a clone of the executor; hence, all settings apply to each command.
+    ///
+    /// * `contexts` - the contexts the command is executed in
+    /// * `spec` - the command to be executed; its context is replaced by each of the contexts
+    /// * `max_concurrency` - maximum number of commands running at the same time
+    ///
+    pub fn run_fleet(
+        &self,
+        contexts: &[Context],
+        spec: &CommandSpec,
+        max_concurrency: usize,
+    ) -> FleetResult {
+        let results = run_all(
+            self,
+            contexts,
+            max_concurrency,
+            |exec, context| exec.exec_report(&spec.clone().context(context.clone())),
+            |result| {
+                let report = match result {
+                    Ok(report) => Some(report),
+                    Err(ExecError::Failed(report)) => Some(report.as_ref()),
+                    Err(_) => None,
+                };
+
+                (
+                    report.map(|report| report.stdout.len() as u64),
+                    report.and_then(|report| report.status),
+                )
+            },
+        );
+
+        FleetResult {
+            hosts: contexts
+                .iter()
+                .zip(results)
+                .map(|(context, result)| HostResult {
+                    host: host_name(context),
+                    context: context.clone(),
+                    result,
+                })
+                .collect(),
+        }
+    }
+
+    /// Runs the command in all contexts with bounded concurrency and returns the output per host
+    ///
+    /// A status other than 0 is not an error, like with `Exec::exec_full`. The results are keyed
+    /// by the host name; contexts sharing the host name with another context are keyed by
+    /// `<host>#<index>` instead, with the index of the context in `hosts`.
+    ///
+    /// * `hosts` - the contexts the command is executed in
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `max_concurrency` - maximum number of commands running at the same time
+    ///
+    pub fn exec_on_all(
+        &self,
+        hosts: &[Context],
+        command: &str,
+        args: &[&str],
+        max_concurrency: usize,
+    ) -> HashMap<String, Result<ExecOutput, ExecError>> {
+        let results = run_all(
+            self,
+            hosts,
+            max_concurrency,
+            |exec, context| exec.exec_full(command, args, Some(context)),
+            |result| match result {
+                Ok(output) => (Some(output.stdout.len() as u64), Some(output.status)),
+                Err(_) => (None, None),
+            },
+        );
+
+        keys(hosts).into_iter().zip(results).collect()
+    }
+}
+
+/// Runs the command in all contexts with bounded concurrency and returns the output per host
+///
+/// Same as `CommandExec::exec_on_all`.
+///
+/// * `exec` - the executor; every context uses a clone of it
+/// * `hosts` - the contexts the command is executed in
+/// * `command` - the command to be executed
+/// * `args` - the arguments of the command
+/// * `max_concurrency` - maximum number of commands running at the same time
+///
+pub fn exec_on_all(
+    exec: &CommandExec,
+    hosts: &[Context],
+    command: &str,
+    args: &[&str],
+    max_concurrency: usize,
+) -> HashMap<String, Result<ExecOutput, ExecError>> {
+    exec.exec_on_all(hosts, command, args, max_concurrency)
+}
+
+/// Runs the function for all contexts on a bounded number of threads, reporting the progress
+///
+/// * `outcome` - returns the number of bytes of stdout and the status of a result
+///
+fn run_all<T: Send>(
+    exec: &CommandExec,
+    contexts: &[Context],
+    max_concurrency: usize,
+    run: impl Fn(&mut CommandExec, &Context) -> Result<T, ExecError> + Sync,
+    outcome: impl Fn(&Result<T, ExecError>) -> (Option<u64>, Option<i32>) + Sync,
+) -> Vec<Result<T, ExecError>> {
+    let start = Instant::now();
+
+    exec.emit(ProgressEvent::BatchStarted {
+        commands: contexts.len(),
+    });
+
+    let results = parallel::run_bounded(exec, contexts, max_concurrency, |exec, index, context| {
+        exec.emit(ProgressEvent::CommandStarted { index });
+
+        let result = run(exec, context);
+        let (bytes, status) = outcome(&result);
+
+        exec.emit(ProgressEvent::StageFinished {
+            index,
+            bytes,
+            status,
+        });
+        result
+    });
+
+    exec.emit(ProgressEvent::BatchFinished {
+        summary: BatchSummary {
+            commands: contexts.len(),
+            failed: results.iter().filter(|result| result.is_err()).count(),
+            duration: start.elapsed(),
+        },
+    });
+
+    results
+}
+
+/// Returns a unique key per context: the host name, followed by the index if it is not unique
+fn keys(contexts: &[Context]) -> Vec<String> {
+    let names: Vec<String> = contexts.iter().map(host_name).collect();
+
+    names
+        .iter()
+        .enumerate()
+        .map(
+            |(index, name)| match names.iter().filter(|n| *n == name).count() {
+                1 => name.clone(),
+                _ => format!("{}#{}", name, index),
+            },
+        )
+        .collect()
+}
+
+fn host_name(context: &Context) -> String {
+    match context {
+        Context::Remote { host, .. } => host.clone(),
+        _ => context.to_string(),
+    }
+}
+
+impl HostResult {
+    /// Short status: `ok`, `exit <code>`, `signal` or `error`
+    pub fn status(&self) -> String {
+        match &self.result {
+            Ok(_) => "ok".to_string(),
+            Err(ExecError::Failed(report)) => match report.status {
+                Some(code) => format!("exit {}", code),
+                None => "signal".to_string(),
+            },
+            Err(_) => "error".to_string(),
+        }
+    }
+
+    /// Duration of the execution, if the command was run
+    pub fn duration(&self) -> Option<Duration> {
+        match &self.result {
+            Ok(report) => Some(report.duration),
+            Err(ExecError::Failed(report)) => Some(report.duration),
+            Err(_) => None,
+        }
+    }
+
+    /// First non-empty line of the error output or message
+    pub fn first_error_line(&self) -> Option<String> {
+        let text = match &self.result {
+            Ok(_) => return None,
+            Err(ExecError::Failed(report)) => String::from_utf8_lossy(&report.stderr).into_owned(),
+            Err(e) => e.to_string(),
+        };
+
+        text.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(str::to_string)
+    }
+}
+
+impl FleetResult {
+    /// Checks whether the command succeeded on all hosts
+    pub fn success(&self) -> bool {
+        self.hosts.iter().all(|host| host.result.is_ok())
+    }
+
+    /// Renders a human-readable summary table with one row per host
+    pub fn render_table(&self) -> String {
+        let rows: Vec<[String; 4]> = self
+            .hosts
+            .iter()
+            .map(|host| {
+                [
+                    host.host.clone(),
+                    host.status(),
+                    host.duration()
+                        .map(|d| format!("{:.3}s", d.as_secs_f64()))
+                        .unwrap_or_else(|| "-".to_string()),
+                    host.first_error_line().unwrap_or_default(),
+                ]
+            })
+            .collect();
+        let header = ["HOST", "STATUS", "DURATION", "ERROR"].map(String::from);
+        let mut widths = header.clone().map(|h| h.chars().count());
+
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        std::iter::once(&header)
+            .chain(&rows)
+            .map(|row| {
+                row.iter()
+                    .zip(widths)
+                    .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+                    .trim_end()
+                    .to_string()
+                    + "\n"
+            })
+            .collect()
+    }
+
+    /// Renders the results as a JSON array with one object per host
+    pub fn render_json(&self) -> String {
+        let entries: Vec<String> = self
+            .hosts
+            .iter()
+            .map(|host| {
+                format!(
+                    "{{\"host\":{},\"status\":{},\"success\":{},\"duration\":{},\"error\":{}}}",
+                    json_string(&host.host),
+                    json_string(&host.status()),
+                    host.result.is_ok(),
+                    host.duration()
+                        .map(|d| d.as_secs_f64().to_string())
+                        .unwrap_or_else(|| "null".to_string()),
+                    host.first_error_line()
+                        .map(|line| json_string(&line))
+                        .unwrap_or_else(|| "null".to_string()),
+                )
+            })
+            .collect();
+
+        format!("[{}]", entries.join(","))
+    }
+
+    /// Renders the results as JUnit XML with one test case per host
+    ///
+    /// Commands finishing with a non-zero status are reported as failures, all other errors as
+    /// errors. The message is taken from the `ExecError`, the body from the captured stderr.
+    ///
+    /// * `suite` - name of the test suite
+    ///
+    pub fn render_junit(&self, suite: &str) -> String {
+        let failures = self
+            .hosts
+            .iter()
+            .filter(|host| matches!(host.result, Err(ExecError::Failed(_))))
+            .count();
+        let errors = self
+            .hosts
+            .iter()
+            .filter(|host| matches!(host.result, Err(ref e) if !matches!(e, ExecError::Failed(_))))
+            .count();
+        let time: f64 = self
+            .hosts
+            .iter()
+            .filter_map(|host| host.duration())
+            .map(|d| d.as_secs_f64())
+            .sum();
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(suite),
+            self.hosts.len(),
+            failures,
+            errors,
+            time
+        );
+
+        for host in &self.hosts {
+            xml.push_str(&format!(
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                xml_escape(&host.host),
+                xml_escape(suite),
+                host.duration().unwrap_or_default().as_secs_f64()
+            ));
+
+            match &host.result {
+                Ok(_) => xml.push_str("/>\n"),
+                Err(e) => {
+                    let (element, body) = match e {
+                        ExecError::Failed(report) => (
+                            "failure",
+                            String::from_utf8_lossy(&report.stderr).into_owned(),
+                        ),
+                        e => ("error", e.to_string()),
+                    };
+
+                    xml.push_str(&format!(
+                        ">\n    <{element} message=\"{}\">{}</{element}>\n  </testcase>\n",
+                        xml_escape(&e.to_string()),
+                        xml_escape(&body),
+                    ));
+                }
+            }
+        }
+
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        .map(|c| match c {
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '&' => "&amp;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,217 @@
+use crate::{multiplex, ssh, CommandExec, Context, ExecError};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// Port forwarding through an ssh connection
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Forward {
+    /// Forwards connections to the local port to the host and port as seen from the remote host (`-L`)
+    Local {
+        bind_port: u16,
+        host: String,
+        port: u16,
+    },
+    /// Forwards connections to the port on the remote host to the host and port as seen locally (`-R`)
+    Remote {
+        bind_port: u16,
+        host: String,
+        port: u16,
+    },
+}
+
+impl Forward {
+    /// Renders the forward as ssh config option, e.g. `LocalForward=8080 localhost:80`
+    pub(crate) fn option(&self) -> String {
+        match self {
+            Forward::Local {
+                bind_port,
+                host,
+                port,
+            } => format!("LocalForward={} {}:{}", bind_port, host, port),
+            Forward::Remote {
+                bind_port,
+                host,
+                port,
+            } => format!("RemoteForward={} {}:{}", bind_port, host, port),
+        }
+    }
+
+    fn args(&self) -> [String; 2] {
+        match self {
+            Forward::Local {
+                bind_port,
+                host,
+                port,
+            } => ["-L".into(), format!("{}:{}:{}", bind_port, host, port)],
+            Forward::Remote {
+                bind_port,
+                host,
+                port,
+            } => ["-R".into(), format!("{}:{}:{}", bind_port, host, port)],
+        }
+    }
+}
+
+/// Guard keeping port forwards open while it is alive
+///
+/// The forwards are provided by a dedicated ssh process without a remote command in the
+/// background. It is controlled through a socket in a directory only accessible by the current
+/// user and asked to exit when the guard is dropped.
+pub struct PortForward {
+    host: String,
+    dir: PathBuf,
+}
+
+impl PortForward {
+    /// Time to wait for the forwards to be established
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Establishes the forwards through the remote context
+    ///
+    /// Returns once all forwards are established: ssh goes to the background (`-f`) only after
+    /// the local ports are bound and the remote host confirmed the remote forwards
+    /// (`ExitOnForwardFailure`). If any of the forwards cannot be set up, ssh exits, which is
+    /// reported as an error with the output of ssh.
+    ///
+    /// * `exec` - executor whose ssh settings are used
+    /// * `context` - the remote context
+    /// * `forwards` - the forwards to be established
+    /// * `timeout` - maximum time to wait for the forwards
+    ///
+    pub fn start(
+        exec: &CommandExec,
+        context: &Context,
+        forwards: &[Forward],
+        timeout: Duration,
+    ) -> Result<Self, ExecError> {
+        let Context::Remote { host, .. } = context else {
+            return Err(ExecError::Execution(
+                "port forwarding requires a remote context".into(),
+            ));
+        };
+        let forward = PortForward {
+            host: host.clone(),
+            dir: multiplex::socket_dir()?,
+        };
+        let mut child = PortForward::command(exec, context, forwards, &forward.socket())?
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // the process in the background may keep the pipe open
+        let stderr = child.stderr.take().map(|mut pipe| {
+            std::thread::spawn(move || {
+                let mut stderr = Vec::new();
+                let _ = pipe.read_to_end(&mut stderr);
+                stderr
+            })
+        });
+        let deadline = Instant::now() + timeout;
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(ExecError::Execution(
+                    "port forwards were not established in time".into(),
+                ));
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        };
+
+        match status.code() {
+            Some(0) => Ok(forward),
+            Some(code) => {
+                let stderr = match stderr {
+                    Some(reader) => reader.join().map_err(|_| ExecError::Chaining)?,
+                    None => Vec::new(),
+                };
+
+                Err(ssh::classify(
+                    host,
+                    ExecError::TerminationWithError(
+                        code,
+                        String::from_utf8_lossy(&stderr).into_owned(),
+                    ),
+                ))
+            }
+            None => Err(ExecError::TerminationBySignal),
+        }
+    }
+
+    fn socket(&self) -> PathBuf {
+        self.dir.join(multiplex::SOCKET)
+    }
+
+    pub(crate) fn command(
+        exec: &CommandExec,
+        context: &Context,
+        forwards: &[Forward],
+        socket: &Path,
+    ) -> Result<std::process::Command, ExecError> {
+        let Context::Remote {
+            host,
+            config,
+            proxy,
+            settings,
+        } = context
+        else {
+            return Err(ExecError::Execution(
+                "port forwarding requires a remote context".into(),
+            ));
+        };
+        let mut com = exec.ssh_command(config.as_deref(), proxy.as_ref(), settings.as_deref());
+
+        com.args([
+            "-f",
+            "-N",
+            "-o",
+            "ExitOnForwardFailure=yes",
+            "-o",
+            "ControlMaster=yes",
+            "-o",
+        ])
+        .arg(multiplex::control_path(socket));
+
+        for forward in forwards {
+            com.args(forward.args());
+        }
+
+        com.arg(host);
+        Ok(com)
+    }
+}
+
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        multiplex::control(&self.socket(), &self.host, "exit");
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl CommandExec {
+    /// Runs the operation while the port forwards are established
+    ///
+    /// * `context` - the remote context
+    /// * `forwards` - the forwards to be established
+    /// * `operation` - the operation using the forwarded ports
+    ///
+    pub fn with_forwards<T>(
+        &mut self,
+        context: &Context,
+        forwards: &[Forward],
+        operation: impl FnOnce(&mut Self) -> Result<T, ExecError>,
+    ) -> Result<T, ExecError> {
+        let _forward = PortForward::start(self, context, forwards, PortForward::DEFAULT_TIMEOUT)?;
+
+        operation(self)
+    }
+}
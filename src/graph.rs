@@ -0,0 +1,217 @@
+use crate::{CommandExec, CommandSpec, ExecError};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Identifier of a task, returned when the task is added to a graph
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct TaskId(usize);
+
+/// Outcome of a task
+#[derive(Debug)]
+pub enum TaskOutcome {
+    /// The task finished successfully with the output
+    Succeeded(String),
+    /// The task failed
+    Failed(ExecError),
+    /// The task was not run, because one of its dependencies did not succeed
+    Skipped,
+}
+
+/// Result of a single task
+///
+/// * `name` - name of the task
+/// * `outcome` - output, error or skip of the task
+/// * `duration` - wall-clock time of the execution; `None` if the task was skipped
+///
+#[derive(Debug)]
+pub struct TaskResult {
+    pub name: String,
+    pub outcome: TaskOutcome,
+    pub duration: Option<Duration>,
+}
+
+/// Results of all tasks of a graph, in the order the tasks were added
+#[derive(Debug)]
+pub struct GraphResult {
+    pub tasks: Vec<TaskResult>,
+}
+
+impl GraphResult {
+    /// Checks whether all tasks succeeded
+    pub fn success(&self) -> bool {
+        self.tasks
+            .iter()
+            .all(|task| matches!(task.outcome, TaskOutcome::Succeeded(_)))
+    }
+
+    /// Returns the result of the task
+    pub fn get(&self, id: TaskId) -> Option<&TaskResult> {
+        self.tasks.get(id.0)
+    }
+}
+
+struct Task {
+    name: String,
+    stages: Vec<CommandSpec>,
+    dependencies: Vec<TaskId>,
+}
+
+enum State {
+    Pending,
+    Running,
+    Done(TaskResult),
+}
+
+/// Commands and pipelines with dependencies between them
+///
+/// A task can only depend on tasks added before it; hence, the graph cannot contain cycles. When
+/// run, every task starts as soon as all of its dependencies succeeded. Tasks depending on a
+/// failed task are skipped.
+#[derive(Default)]
+pub struct TaskGraph {
+    tasks: Vec<Task>,
+}
+
+impl TaskGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command and returns its identifier
+    ///
+    /// * `name` - name of the task
+    /// * `spec` - the command to be executed
+    /// * `dependencies` - tasks that must succeed before the command is run
+    ///
+    pub fn add(&mut self, name: &str, spec: CommandSpec, dependencies: &[TaskId]) -> TaskId {
+        self.add_pipeline(name, vec![spec], dependencies)
+    }
+
+    /// Adds a pipeline and returns its identifier
+    ///
+    /// * `name` - name of the task
+    /// * `stages` - the commands piped into each other
+    /// * `dependencies` - tasks that must succeed before the pipeline is run
+    ///
+    pub fn add_pipeline(
+        &mut self,
+        name: &str,
+        stages: Vec<CommandSpec>,
+        dependencies: &[TaskId],
+    ) -> TaskId {
+        assert!(
+            dependencies.iter().all(|id| id.0 < self.tasks.len()),
+            "dependencies must be added to the graph first"
+        );
+
+        self.tasks.push(Task {
+            name: name.to_string(),
+            stages,
+            dependencies: dependencies.to_vec(),
+        });
+        TaskId(self.tasks.len() - 1)
+    }
+
+    /// Runs all tasks in dependency order with bounded concurrency
+    ///
+    /// * `exec` - executor whose configuration is used for every task
+    /// * `max_concurrency` - maximum number of tasks running at the same time
+    ///
+    pub fn run(&self, exec: &CommandExec, max_concurrency: usize) -> GraphResult {
+        let states = Mutex::new(
+            (0..self.tasks.len())
+                .map(|_| State::Pending)
+                .collect::<Vec<_>>(),
+        );
+        let changed = Condvar::new();
+
+        std::thread::scope(|scope| {
+            for _ in 0..max_concurrency.clamp(1, self.tasks.len().max(1)) {
+                scope.spawn(|| {
+                    let mut exec = exec.clone();
+                    let mut guard = states.lock().unwrap_or_else(|e| e.into_inner());
+
+                    loop {
+                        let Some(index) = self.next(&mut guard) else {
+                            if guard.iter().all(|state| matches!(state, State::Done(_))) {
+                                // the last tasks may have been skipped by this worker
+                                changed.notify_all();
+                                return;
+                            }
+
+                            guard = changed.wait(guard).unwrap_or_else(|e| e.into_inner());
+                            continue;
+                        };
+                        let task = &self.tasks[index];
+
+                        guard[index] = State::Running;
+                        drop(guard);
+
+                        let start = Instant::now();
+                        let outcome = match exec.exec_specs_piped(&task.stages) {
+                            Ok(output) => TaskOutcome::Succeeded(output),
+                            Err(e) => TaskOutcome::Failed(e),
+                        };
+
+                        guard = states.lock().unwrap_or_else(|e| e.into_inner());
+                        guard[index] = State::Done(TaskResult {
+                            name: task.name.clone(),
+                            outcome,
+                            duration: Some(start.elapsed()),
+                        });
+                        changed.notify_all();
+                    }
+                });
+            }
+        });
+
+        GraphResult {
+            tasks: states
+                .into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .into_iter()
+                .map(|state| match state {
+                    State::Done(result) => result,
+                    _ => unreachable!("all tasks are done once the workers returned"),
+                })
+                .collect(),
+        }
+    }
+
+    /// Skips the tasks with failed dependencies and returns a task ready to run
+    fn next(&self, states: &mut [State]) -> Option<usize> {
+        // dependencies precede their dependents, so skips propagate within a single pass
+        for (index, task) in self.tasks.iter().enumerate() {
+            if !matches!(states[index], State::Pending) {
+                continue;
+            }
+
+            let mut ready = true;
+
+            for dependency in &task.dependencies {
+                match &states[dependency.0] {
+                    State::Done(TaskResult {
+                        outcome: TaskOutcome::Succeeded(_),
+                        ..
+                    }) => {}
+                    State::Done(_) => {
+                        states[index] = State::Done(TaskResult {
+                            name: task.name.clone(),
+                            outcome: TaskOutcome::Skipped,
+                            duration: None,
+                        });
+                        ready = false;
+                        break;
+                    }
+                    _ => ready = false,
+                }
+            }
+
+            if ready {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{CommandExec, CommandSpec, Context, ExecError};
+use std::time::{Duration, Instant};
+
+/// Information about a context gathered by a successful check
+///
+/// * `user` - name of the user commands are executed as in the context
+/// * `latency` - time the probe command took, including establishing the connection
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct ContextInfo {
+    pub user: String,
+    pub latency: Duration,
+}
+
+impl CommandExec {
+    /// Verifies that commands can be executed in the context
+    ///
+    /// Runs `whoami` in the context, which requires the escalation tool to grant the rights for
+    /// local contexts, the host to be reachable and to accept the credentials for remote
+    /// contexts and the container to be running for Docker contexts. Errors identified by
+    /// their own variant (e.g. `ExecError::Authentication`) are passed on; other failures of the
+    /// probe are reported as `ExecError::Unavailable`. Retries are bypassed.
+    ///
+    /// * `context` - the context to be checked
+    ///
+    pub fn check(&mut self, context: Option<&Context>) -> Result<ContextInfo, ExecError> {
+        let spec = match context {
+            Some(context) => CommandSpec::new("whoami").context(context.clone()),
+            None => CommandSpec::new("whoami"),
+        };
+        let start = Instant::now();
+        let output = self
+            .run_specs_once(&[spec], None, None)
+            .and_then(|output| self.text(output))
+            .map_err(|e| unavailable(context, e))?;
+
+        Ok(ContextInfo {
+            // whoami on Windows reports the user as <domain>\<user>
+            user: output
+                .trim()
+                .rsplit('\\')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            latency: start.elapsed(),
+        })
+    }
+}
+
+/// Converts a failure of the probe command into an error naming the context
+fn unavailable(context: Option<&Context>, error: ExecError) -> ExecError {
+    let reason = match &error {
+        ExecError::TerminationWithError(_, stderr) => stderr.clone(),
+        ExecError::Failed(report) => String::from_utf8_lossy(&report.stderr).into_owned(),
+        _ => return error,
+    };
+    let context = context
+        .map(|context| context.to_string())
+        .unwrap_or_else(|| "local".to_string());
+
+    ExecError::Unavailable(
+        context,
+        reason.lines().next().unwrap_or_default().trim().to_string(),
+    )
+}
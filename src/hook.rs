@@ -0,0 +1,158 @@
+use crate::{CommandExec, CommandSpec, ExecError, ExecOutput};
+use std::sync::Arc;
+use std::time::Instant;
+
+pub(crate) type Hooks = Vec<Arc<dyn Hook>>;
+
+/// Callbacks around the execution of commands, e.g. for audit logs, metrics or confirmations
+///
+/// For pipelines, the callbacks are invoked for every stage; the result passed to `after` is the
+/// one of the whole pipeline.
+pub trait Hook: Send + Sync {
+    /// Called before the command is executed; returns the command to be executed instead
+    ///
+    /// An error aborts the execution, e.g. if the user did not confirm the command.
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    fn before(&self, spec: CommandSpec) -> Result<CommandSpec, ExecError> {
+        Ok(spec)
+    }
+
+    /// Called after the command was executed
+    ///
+    /// * `spec` - the executed command, as returned by `before`
+    /// * `result` - the output of the command or the error; output returned as a whole only
+    ///   contains stdout, commands terminated by a signal have the status -1
+    ///
+    fn after(&self, _spec: &CommandSpec, _result: &Result<ExecOutput, ExecError>) {}
+}
+
+impl CommandExec {
+    /// Adds a hook to the stack of hooks invoked around every execution
+    ///
+    /// `before` is invoked in the order the hooks were added, `after` in the reverse order.
+    /// Applies to every execution of specs and pipelines, including the ones of the `Exec`,
+    /// `Transfer` and `AsyncExec` traits and of data flows. For commands which are not waited for,
+    /// only `before` is invoked: `spawn`, `spawn_pipeline`, `stream`, `stream_piped`, the
+    /// `exec_stream` methods, `launch_detached` and the shell of `ShellSession::start`. Files
+    /// copied by `scp` or `docker cp` and the commands detecting the platform are not hooked.
+    ///
+    /// * `hook` - the hook to be added
+    ///
+    pub fn hook(mut self, hook: impl Hook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Passes the specs through the `before` callbacks of all hooks
+    pub(crate) fn before_hooks(
+        &self,
+        specs: Vec<CommandSpec>,
+    ) -> Result<Vec<CommandSpec>, ExecError> {
+        specs
+            .into_iter()
+            .map(|spec| {
+                self.hooks
+                    .iter()
+                    .try_fold(spec, |spec, hook| hook.before(spec))
+            })
+            .collect()
+    }
+
+    /// Passes the result to the `after` callbacks of all hooks
+    pub(crate) fn after_hooks(
+        &self,
+        specs: &[CommandSpec],
+        result: &Result<ExecOutput, ExecError>,
+    ) {
+        for hook in self.hooks.iter().rev() {
+            for spec in specs {
+                hook.after(spec, result);
+            }
+        }
+    }
+
+    /// Passes the output returned as a whole to the `after` callbacks of all hooks
+    pub(crate) fn after_hooks_bytes(
+        &self,
+        specs: &[CommandSpec],
+        start: Instant,
+        result: Result<Vec<u8>, ExecError>,
+    ) -> Result<Vec<u8>, ExecError> {
+        self.after_hooks_with(specs, start, result, |stdout| {
+            output(
+                String::from_utf8_lossy(stdout).into_owned(),
+                String::new(),
+                0,
+            )
+        })
+    }
+
+    /// Resolves the spec, runs it and passes the result to the `after` callbacks of all hooks
+    ///
+    /// * `spec` - the command to be executed
+    /// * `run` - executes the resolved spec
+    /// * `output` - returns the output passed to the hooks for a successful result
+    ///
+    pub(crate) fn hooked<T>(
+        &mut self,
+        spec: &CommandSpec,
+        run: impl FnOnce(&mut Self, &CommandSpec) -> Result<T, ExecError>,
+        output: impl FnOnce(&T) -> ExecOutput,
+    ) -> Result<T, ExecError> {
+        let spec = self.resolve_one(spec)?;
+        let start = Instant::now();
+        let result = run(self, &spec);
+
+        self.after_hooks_with(std::slice::from_ref(&spec), start, result, output)
+    }
+
+    /// Passes any result to the `after` callbacks of all hooks and returns it
+    ///
+    /// * `specs` - the executed specs
+    /// * `start` - the time the execution started
+    /// * `result` - the result of the execution
+    /// * `output` - returns the output passed to the hooks for a successful result
+    ///
+    pub(crate) fn after_hooks_with<T>(
+        &self,
+        specs: &[CommandSpec],
+        start: Instant,
+        result: Result<T, ExecError>,
+        output: impl FnOnce(&T) -> ExecOutput,
+    ) -> Result<T, ExecError> {
+        if self.hooks.is_empty() {
+            return result;
+        }
+
+        match result {
+            Ok(value) => {
+                self.after_hooks(
+                    specs,
+                    &Ok(ExecOutput {
+                        duration: start.elapsed(),
+                        ..output(&value)
+                    }),
+                );
+                Ok(value)
+            }
+            Err(e) => {
+                let result = Err(e);
+
+                self.after_hooks(specs, &result);
+                result.map(|_: ExecOutput| unreachable!("the result is an error"))
+            }
+        }
+    }
+}
+
+/// Returns the output passed to the hooks; the duration is set by `after_hooks_with`
+pub(crate) fn output(stdout: String, stderr: String, status: i32) -> ExecOutput {
+    ExecOutput {
+        stdout,
+        stderr,
+        status,
+        duration: std::time::Duration::ZERO,
+    }
+}
@@ -0,0 +1,329 @@
+use crate::pty::{self, PtyReader};
+use crate::{hook, CommandExec, CommandSpec, Context, ExecError, ExecReport};
+use regex::Regex;
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Expected output and the response to it
+#[derive(Debug, Clone)]
+struct Step {
+    expect: Regex,
+    send: String,
+    timeout: Option<Duration>,
+}
+
+/// Script of an interactive session with a command (unix only)
+///
+/// The steps are processed in order: the output of the command is searched for the pattern of
+/// the step and, once it appears, the response is written to the terminal of the command. Every
+/// search only considers output following the previous match.
+#[derive(Debug, Clone)]
+pub struct Interaction {
+    steps: Vec<Step>,
+    timeout: Duration,
+}
+
+impl Default for Interaction {
+    fn default() -> Self {
+        Interaction {
+            steps: Vec::new(),
+            timeout: Interaction::DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Interaction {
+    /// Default time to wait for an expected output
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time to wait for an expected output; applies to steps without their own timeout
+    ///
+    /// * `timeout` - maximum time between two matches
+    ///
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Appends a step writing the response once the pattern matched the output
+    ///
+    /// * `pattern` - regular expression searched for in the output
+    /// * `send` - text written to the terminal; include a line break to submit a line
+    ///
+    pub fn expect(self, pattern: &str, send: &str) -> Result<Self, ExecError> {
+        self.step(pattern, send, None)
+    }
+
+    /// Appends a step with its own timeout
+    ///
+    /// * `pattern` - regular expression searched for in the output
+    /// * `send` - text written to the terminal; include a line break to submit a line
+    /// * `timeout` - maximum time to wait for the pattern
+    ///
+    pub fn expect_within(
+        self,
+        pattern: &str,
+        send: &str,
+        timeout: Duration,
+    ) -> Result<Self, ExecError> {
+        self.step(pattern, send, Some(timeout))
+    }
+
+    fn step(
+        mut self,
+        pattern: &str,
+        send: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Self, ExecError> {
+        self.steps.push(Step {
+            expect: Regex::new(pattern)?,
+            send: send.to_string(),
+            timeout,
+        });
+        Ok(self)
+    }
+}
+
+impl CommandExec {
+    /// Runs the command on a pseudo-terminal and answers its prompts according to the interaction
+    ///
+    /// After the last step, the output is read until the command terminates. The complete output
+    /// of the terminal is returned, including the echoed responses. If a pattern does not appear
+    /// in time or before the output ends, the command is killed. Note that ssh does not allocate a
+    /// terminal on the remote host for commands.
+    ///
+    /// * `spec` - the command to be executed
+    /// * `interaction` - the expected prompts and the responses
+    ///
+    pub fn exec_interaction(
+        &mut self,
+        spec: &CommandSpec,
+        interaction: &Interaction,
+    ) -> Result<String, ExecError> {
+        self.hooked(
+            spec,
+            |exec, spec| exec.run_interaction(spec, interaction),
+            |output| hook::output(output.clone(), String::new(), 0),
+        )
+    }
+
+    fn run_interaction(
+        &mut self,
+        spec: &CommandSpec,
+        interaction: &Interaction,
+    ) -> Result<String, ExecError> {
+        let com = self.command(spec)?;
+        let (output, status) = self.run_on_pty(com, interaction)?;
+
+        match status.code() {
+            Some(0) => Ok(self.postprocess(output)),
+            Some(code) => Err(ExecError::TerminationWithError(code, output)),
+            None => Err(ExecError::TerminationBySignal),
+        }
+    }
+
+    /// Runs the command on a pseudo-terminal and returns the output and the exit status
+    fn run_on_pty(
+        &mut self,
+        mut com: std::process::Command,
+        interaction: &Interaction,
+    ) -> Result<(String, std::process::ExitStatus), ExecError> {
+        let mut tree = self.process_tree()?;
+        let master = pty::attach(&mut com)?;
+
+        tree.push(com.spawn()?)?;
+        // the terminal must only be open in the child, so that its end is noticed
+        drop(com);
+
+        let mut input = master.try_clone()?;
+        let rx = read_chunks(PtyReader(master));
+        let mut output = String::new();
+        let mut start = 0;
+
+        for step in &interaction.steps {
+            let deadline = Instant::now() + step.timeout.unwrap_or(interaction.timeout);
+            let found = loop {
+                if let Some(found) = step.expect.find(&output[start..]) {
+                    break Ok(start + found.end());
+                }
+
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(Ok(chunk)) => output.push_str(&String::from_utf8_lossy(&chunk)),
+                    Ok(Err(e)) => break Err(ExecError::Io(e)),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        break Err(ExecError::ExpectTimeout(step.expect.to_string()))
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        break Err(ExecError::ExpectEof(step.expect.to_string()))
+                    }
+                }
+            };
+
+            let written = found.and_then(|end| {
+                input.write_all(step.send.as_bytes())?;
+                Ok(end)
+            });
+
+            match written {
+                Ok(end) => start = end,
+                Err(e) => {
+                    tree.terminate();
+                    return Err(e);
+                }
+            }
+        }
+
+        for chunk in rx {
+            output.push_str(&String::from_utf8_lossy(&chunk?));
+        }
+
+        Ok((output, tree.wait()?))
+    }
+}
+
+impl CommandExec {
+    /// Runs the command on a pseudo-terminal and returns the merged output of stdout and stderr
+    ///
+    /// For tools behaving differently without a terminal, e.g. regarding colors or prompts. In
+    /// remote contexts, ssh is forced to allocate a terminal on the remote host, too (`-tt`). The
+    /// output contains the line endings of the terminal (`\r\n`).
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    pub fn exec_tty(&mut self, spec: &CommandSpec) -> Result<String, ExecError> {
+        let spec = match spec.get_context() {
+            Some(context) => spec.clone().context(request_tty(context)),
+            None => spec.clone(),
+        };
+
+        self.exec_interaction(&spec, &Interaction::new())
+    }
+
+    /// Runs the command on a pseudo-terminal like `exec_tty` and reports the execution
+    ///
+    /// The report tells whether the command saw a terminal: `docker exec` and `kubectl exec` do
+    /// not pass it on, so commands in these contexts still write to a pipe. The merged output is
+    /// reported as stdout. A command finishing with a status not accepted by the exit policy
+    /// results in `ExecError::Failed`, which contains the report.
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    pub fn exec_tty_report(&mut self, spec: &CommandSpec) -> Result<ExecReport, ExecError> {
+        let spec = match spec.get_context() {
+            Some(context) => spec.clone().context(request_tty(context)),
+            None => spec.clone(),
+        };
+
+        self.hooked(&spec, CommandExec::run_tty_report, |report| {
+            hook::output(
+                String::from_utf8_lossy(&report.stdout).into_owned(),
+                String::new(),
+                report.status.unwrap_or(-1),
+            )
+        })
+    }
+
+    fn run_tty_report(&mut self, spec: &CommandSpec) -> Result<ExecReport, ExecError> {
+        let com = self.command(spec)?;
+        let argv = std::iter::once(com.get_program())
+            .chain(com.get_args())
+            .map(|part| part.to_string_lossy().into_owned())
+            .collect();
+        let start = Instant::now();
+        let (output, status) = self.run_on_pty(com, &Interaction::new())?;
+        let report = ExecReport {
+            argv,
+            context: match spec.get_context() {
+                Some(context) => context.to_string(),
+                None => "local".to_string(),
+            },
+            stdout: output.into_bytes(),
+            stderr: Vec::new(),
+            status: status.code(),
+            duration: start.elapsed(),
+            rusage: None,
+            retries: 0,
+            stdout_truncated: false,
+            stderr_truncated: false,
+            warnings: Vec::new(),
+            tty: passes_tty(spec.get_context()),
+        };
+
+        match report
+            .status
+            .is_some_and(|code| self.accepts(Some(spec), code))
+        {
+            true => Ok(report),
+            false => Err(spec.classify(ExecError::Failed(Box::new(report)))),
+        }
+    }
+}
+
+/// Checks whether commands in the context see the terminal the context is entered from
+///
+/// ssh is forced to allocate a terminal by `request_tty`; `docker exec` and `kubectl exec` are run
+/// without `-t`.
+pub(crate) fn passes_tty(context: Option<&Context>) -> bool {
+    match context {
+        Some(Context::Docker { .. } | Context::Kubernetes { .. }) => false,
+        Some(Context::Chain(contexts)) => contexts.iter().all(|context| passes_tty(Some(context))),
+        _ => true,
+    }
+}
+
+/// Returns the context with ssh forced to allocate a terminal on remote hosts
+pub(crate) fn request_tty(context: &Context) -> Context {
+    match context {
+        Context::Remote {
+            host,
+            config,
+            proxy,
+            settings,
+        } => {
+            let mut settings = settings.clone().unwrap_or_default();
+
+            settings
+                .options
+                .push(("RequestTTY".to_string(), "force".to_string()));
+
+            Context::Remote {
+                host: host.clone(),
+                config: config.clone(),
+                proxy: proxy.clone(),
+                settings: Some(settings),
+            }
+        }
+        Context::Chain(contexts) => Context::Chain(contexts.iter().map(request_tty).collect()),
+        context => context.clone(),
+    }
+}
+
+fn read_chunks<R: Read + Send + 'static>(
+    mut reader: R,
+) -> mpsc::Receiver<std::io::Result<Vec<u8>>> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 8192];
+
+        loop {
+            let chunk = match reader.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(len) => Ok(buffer[..len].to_vec()),
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+
+            if tx.send(chunk).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    rx
+}
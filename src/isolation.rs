@@ -0,0 +1,39 @@
+use std::ffi::OsString;
+
+/// Filesystem isolation of a command based on a private mount namespace (Linux)
+///
+/// The command is wrapped in `unshare --mount`; changes to the mounts only affect the command.
+/// Setting up the mounts requires privileges (root or `CAP_SYS_ADMIN`) in the context the
+/// command is executed in.
+///
+/// * `private_tmp` - mount an empty tmpfs on `/tmp`
+/// * `read_only_root` - make the root mount read-only; other mounts (e.g. a separate `/home`) are not affected
+///
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Isolation {
+    pub private_tmp: bool,
+    pub read_only_root: bool,
+}
+
+impl Isolation {
+    /// Prefixes the command line with the `unshare` invocation setting up the mounts
+    pub(crate) fn wrap(&self, argv: Vec<OsString>) -> Vec<OsString> {
+        let mut script = String::from("set -e; ");
+
+        if self.read_only_root {
+            script.push_str("mount -o remount,bind,ro /; ");
+        }
+
+        if self.private_tmp {
+            script.push_str("mount -t tmpfs tmpfs /tmp; ");
+        }
+
+        script.push_str("exec \"$@\"");
+
+        ["unshare", "--mount", "--", "sh", "-c", &script, "sh"]
+            .into_iter()
+            .map(OsString::from)
+            .chain(argv)
+            .collect()
+    }
+}
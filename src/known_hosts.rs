@@ -0,0 +1,180 @@
+use crate::{ssh, CommandExec, ExecError};
+use std::path::{Path, PathBuf};
+
+/// Public key of a host as stored in a known_hosts file
+///
+/// * `host` - host pattern, e.g. `example.com`, `[example.com]:2222` or a hashed name
+/// * `key_type` - type of the key, e.g. `ssh-ed25519`
+/// * `key` - base64 encoded public key
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HostKey {
+    pub host: String,
+    pub key_type: String,
+    pub key: String,
+}
+
+impl HostKey {
+    /// Parses a line of a known_hosts file; comments, markers and malformed lines result in `None`
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let host = fields.next().filter(|host| !host.starts_with(['#', '@']))?;
+
+        Some(HostKey {
+            host: host.to_string(),
+            key_type: fields.next()?.to_string(),
+            key: fields.next()?.to_string(),
+        })
+    }
+
+    /// Renders the key as a line of a known_hosts file
+    pub fn to_line(&self) -> String {
+        format!("{} {} {}", self.host, self.key_type, self.key)
+    }
+
+    /// Retrieves the keys offered by the host with `ssh-keyscan`
+    ///
+    /// The keys are not verified in any way; compare them to keys obtained through a trusted
+    /// channel before relying on them.
+    ///
+    /// * `host` - name or address of the host
+    /// * `port` - ssh port; the default port is used if not set
+    ///
+    pub fn scan(host: &str, port: Option<u16>) -> Result<Vec<HostKey>, ExecError> {
+        let mut com = std::process::Command::new("ssh-keyscan");
+
+        if let Some(port) = port {
+            com.arg("-p").arg(port.to_string());
+        }
+
+        let output = com.arg(host).output()?;
+        let output = String::from_utf8(CommandExec::check_output(output)?)?;
+
+        Ok(output.lines().filter_map(HostKey::parse).collect())
+    }
+}
+
+/// known_hosts file used for verifying the keys of remote hosts
+///
+/// When set on an executor, ssh only accepts hosts whose keys are stored in the file
+/// (`StrictHostKeyChecking=yes`). Unknown and changed keys result in `ExecError::HostKeyUnknown`
+/// and `ExecError::HostKeyMismatch`, respectively, where the output of ssh is captured.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KnownHosts {
+    path: PathBuf,
+}
+
+impl KnownHosts {
+    /// Uses the file at the path; it is created when keys are added
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        KnownHosts {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns all keys stored in the file; a missing file contains no keys
+    pub fn keys(&self) -> Result<Vec<HostKey>, ExecError> {
+        Ok(self
+            .lines()?
+            .iter()
+            .filter_map(|line| HostKey::parse(line))
+            .collect())
+    }
+
+    /// Adds the keys that are not yet stored in the file
+    ///
+    /// The lines already in the file, including comments and markers such as `@revoked`, are
+    /// kept as they are.
+    ///
+    /// * `keys` - the keys to be added, e.g. obtained by `HostKey::scan`
+    ///
+    pub fn add(&self, keys: &[HostKey]) -> Result<(), ExecError> {
+        let mut lines = self.lines()?;
+        let mut stored = self.keys()?;
+
+        for key in keys {
+            if !stored.contains(key) {
+                lines.push(key.to_line());
+                stored.push(key.clone());
+            }
+        }
+
+        self.write(&lines)
+    }
+
+    /// Replaces all keys of the host with the expected ones
+    ///
+    /// Only the plain key lines of the host are removed; comments, markers such as `@revoked`
+    /// and the lines of other hosts are kept as they are.
+    ///
+    /// * `host` - host pattern as used in the file
+    /// * `keys` - the only keys accepted for the host
+    ///
+    pub fn pin(&self, host: &str, keys: &[HostKey]) -> Result<(), ExecError> {
+        let mut lines: Vec<String> = self
+            .lines()?
+            .into_iter()
+            .filter(|line| HostKey::parse(line).is_none_or(|key| key.host != host))
+            .collect();
+
+        lines.extend(keys.iter().map(|key| {
+            HostKey {
+                host: host.to_string(),
+                ..key.clone()
+            }
+            .to_line()
+        }));
+        self.write(&lines)
+    }
+
+    /// Returns the lines of the file; a missing file has no lines
+    fn lines(&self) -> Result<Vec<String>, ExecError> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(content) => Ok(content.lines().map(str::to_string).collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces the file atomically, so that readers never see a partially written file
+    fn write(&self, lines: &[String]) -> Result<(), ExecError> {
+        let content: String = lines.iter().map(|line| line.clone() + "\n").collect();
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+
+        name.push(format!(".{}.tmp", std::process::id()));
+
+        let temp = self.path.with_file_name(name);
+        let result = std::fs::write(&temp, content).and_then(|_| {
+            if let Ok(metadata) = std::fs::metadata(&self.path) {
+                std::fs::set_permissions(&temp, metadata.permissions())?;
+            }
+
+            std::fs::rename(&temp, &self.path)
+        });
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+
+        Ok(result?)
+    }
+}
+
+/// Converts errors of ssh about host keys into the corresponding typed errors
+pub(crate) fn classify(host: &str, error: ExecError) -> ExecError {
+    let classified = ssh::stderr(&error).and_then(|stderr| {
+        if stderr.contains("REMOTE HOST IDENTIFICATION HAS CHANGED") {
+            Some(ExecError::HostKeyMismatch(host.to_string()))
+        } else if stderr.contains("host key is known for") {
+            Some(ExecError::HostKeyUnknown(host.to_string()))
+        } else {
+            None
+        }
+    });
+
+    classified.unwrap_or(error)
+}
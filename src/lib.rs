@@ -1,6 +1,126 @@
 #[cfg(feature = "mockall")]
 use mockall::automock;
 
+mod ansi;
+#[cfg(feature = "tokio")]
+mod async_exec;
+mod backend;
+mod cancel;
+mod capture;
+mod color;
+mod cwd;
+mod dataflow;
+mod decoding;
+#[cfg(feature = "serde")]
+mod description;
+mod detach;
+mod deterministic;
+mod dry_run;
+mod escalation;
+mod exit;
+mod ext;
+mod fake;
+mod fifo;
+pub mod fleet;
+mod forward;
+mod graph;
+mod health;
+mod hook;
+#[cfg(unix)]
+mod interaction;
+mod isolation;
+mod known_hosts;
+mod limits;
+mod multiplex;
+#[cfg(feature = "ssh2")]
+mod native_ssh;
+mod oom;
+mod parallel;
+mod pipeline;
+mod platform;
+mod pool;
+mod prepared;
+mod progress;
+#[cfg(unix)]
+mod pty;
+pub mod quote;
+mod redirect;
+mod replay;
+mod report;
+mod retry;
+mod running;
+mod sched;
+mod shell;
+mod snapshot;
+mod spec;
+mod ssh;
+mod stream;
+mod sudo;
+mod tmux;
+#[cfg(feature = "tracing")]
+mod trace;
+mod transfer;
+mod tree;
+
+pub use ansi::strip_ansi;
+#[cfg(feature = "tokio")]
+pub use async_exec::{AsyncCommandExec, AsyncExec};
+pub use backend::Backend;
+pub use cancel::Cancellation;
+pub use capture::OutputEvent;
+pub use color::ColorMode;
+pub use dataflow::{DataFlow, FlowId, FlowResult};
+pub use decoding::Decoding;
+#[cfg(feature = "serde")]
+pub use description::{CommandDescription, PipelineDescription};
+pub use detach::Detach;
+pub use deterministic::DeterministicExec;
+pub use dry_run::DryRunExec;
+pub use escalation::Escalation;
+pub use exit::ExitPolicy;
+pub use ext::ExecExt;
+pub use fake::{FakeExec, FakeResponse};
+pub use fleet::{FleetResult, HostResult};
+pub use forward::{Forward, PortForward};
+pub use graph::{GraphResult, TaskGraph, TaskId, TaskOutcome, TaskResult};
+pub use health::ContextInfo;
+pub use hook::Hook;
+#[cfg(unix)]
+pub use interaction::Interaction;
+pub use isolation::Isolation;
+pub use known_hosts::{HostKey, KnownHosts};
+pub use limits::{IoPriority, OutputLimit, ResourceLimits};
+#[cfg(feature = "ssh2")]
+pub use native_ssh::SshExec;
+pub use pipeline::{Cmd, Pipeline, PipelineOutput, StageStatus};
+pub use platform::{OsFamily, Platform};
+pub use pool::{ExecPool, PooledExec};
+pub use prepared::PreparedCommand;
+pub use progress::{BatchSummary, ProgressEvent};
+pub use quote::Quoting;
+pub use redirect::Redirect;
+pub use replay::{RecordedCommand, RecorderExec, Recording, ReplayExec};
+pub use report::{ExecOutput, ExecReport, ExecStatus, ResourceUsage};
+pub use retry::{
+    BackoffStrategy, DecorrelatedBackoff, ExponentialBackoff, FixedBackoff, Jitter, RetryPolicy,
+};
+pub use running::RunningCommand;
+#[cfg(unix)]
+pub use running::Signal;
+pub use sched::Scheduling;
+pub use shell::ShellSession;
+pub use snapshot::{Difference, EnvSnapshot};
+pub use spec::CommandSpec;
+pub use ssh::{ProxyConfig, RemoteContext, SshSettings};
+pub use stream::{LineStream, OutputLine, Stream};
+pub use sudo::{SudoAuth, SudoKeepAlive};
+pub use tmux::{SessionState, TmuxSession};
+#[cfg(feature = "mockall")]
+pub use transfer::MockTransfer;
+pub use transfer::Transfer;
+#[cfg(windows)]
+pub use tree::JobLimits;
+
 #[cfg_attr(feature = "mockall", automock)]
 pub trait Exec {
     /// Runs a command in the provided context
@@ -23,9 +143,144 @@ pub trait Exec {
         &mut self,
         commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
     ) -> Result<String, ExecError>;
+
+    /// Runs the commands of the pipeline piping stdout of one command into stdin of the next
+    ///
+    /// The default implementation passes the programs, arguments and contexts of the stages to
+    /// `exec_piped`; the arguments are converted lossily, other settings of the stages are
+    /// ignored and pipelines with input fail.
+    ///
+    /// * `pipeline` - the commands built with `Cmd` and `Pipeline`
+    ///
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        if pipeline.get_input().is_some() {
+            return Err(ExecError::Execution(
+                "the executor does not support the input of pipelines".into(),
+            ));
+        }
+
+        let stages: Vec<(String, Vec<String>)> = pipeline
+            .stages()
+            .iter()
+            .map(|spec| {
+                (
+                    spec.get_program().to_string_lossy().into_owned(),
+                    spec.get_args()
+                        .iter()
+                        .map(|arg| arg.to_string_lossy().into_owned())
+                        .collect(),
+                )
+            })
+            .collect();
+        let args: Vec<Vec<&str>> = stages
+            .iter()
+            .map(|(_, args)| args.iter().map(String::as_str).collect())
+            .collect();
+        let commands: Vec<(&str, &[&str], Option<&Context>)> = stages
+            .iter()
+            .zip(&args)
+            .zip(pipeline.stages())
+            .map(|(((program, _), args), spec)| {
+                (program.as_str(), args.as_slice(), spec.get_context())
+            })
+            .collect();
+
+        self.exec_piped(&commands)
+    }
+
+    /// Runs a command in the provided context and returns its output as is, e.g. for binary data
+    ///
+    /// The default implementation returns the output of `exec`.
+    ///
+    /// * `command` - array of strings containing the command and arguments
+    /// * `context` - either a local or a remote context
+    ///
+    fn exec_bytes<'a>(
+        &mut self,
+        command: &str,
+        args: &[&'a str],
+        context: Option<&'a Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        Ok(self.exec(command, args, context)?.into_bytes())
+    }
+
+    /// Runs several commands piping stdout of one command into stdin of the next and returns the
+    /// output of the last command as is
+    ///
+    /// The default implementation returns the output of `exec_piped`.
+    ///
+    /// * `commands` - a vector of tuples of arrays of string containing the command and arguments, and contexts
+    ///
+    fn exec_piped_bytes<'a>(
+        &mut self,
+        commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        Ok(self.exec_piped(commands)?.into_bytes())
+    }
+
+    /// Runs a command in the provided context and returns its output, stderr and exit status
+    ///
+    /// A status other than 0 is not an error. The default implementation is based on `exec`;
+    /// hence, the stderr of successful commands and the stdout of failing ones are missing.
+    ///
+    /// * `command` - array of strings containing the command and arguments
+    /// * `context` - either a local or a remote context
+    ///
+    fn exec_full<'a>(
+        &mut self,
+        command: &str,
+        args: &[&'a str],
+        context: Option<&'a Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        let start = std::time::Instant::now();
+
+        full(self.exec(command, args, context), start)
+    }
+
+    /// Runs several commands piping stdout of one command into stdin of the next and returns the
+    /// output and exit status of the last command together with the stderr of all commands
+    ///
+    /// The default implementation is based on `exec_piped` like the one of `exec_full`.
+    ///
+    /// * `commands` - a vector of tuples of arrays of string containing the command and arguments, and contexts
+    ///
+    fn exec_piped_full<'a>(
+        &mut self,
+        commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        let start = std::time::Instant::now();
+
+        full(self.exec_piped(commands), start)
+    }
+}
+
+/// Converts the result of `exec` into the one of `exec_full`; failures with a status are output
+fn full(
+    result: Result<String, ExecError>,
+    start: std::time::Instant,
+) -> Result<ExecOutput, ExecError> {
+    let output = |stdout, stderr, status| ExecOutput {
+        stdout,
+        stderr,
+        status,
+        duration: start.elapsed(),
+    };
+
+    match result {
+        Ok(stdout) => Ok(output(stdout, String::new(), 0)),
+        Err(ExecError::TerminationWithError(status, stderr)) => {
+            Ok(output(String::new(), stderr, status))
+        }
+        Err(ExecError::TerminationWithErrorCode(status)) => {
+            Ok(output(String::new(), String::new(), status))
+        }
+        Err(e) => Err(e),
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Context {
     /// Local context
     ///
@@ -36,14 +291,91 @@ pub enum Context {
     ///
     /// * `host` - name of the remote host
     /// * `config` - path and filename of the ssh config file
+    /// * `proxy` - proxy the connection is established through
+    /// * `settings` - options of the connection, taking precedence over the config file
     ///
     Remote {
         host: String,
         config: Option<String>,
+        proxy: Option<ProxyConfig>,
+        settings: Option<Box<SshSettings>>,
+    },
+    /// Docker container context; commands are run by `docker exec`
+    ///
+    /// * `container` - name or id of the running container
+    /// * `user` - user executing the command in the container; the default user of the image if not set
+    ///
+    Docker {
+        container: String,
+        user: Option<String>,
+    },
+    /// Kubernetes pod context; commands are run by `kubectl exec`
+    ///
+    /// * `namespace` - namespace of the pod; the one of the current kubectl context if not set
+    /// * `pod` - name of the pod
+    /// * `container` - container within the pod; the default container if not set
+    /// * `kubeconfig` - path and filename of the kubeconfig file
+    ///
+    Kubernetes {
+        namespace: Option<String>,
+        pod: String,
+        container: Option<String>,
+        kubeconfig: Option<String>,
+    },
+    /// Windows Subsystem for Linux context; commands are run by `wsl.exe`
+    ///
+    /// * `distribution` - name of the distribution; the default distribution if not set
+    /// * `user` - user executing the command; the default user of the distribution if not set
+    ///
+    Wsl {
+        distribution: Option<String>,
+        user: Option<String>,
     },
+    /// Nested contexts; each context is entered from within the preceding one
+    ///
+    /// E.g. `Chain(vec![remote, Context::Local { user: "root".to_string() }])` runs the command as
+    /// root on the remote host (`ssh host sudo -nu root -- <command>`). The users of nested local
+    /// contexts are always switched by the escalation tool.
+    ///
+    Chain(Vec<Context>),
+}
+
+impl Context {
+    /// Returns the host of the first ssh connection made for the context
+    pub(crate) fn ssh_host(&self) -> Option<&str> {
+        match self {
+            Context::Remote { host, .. } => Some(host),
+            Context::Chain(contexts) => contexts.iter().find_map(Context::ssh_host),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Context::Local { user } => write!(f, "local as {}", user),
+            Context::Remote { host, .. } => write!(f, "remote on {}", host),
+            Context::Docker { container, .. } => write!(f, "docker container {}", container),
+            Context::Kubernetes { namespace, pod, .. } => match namespace {
+                Some(namespace) => write!(f, "kubernetes pod {}/{}", namespace, pod),
+                None => write!(f, "kubernetes pod {}", pod),
+            },
+            Context::Wsl { distribution, .. } => match distribution {
+                Some(distribution) => write!(f, "wsl distribution {}", distribution),
+                None => write!(f, "wsl"),
+            },
+            Context::Chain(contexts) => {
+                let contexts: Vec<String> = contexts.iter().map(Context::to_string).collect();
+
+                write!(f, "{}", contexts.join(" > "))
+            }
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum ExecError {
     #[error("error during execution: {0}")]
     Execution(String),
@@ -59,9 +391,87 @@ pub enum ExecError {
     TerminationWithError(i32, String),
     #[error("command finished with status code {0}")]
     TerminationWithErrorCode(i32),
+    #[error("parameter {0} is not bound")]
+    UnboundParameter(String),
+    #[error("parameter {0} contains control characters")]
+    InvalidParameter(String),
+    #[error("command {0}")]
+    Failed(Box<ExecReport>),
+    #[error("argument {0:?} is not valid UTF-8 and cannot be passed to a remote shell")]
+    NonUtf8Argument(std::ffi::OsString),
+    #[error(transparent)]
+    InvalidPattern(#[from] regex::Error),
+    #[error("timed out waiting for output matching {0}")]
+    ExpectTimeout(String),
+    #[error("output ended before matching {0}")]
+    ExpectEof(String),
+    #[error("shell session terminated")]
+    SessionTerminated,
+    #[error("command wrote to stderr: {0}")]
+    Stderr(String),
+    #[error("host key of {0} does not match the known key")]
+    HostKeyMismatch(String),
+    #[error("host key of {0} is not known")]
+    HostKeyUnknown(String),
+    #[error("ssh connection to {0} failed: {1}")]
+    Transport(String, String),
+    #[error("authentication to {0} failed")]
+    Authentication(String),
+    #[error("user {0} does not exist")]
+    UnknownUser(String),
+    #[error("command timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("execution was cancelled")]
+    Cancelled,
+    #[error("output exceeded the limit of {0} bytes")]
+    OutputTooLarge(usize),
+    #[error("sudo requires authentication to run commands as {0}")]
+    SudoAuthenticationRequired(String),
+    #[error("sudo authentication to run commands as {0} failed")]
+    SudoAuthenticationFailed(String),
+    #[error("context {0} is not usable: {1}")]
+    Unavailable(String, String),
+    #[error("unexpected command {0}")]
+    UnexpectedCommand(String),
+    #[error("output could not be parsed: {0}")]
+    Parse(String, String),
 }
 
-pub struct CommandExec {}
+/// Exit status and captured stderr of a pipeline stage
+type Stage = (std::process::ExitStatus, Vec<u8>);
+
+/// Executor running commands as processes, configured by its builder methods
+///
+/// Since version 3, the executor has settings; it is created by `CommandExec::new()` or
+/// `CommandExec::default()` instead of `CommandExec {}`.
+#[derive(Default, Clone)]
+pub struct CommandExec {
+    escalation: Escalation,
+    sudo_auth: Option<SudoAuth>,
+    force_escalation: bool,
+    switch_user: bool,
+    strip_ansi: bool,
+    decoding: Decoding,
+    color: ColorMode,
+    forward_env: Vec<String>,
+    retry: Option<RetryPolicy>,
+    exit_policy: ExitPolicy,
+    reconnect: bool,
+    timeout: Option<std::time::Duration>,
+    cancellation: Option<Cancellation>,
+    output_limit: Option<OutputLimit>,
+    known_hosts: Option<KnownHosts>,
+    strict_stderr: bool,
+    stderr_warnings: bool,
+    pipefail: bool,
+    progress: Option<progress::Callback>,
+    hooks: hook::Hooks,
+    resolver: Option<platform::Resolver>,
+    platforms: platform::Cache,
+    masters: multiplex::Masters,
+    #[cfg(windows)]
+    job_limits: JobLimits,
+}
 
 impl Exec for CommandExec {
     fn exec(
@@ -70,7 +480,7 @@ impl Exec for CommandExec {
         args: &[&str],
         context: Option<&Context>,
     ) -> Result<String, ExecError> {
-        self.run_piped(&vec![(command, args, context)])
+        self.run_piped(&[(command, args, context)])
     }
 
     fn exec_piped(
@@ -79,164 +489,4761 @@ impl Exec for CommandExec {
     ) -> Result<String, ExecError> {
         self.run_piped(commands)
     }
-}
-
-impl CommandExec {
-    fn run_piped(
-        &mut self,
-        commands: &[(&str, &[&str], Option<&Context>)],
-    ) -> Result<String, ExecError> {
-        let mut child: Option<std::process::Child> = None;
 
-        for (command, args, context) in commands {
-            match child {
-                Some(mut c) => {
-                    child = Some(self.run_single(command, args, *context, Some(&mut c))?);
-                }
-                None => {
-                    child = Some(self.run_single(command, args, *context, None)?);
-                }
-            }
-        }
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        self.run_specs(pipeline.stages(), pipeline.get_input())
+    }
 
-        let output = child.ok_or(ExecError::Chaining)?.wait_with_output()?;
-        let output = CommandExec::check_output(&output)?;
+    fn exec_bytes(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        self.run_piped_bytes(&[(command, args, context)])
+    }
 
-        Ok(String::from_utf8(output)?)
+    fn exec_piped_bytes(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        self.run_piped_bytes(commands)
     }
 
-    fn run_single(
+    fn exec_full(
         &mut self,
         command: &str,
         args: &[&str],
         context: Option<&Context>,
-        pre: Option<&mut std::process::Child>,
-    ) -> Result<std::process::Child, ExecError> {
-        let mut com = match context {
-            Some(Context::Local { user }) => {
-                let mut com = std::process::Command::new("sudo");
+    ) -> Result<ExecOutput, ExecError> {
+        self.exec_piped_full(&[(command, args, context)])
+    }
 
-                com.arg("-nu").arg(user).arg("--").arg(command);
-                com
-            }
-            Some(Context::Remote { host, config }) => {
-                let mut com = std::process::Command::new("ssh");
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        let specs = self.resolve(&CommandExec::specs(commands))?;
+        let result = self.with_retry(|exec| exec.run_specs_full(&specs)).0;
 
-                if let Some(config) = config {
-                    com.arg("-F").arg(config);
-                }
+        self.after_hooks(&specs, &result);
+        result
+    }
+}
 
-                com.arg(host).arg(command);
-                com
-            }
-            None => std::process::Command::new(command),
-        };
+impl CommandExec {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        com.args(args);
+    /// Sets the tool used to run local commands as a different user
+    ///
+    /// * `escalation` - the escalation tool; see `Escalation::detect` for picking an available one
+    ///
+    pub fn escalation(mut self, escalation: Escalation) -> Self {
+        self.escalation = escalation;
+        self
+    }
 
-        match pre {
-            Some(child) => {
-                let stdout = child.stdout.take().ok_or(ExecError::Chaining)?;
-                com.stdin(stdout);
-            }
-            None => {}
-        }
+    /// Provides the credentials for sudo when running local commands as a different user
+    ///
+    /// Only applies to `Escalation::Sudo` in local contexts; without it, sudo is run
+    /// non-interactively and fails with `ExecError::SudoAuthenticationRequired` if it needs a
+    /// password.
+    ///
+    /// * `auth` - the password or the askpass program
+    ///
+    pub fn sudo_auth(mut self, auth: SudoAuth) -> Self {
+        self.sudo_auth = Some(auth);
+        self
+    }
 
-        com.stdout(std::process::Stdio::piped())
-            .spawn()
-            .map_err(|e| ExecError::Io(e))
+    /// Always wraps local commands in the escalation tool, even if the requested user is the effective user
+    ///
+    /// * `force` - escalate regardless of the current user
+    ///
+    pub fn force_escalation(mut self, force: bool) -> Self {
+        self.force_escalation = force;
+        self
     }
 
-    fn check_output(output: &std::process::Output) -> Result<Vec<u8>, ExecError> {
-        match output.status.code() {
-            Some(code) => {
-                if code == 0 {
-                    Ok(output.stdout.clone())
-                } else {
-                    match String::from_utf8(output.stderr.clone()) {
-                        Ok(s) => Err(ExecError::TerminationWithError(code, s)),
-                        Err(_) => Err(ExecError::TerminationWithErrorCode(code)),
-                    }
-                }
-            }
-            None => Err(ExecError::TerminationBySignal),
-        }
+    /// Switches to the user of local contexts directly instead of using the escalation tool (unix)
+    ///
+    /// Only applies if the calling process runs as root; the ids of the user are set before the
+    /// command is executed. Hence, no escalation tool needs to be installed and its policy does
+    /// not apply.
+    ///
+    /// * `switch` - set the ids of the user directly when running as root
+    ///
+    pub fn switch_user(mut self, switch: bool) -> Self {
+        self.switch_user = switch;
+        self
     }
-}
 
-#[cfg(all(test, feature = "mockall"))]
-mod tests {
-    use super::*;
+    /// Removes ANSI escape sequences from the captured output
+    ///
+    /// * `strip` - strip colors and other escape sequences emitted by the commands
+    ///
+    pub fn strip_ansi(mut self, strip: bool) -> Self {
+        self.strip_ansi = strip;
+        self
+    }
 
-    #[test]
-    fn example() {
-        let mut mock = MockExec::new();
+    /// Sets how the captured output is converted into strings
+    ///
+    /// By default, output that is not valid UTF-8 is an error. For single calls, a clone of the
+    /// executor can be configured differently.
+    ///
+    /// * `decoding` - strict, lossy or latin-1 decoding
+    ///
+    pub fn decoding(mut self, decoding: Decoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
 
-        mock.expect_exec()
-            .once()
-            .returning(|_command, _args, _context| Ok("ok".to_string()));
+    /// Forwards variables of the own environment to commands run in other contexts
+    ///
+    /// Neither sudo nor ssh pass on the environment by default. The forwarded variables are set by
+    /// the command line in the context, e.g. `ssh host env HTTP_PROXY=... <command>`; variables
+    /// set by the spec take precedence. Locally run commands inherit the environment anyway.
+    ///
+    /// * `names` - names of the variables; a trailing `*` matches all names with the prefix, e.g. `AWS_*`
+    ///
+    pub fn forward_env(mut self, names: &[&str]) -> Self {
+        self.forward_env = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
 
-        let res = mock
-            .exec(
-                "",
-                &[""],
-                Some(&Context::Local {
-                    user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
-                }),
-            )
-            .unwrap();
+    /// Requests colored or uncolored output from the commands
+    ///
+    /// * `color` - the color mode
+    ///
+    pub fn color(mut self, color: ColorMode) -> Self {
+        self.color = color;
+        self
+    }
 
-        assert_eq!(res, "ok");
+    /// Treats output on stderr as failure, even if the commands finished with status code 0
+    ///
+    /// The stderr of all stages of a pipeline is captured instead of being passed through. Any
+    /// output results in `ExecError::Stderr` with the captured text.
+    ///
+    /// * `strict` - fail on output to stderr
+    ///
+    pub fn strict_stderr(mut self, strict: bool) -> Self {
+        self.strict_stderr = strict;
+        self
     }
 
-    #[test]
-    fn run() {
-        let mut com = CommandExec {};
+    /// Collects the stderr of successful commands as warnings in the report
+    ///
+    /// * `collect` - fill `ExecReport::warnings` with the lines written to stderr
+    ///
+    pub fn stderr_warnings(mut self, collect: bool) -> Self {
+        self.stderr_warnings = collect;
+        self
+    }
 
-        assert_eq!(
-            com.exec(
-                "ls",
-                &["Cargo.toml"],
-                Some(&Context::Local {
-                    user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
-                })
-            )
-            .unwrap(),
-            "Cargo.toml\n"
-        );
+    /// Sets the exit statuses treated as success; applies to specs without accepted statuses
+    ///
+    /// * `policy` - decides whether an exit status is a success
+    ///
+    pub fn exit_policy(mut self, policy: ExitPolicy) -> Self {
+        self.exit_policy = policy;
+        self
     }
 
-    #[test]
-    fn run_piped() {
-        let mut com = CommandExec {};
-        let context = Context::Local {
-            user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
-        };
+    /// Treats the listed exit statuses as success, e.g. `&[0, 1]` for `grep` or `diff`
+    ///
+    /// * `codes` - the statuses that are a success
+    ///
+    pub fn accept_codes(self, codes: &[i32]) -> Self {
+        self.exit_policy(ExitPolicy::accept_codes(codes))
+    }
 
-        assert_eq!(
-            com.run_piped(&[
-                ("cat", &["Cargo.toml"], Some(&context)),
-                ("grep", &["name"], Some(&context)),
-            ])
-            .unwrap(),
-            "name = \"exec-rs\"\n"
-        );
+    /// Retries failed executions according to the policy
+    ///
+    /// * `policy` - the retry policy; applies to all executions capturing the output as a whole
+    ///
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
     }
 
-    #[test]
-    fn run_piped_mixed_context() {
-        let mut com = CommandExec {};
-        let context = Context::Local {
-            user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
-        };
+    /// Runs the operation with the retry policy instead of the one set on the executor
+    ///
+    /// * `policy` - the retry policy for the executions of the operation
+    /// * `operation` - the operation, e.g. a single call of `exec`
+    ///
+    pub fn with_retry_policy<T>(
+        &mut self,
+        policy: RetryPolicy,
+        operation: impl FnOnce(&mut Self) -> Result<T, ExecError>,
+    ) -> Result<T, ExecError> {
+        let previous = self.retry.replace(policy);
+        let res = operation(self);
 
-        assert_eq!(
-            com.run_piped(&[
-                ("cat", &["Cargo.toml"], Some(&context)),
-                ("grep", &["name"], None),
-            ])
-            .unwrap(),
-            "name = \"exec-rs\"\n"
-        );
+        self.retry = previous;
+        res
+    }
+
+    /// Kills the commands if they did not finish in time
+    ///
+    /// Exceeding the timeout results in `ExecError::Timeout`. It applies to every execution
+    /// capturing the output as a whole, i.e. to each attempt of a retry policy.
+    ///
+    /// * `timeout` - maximum duration of an execution
+    ///
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails pipelines if any stage fails, like `set -o pipefail` of bash
+    ///
+    /// By default, only the status of the last stage counts, so that `false | cat` succeeds. With
+    /// pipefail, the first failing stage determines the error; stages terminated by a signal,
+    /// e.g. by `SIGPIPE` when the next stage stopped reading, fail the pipeline, too.
+    ///
+    /// * `pipefail` - check the status of every stage
+    ///
+    pub fn pipefail(mut self, pipefail: bool) -> Self {
+        self.pipefail = pipefail;
+        self
+    }
+
+    /// Limits the size of each captured output stream, e.g. of commands printing gigabytes
+    ///
+    /// The output beyond the limit is discarded while it is read. Depending on the limit, the
+    /// execution then either returns the truncated output or terminates the command and fails.
+    /// The limit applies to every captured stream, including the stderr of pipeline stages.
+    /// Reports mark truncated streams.
+    ///
+    /// * `limit` - maximum size and treatment of larger output
+    ///
+    pub fn output_limit(mut self, limit: OutputLimit) -> Self {
+        self.output_limit = Some(limit);
+        self
+    }
+
+    /// Aborts the executions once the token is cancelled, e.g. from another thread
+    ///
+    /// Like the timeout, it applies to every execution capturing the output as a whole.
+    ///
+    /// * `cancellation` - token cancelling the executions
+    ///
+    pub fn cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    /// Runs the commands once more if the ssh connection failed
+    ///
+    /// Failures of the connection are reported as `ExecError::Transport`; they are recognized by
+    /// the messages ssh writes to stderr, which is passed through and captured for remote commands.
+    /// The retry establishes a new connection and is independent of the retry policy.
+    ///
+    /// * `reconnect` - retry once on `ExecError::Transport`
+    ///
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Verifies the keys of remote hosts against the known_hosts file
+    ///
+    /// * `known_hosts` - the only keys accepted for remote hosts
+    ///
+    pub fn known_hosts(mut self, known_hosts: KnownHosts) -> Self {
+        self.known_hosts = Some(known_hosts);
+        self
+    }
+
+    /// Reports the progress of pipelines and fleet runs to the callback
+    ///
+    /// The callback is invoked on the thread executing the commands; forward the events to a
+    /// channel to process them elsewhere.
+    ///
+    /// * `callback` - receives the progress events
+    ///
+    pub fn progress(mut self, callback: impl Fn(&ProgressEvent) + Send + Sync + 'static) -> Self {
+        self.progress = Some(std::sync::Arc::new(callback));
+        self
+    }
+
+    pub(crate) fn emit(&self, event: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress(&event);
+        }
+    }
+
+    /// Sets the limits of the job object the spawned processes are assigned to
+    ///
+    /// * `limits` - CPU time and memory limits shared by all processes of an execution
+    ///
+    #[cfg(windows)]
+    pub fn job_limits(mut self, limits: JobLimits) -> Self {
+        self.job_limits = limits;
+        self
+    }
+
+    /// Returns the command as it would be spawned, i.e., wrapped according to its context
+    ///
+    /// * `spec` - the command to be converted
+    ///
+    pub fn command(&self, spec: &CommandSpec) -> Result<std::process::Command, ExecError> {
+        let local = matches!(spec.get_context(), None | Some(Context::Local { .. }));
+        let mut com = self.command_line(spec)?;
+
+        self.validate_sudo(spec.get_context())?;
+
+        #[cfg(target_os = "linux")]
+        if let (true, Some(scheduling)) = (local, spec.get_scheduling()) {
+            scheduling.apply(&mut com);
+        }
+
+        #[cfg(target_os = "linux")]
+        if let (true, Some(adjustment)) = (local, spec.get_oom_score_adj()) {
+            oom::apply(adjustment, &mut com);
+        }
+
+        #[cfg(unix)]
+        if let (true, Some(limits)) = (local, spec.get_resource_limits()) {
+            limits.apply(&mut com);
+        }
+
+        if let (true, Some(stdin)) = (local, spec.get_stdin()) {
+            com.stdin(std::fs::File::open(stdin)?);
+        }
+
+        if let (true, Some(stdout)) = (local, spec.get_stdout()) {
+            com.stdout(stdout.open()?);
+        }
+
+        if let (true, Some(stderr)) = (local, spec.get_stderr()) {
+            com.stderr(stderr.open()?);
+        }
+
+        if spec.get_new_session() {
+            CommandExec::start_new_session(&mut com);
+        }
+
+        spec.apply_configurators(&mut com);
+        Ok(com)
+    }
+
+    /// Returns the command wrapped according to its context, without setting up the process
+    ///
+    /// Local redirections, process attributes and the configurators of the spec are left out, so
+    /// that no file is opened and no callback is run.
+    pub(crate) fn command_line(
+        &self,
+        spec: &CommandSpec,
+    ) -> Result<std::process::Command, ExecError> {
+        let context = spec.get_context();
+        let direct = self.is_direct(context);
+        let argv = self.context_argv(spec)?;
+        let mut com = self.build_command(&argv[0], &argv[1..], context)?;
+
+        if direct {
+            com.envs(self.color.env().iter().copied());
+
+            for (key, value) in spec.get_envs() {
+                match value {
+                    Some(value) => com.env(key, value),
+                    None => com.env_remove(key),
+                };
+            }
+        }
+
+        if let (true, Some(dir)) = (direct, spec.get_current_dir()) {
+            com.current_dir(dir);
+        }
+
+        Ok(com)
+    }
+
+    /// Returns the command line executed within the context of the spec
+    pub(crate) fn context_argv(
+        &self,
+        spec: &CommandSpec,
+    ) -> Result<Vec<std::ffi::OsString>, ExecError> {
+        let context = spec.get_context();
+        let direct = self.is_direct(context);
+        let local = matches!(context, None | Some(Context::Local { .. }));
+        let color = self.color.env();
+        let mut argv: Vec<std::ffi::OsString> = spec.get_quoting().argv(
+            std::iter::once(spec.get_program())
+                .chain(spec.get_args().iter().map(|arg| arg.as_os_str()))
+                .map(|part| part.to_os_string())
+                .collect(),
+        );
+
+        if let Some(isolation) = spec.get_isolation() {
+            argv = isolation.wrap(argv);
+        }
+
+        if let Some(scheduling) = spec.get_scheduling() {
+            if !(local && cfg!(target_os = "linux")) {
+                argv = scheduling.wrap(argv);
+            }
+        }
+
+        if let Some(adjustment) = spec.get_oom_score_adj() {
+            if !(local && cfg!(target_os = "linux")) {
+                argv = oom::wrap(adjustment, argv);
+            }
+        }
+
+        if spec.get_stdin_fifo().is_some() || spec.get_stdout_fifo().is_some() {
+            argv = fifo::wrap(spec.get_stdin_fifo(), spec.get_stdout_fifo(), argv)?;
+        }
+
+        if !local
+            && (spec.get_stdin().is_some()
+                || spec.get_stdout().is_some()
+                || spec.get_stderr().is_some())
+        {
+            argv = redirect::wrap(spec.get_stdin(), spec.get_stdout(), spec.get_stderr(), argv)?;
+        }
+
+        if let (false, Some(dir)) = (direct, spec.get_current_dir()) {
+            // the directory must be changed by the command line in the context
+            argv = cwd::wrap(dir, argv);
+        }
+
+        if !direct {
+            let envs: Vec<(std::ffi::OsString, Option<std::ffi::OsString>)> = self
+                .forwarded_env()
+                .chain(spec.get_envs().iter().cloned())
+                .collect();
+
+            if !color.is_empty() || !envs.is_empty() {
+                // sudo and ssh do not forward the environment; hence, it is set by the command line
+                argv = std::iter::once("env".into())
+                    .chain(CommandExec::env_args(color, &envs))
+                    .chain(argv)
+                    .collect();
+            }
+        }
+
+        Ok(argv)
+    }
+
+    /// Returns the variables of the own environment to be forwarded, sorted by name
+    fn forwarded_env(
+        &self,
+    ) -> impl Iterator<Item = (std::ffi::OsString, Option<std::ffi::OsString>)> {
+        let mut vars: Vec<_> = std::env::vars_os()
+            .filter(|(key, _)| {
+                key.to_str().is_some_and(|key| {
+                    self.forward_env
+                        .iter()
+                        .any(|name| match name.strip_suffix('*') {
+                            Some(prefix) => key.starts_with(prefix),
+                            None => key == name,
+                        })
+                })
+            })
+            .map(|(key, value)| (key, Some(value)))
+            .collect();
+
+        vars.sort();
+        vars.into_iter()
+    }
+
+    /// Returns the arguments of `env` applying the variables; removals precede the assignments
+    fn env_args(
+        color: &[(&str, &str)],
+        envs: &[(std::ffi::OsString, Option<std::ffi::OsString>)],
+    ) -> Vec<std::ffi::OsString> {
+        let mut vars: Vec<(std::ffi::OsString, Option<std::ffi::OsString>)> = color
+            .iter()
+            .map(|(key, value)| (key.into(), Some(value.into())))
+            .collect();
+
+        // later settings of a variable replace earlier ones
+        for (key, value) in envs {
+            vars.retain(|(other, _)| other != key);
+            vars.push((key.clone(), value.clone()));
+        }
+
+        let removed = vars
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .flat_map(|(key, _)| ["-u".into(), key.clone()]);
+        let assigned = vars.iter().filter_map(|(key, value)| {
+            value.as_ref().map(|value| {
+                let mut assignment = key.clone();
+
+                assignment.push("=");
+                assignment.push(value);
+                assignment
+            })
+        });
+
+        removed.chain(assigned).collect()
+    }
+
+    /// Runs the command described by the spec
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    pub fn exec_spec(&mut self, spec: &CommandSpec) -> Result<String, ExecError> {
+        self.run_specs(std::slice::from_ref(spec), None)
+    }
+
+    /// Runs a shell snippet, e.g. a one-liner with redirections, globs or pipes
+    ///
+    /// The snippet is interpreted by `sh -c` within the context, i.e. as the user of a local
+    /// context or on the remote host, independent of the login shell there.
+    ///
+    /// * `script` - the command line to be interpreted by the shell
+    /// * `context` - either a local or a remote context
+    ///
+    pub fn exec_shell(
+        &mut self,
+        script: &str,
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        let spec = CommandSpec::new(script).quoting(Quoting::None);
+
+        self.exec_spec(&match context {
+            Some(context) => spec.context(context.clone()),
+            None => spec,
+        })
+    }
+
+    /// Runs the commands described by the specs piping stdout of one command into stdin of the next
+    ///
+    /// * `specs` - the stages of the pipeline
+    ///
+    pub fn exec_specs_piped(&mut self, specs: &[CommandSpec]) -> Result<String, ExecError> {
+        self.run_specs(specs, None)
+    }
+
+    /// Runs a command writing the input to its stdin
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    /// * `input` - data written to stdin of the command
+    ///
+    pub fn exec_with_input(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+        input: &[u8],
+    ) -> Result<String, ExecError> {
+        self.run_specs(
+            &CommandExec::specs(&[(command, args, context)]),
+            Some(input),
+        )
+    }
+
+    /// Runs several commands piping stdout of one command into stdin of the next; stdin of the
+    /// first command is read from the reader
+    ///
+    /// The input cannot be replayed; hence, the retry policy does not apply.
+    ///
+    /// * `commands` - a vector of tuples of arrays of string containing the command and arguments, and contexts
+    /// * `input` - reader providing stdin of the first command
+    ///
+    pub fn exec_piped_from(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+        mut input: impl std::io::Read + Send,
+    ) -> Result<String, ExecError> {
+        let specs = self.resolve(&CommandExec::specs(commands))?;
+        let start = std::time::Instant::now();
+        let output = self.run_specs_once(&specs, Some(&mut input), None);
+        let output = self.after_hooks_bytes(&specs, start, output)?;
+
+        self.text(output)
+    }
+
+    /// Runs the pipeline and copies the output to the writer while it is produced
+    ///
+    /// The output is returned, too. As it may have been written partially, the retry policy does
+    /// not apply. Unlike a `tee` within the pipeline, this works the same way in every context.
+    ///
+    /// * `pipeline` - the commands piped into each other
+    /// * `tee` - writer receiving a copy of stdout of the last command, e.g. a log file
+    ///
+    pub fn exec_tee(
+        &mut self,
+        pipeline: &Pipeline,
+        tee: &mut (dyn std::io::Write + Send),
+    ) -> Result<String, ExecError> {
+        let specs = self.resolve(pipeline.stages())?;
+        let mut input = pipeline.get_input();
+        let start = std::time::Instant::now();
+        let output = self.run_specs_once(&specs, input.as_mut().map(|input| input as _), Some(tee));
+        let output = self.after_hooks_bytes(&specs, start, output)?;
+
+        self.text(output)
+    }
+
+    /// Replaces the current process with the command (unix only)
+    ///
+    /// The command is wrapped according to its context, so that a program can hand over to
+    /// `ssh` or `sudo` completely. The function only returns if the replacement failed.
+    ///
+    /// * `spec` - the command replacing the current process
+    ///
+    pub fn exec_replace(&self, spec: &CommandSpec) -> ExecError {
+        let mut com = match self.command(spec) {
+            Ok(com) => com,
+            Err(e) => return e,
+        };
+
+        #[cfg(unix)]
+        {
+            // std resets SIGPIPE to the default before the replacement, also if it fails
+            // SAFETY: only queries the current disposition, which is restored right away
+            let sigpipe = unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN) };
+            // SAFETY: restores a disposition returned by signal
+            unsafe { libc::signal(libc::SIGPIPE, sigpipe) };
+
+            let error = std::os::unix::process::CommandExt::exec(&mut com);
+
+            // SAFETY: restores a disposition returned by signal
+            unsafe { libc::signal(libc::SIGPIPE, sigpipe) };
+            ExecError::Io(error)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = &mut com;
+            ExecError::Io(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "process replacement is only supported on unix",
+            ))
+        }
+    }
+
+    fn run_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        self.run_specs(&CommandExec::specs(commands), None)
+    }
+
+    fn run_piped_bytes(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        self.run_specs_bytes(&CommandExec::specs(commands), None)
+    }
+
+    /// Converts the commands given as tuples into specs
+    pub(crate) fn specs(commands: &[(&str, &[&str], Option<&Context>)]) -> Vec<CommandSpec> {
+        commands
+            .iter()
+            .map(|(command, args, context)| {
+                let spec = CommandSpec::new(command).args(*args);
+
+                match context {
+                    Some(context) => spec.context((*context).clone()),
+                    None => spec,
+                }
+            })
+            .collect()
+    }
+
+    fn run_specs(
+        &mut self,
+        specs: &[CommandSpec],
+        input: Option<&[u8]>,
+    ) -> Result<String, ExecError> {
+        let output = self.run_specs_bytes(specs, input)?;
+
+        self.text(output)
+    }
+
+    fn run_specs_bytes(
+        &mut self,
+        specs: &[CommandSpec],
+        input: Option<&[u8]>,
+    ) -> Result<Vec<u8>, ExecError> {
+        let specs = self.resolve(specs)?;
+        let start = std::time::Instant::now();
+        // every attempt reads the input from the start
+        let mut attempt = |exec: &mut Self| {
+            let mut input = input;
+
+            exec.run_specs_once(&specs, input.as_mut().map(|input| input as _), None)
+        };
+        let result = match self.with_retry(&mut attempt).0 {
+            Err(ExecError::Transport(..)) if self.reconnect => self.with_retry(attempt).0,
+            res => res,
+        };
+
+        self.after_hooks_bytes(&specs, start, result)
+    }
+
+    /// Runs the operation according to the retry policy; returns the result and the number of retries
+    fn with_retry<T>(
+        &mut self,
+        mut operation: impl FnMut(&mut Self) -> Result<T, ExecError>,
+    ) -> (Result<T, ExecError>, u32) {
+        match self.retry.clone() {
+            Some(policy) => policy.run(|| operation(self)),
+            None => (operation(self), 0),
+        }
+    }
+
+    fn run_specs_once(
+        &mut self,
+        specs: &[CommandSpec],
+        input: Option<&mut (dyn std::io::Read + Send)>,
+        tee: Option<&mut (dyn std::io::Write + Send)>,
+    ) -> Result<Vec<u8>, ExecError> {
+        let (mut output, stages) = self.run_specs_output(specs, self.strict_stderr, input, tee)?;
+
+        let last = specs.last();
+
+        if self.pipefail {
+            for (spec, (status, stderr)) in specs.iter().zip(stages.iter()) {
+                self.check_status(
+                    Some(spec),
+                    std::process::Output {
+                        status: *status,
+                        stdout: Vec::new(),
+                        stderr: stderr.clone(),
+                    },
+                )?;
+            }
+        }
+
+        let stderr = stages.into_iter().map(|(_, stderr)| stderr);
+
+        if self.strict_stderr
+            && output
+                .status
+                .code()
+                .is_some_and(|code| self.accepts(last, code))
+            && (!output.stderr.is_empty() || stderr.clone().any(|stderr| !stderr.is_empty()))
+        {
+            let stderr: Vec<u8> = stderr.flatten().chain(output.stderr).collect();
+
+            return Err(ExecError::Stderr(
+                String::from_utf8_lossy(&stderr).into_owned(),
+            ));
+        }
+
+        // the messages of the preceding stages often explain the failure, e.g. a missing input file
+        if !output
+            .status
+            .code()
+            .is_some_and(|code| code == 0 || self.accepts(last, code))
+        {
+            output.stderr = stderr.flatten().chain(output.stderr).collect();
+        }
+
+        self.check_status(last, output)
+    }
+
+    fn run_specs_full(&mut self, specs: &[CommandSpec]) -> Result<ExecOutput, ExecError> {
+        let start = std::time::Instant::now();
+        let (output, stages) = self.run_specs_output(specs, true, None, None)?;
+        let status = output.status.code().ok_or(ExecError::TerminationBySignal)?;
+        let stderr: Vec<u8> = stages
+            .into_iter()
+            .flat_map(|(_, stderr)| stderr)
+            .chain(output.stderr)
+            .collect();
+
+        Ok(ExecOutput {
+            stdout: self.text(output.stdout)?,
+            stderr: self.postprocess(String::from_utf8_lossy(&stderr).into_owned()),
+            status,
+            duration: start.elapsed(),
+        })
+    }
+
+    /// Runs the pipeline and returns the output of the last stage and the statuses and the stderr
+    /// of the preceding ones; the stderr of stages not capturing it is empty
+    ///
+    /// * `specs` - the commands to be executed
+    /// * `capture` - capture the stderr of all stages; otherwise, the stderr of single commands is
+    ///   only captured for remote commands and the one of pipeline stages is copied to the own stderr
+    /// * `input` - data written to stdin of the first stage
+    ///
+    fn run_specs_output(
+        &mut self,
+        specs: &[CommandSpec],
+        capture: bool,
+        input: Option<&mut (dyn std::io::Read + Send)>,
+        tee: Option<&mut (dyn std::io::Write + Send)>,
+    ) -> Result<(std::process::Output, Vec<Stage>), ExecError> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(Cancellation::is_cancelled)
+        {
+            return Err(ExecError::Cancelled);
+        }
+
+        let mut tree = self.process_tree()?;
+        let start = std::time::Instant::now();
+        #[cfg(feature = "tracing")]
+        let span = trace::span(specs);
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        // stderr of the stages capturing it, with the index of the stage
+        let mut stderr = Vec::new();
+        let mut stdin = None;
+
+        self.emit(ProgressEvent::BatchStarted {
+            commands: specs.len(),
+        });
+
+        for (index, spec) in specs.iter().enumerate() {
+            let child = self
+                .run_single(
+                    spec,
+                    tree.last_mut(),
+                    capture || specs.len() > 1,
+                    input.is_some(),
+                )
+                .and_then(|child| tree.push(child));
+
+            if let Err(e) = child {
+                tree.terminate();
+                self.emit(ProgressEvent::BatchFinished {
+                    summary: BatchSummary {
+                        commands: specs.len(),
+                        failed: specs.len() - index,
+                        duration: start.elapsed(),
+                    },
+                });
+                return Err(e);
+            }
+
+            self.emit(ProgressEvent::CommandStarted { index });
+
+            if index == 0 {
+                stdin = tree.last_mut().and_then(|child| child.stdin.take());
+            }
+
+            if let Some(pipe) = tree.last_mut().and_then(|child| child.stderr.take()) {
+                // if not captured on purpose, the stderr of pipeline stages, remote or traced commands is passed through, too
+                stderr.push((
+                    index,
+                    CommandExec::read_stderr(pipe, !capture, tree.limiter()),
+                ));
+            }
+        }
+
+        let limiter = tree.limiter();
+        // the copied output is read here instead of by the tree
+        let stdout = match tee {
+            Some(_) => tree.last_mut().and_then(|child| child.stdout.take()),
+            None => None,
+        };
+
+        // the input is written concurrently, as the command may only read it while writing output
+        let (mut output, statuses) = std::thread::scope(|scope| {
+            let writer = input.zip(stdin).map(|(input, mut stdin)| {
+                scope.spawn(move || std::io::copy(input, &mut stdin).map(|_| ()))
+            });
+            let reader = tee.zip(stdout).map(|(tee, stdout)| {
+                let limiter = limiter.clone();
+
+                scope.spawn(move || CommandExec::tee(stdout, tee, limiter))
+            });
+            let output = tree.wait_with_output_timeout(self.timeout, self.cancellation.as_ref());
+            let output = match reader.map(|reader| reader.join()) {
+                Some(Ok(stdout)) => output.and_then(|(output, statuses)| {
+                    Ok((
+                        std::process::Output {
+                            stdout: stdout?,
+                            ..output
+                        },
+                        statuses,
+                    ))
+                }),
+                Some(Err(_)) => Err(ExecError::Chaining),
+                None => output,
+            };
+
+            match writer.map(|writer| writer.join()) {
+                // a command not reading all of its input is not an error
+                Some(Ok(Err(e))) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e.into()),
+                Some(Err(_)) => Err(ExecError::Chaining),
+                _ => output,
+            }
+        })?;
+        let mut stderr = stderr
+            .into_iter()
+            .map(|(index, reader)| Ok((index, reader.join().map_err(|_| ExecError::Chaining)??)))
+            .collect::<Result<Vec<_>, ExecError>>()?;
+
+        // the readers of the stages and of the copied output may exceed the limit last
+        limiter.check()?;
+
+        // the captured stderr of the last stage is part of its output
+        if stderr
+            .last()
+            .is_some_and(|(index, _)| index + 1 == specs.len())
+        {
+            output.stderr = stderr.pop().map(|(_, stderr)| stderr).unwrap_or_default();
+        }
+
+        #[cfg(feature = "tracing")]
+        trace::finish(
+            &span,
+            start.elapsed(),
+            output.status.code(),
+            stderr
+                .iter()
+                .map(|(_, stderr)| stderr.as_slice())
+                .chain(std::iter::once(output.stderr.as_slice())),
+        );
+
+        if self.progress.is_some() {
+            let statuses: Vec<Option<i32>> = statuses
+                .iter()
+                .chain(std::iter::once(&output.status))
+                .map(|status| status.code())
+                .collect();
+
+            for (index, status) in statuses.iter().enumerate() {
+                self.emit(ProgressEvent::StageFinished {
+                    index,
+                    bytes: (index + 1 == statuses.len()).then_some(output.stdout.len() as u64),
+                    status: *status,
+                });
+            }
+
+            self.emit(ProgressEvent::BatchFinished {
+                summary: BatchSummary {
+                    commands: specs.len(),
+                    failed: statuses.iter().filter(|status| **status != Some(0)).count(),
+                    duration: start.elapsed(),
+                },
+            });
+        }
+
+        let mut stages: Vec<Stage> = statuses
+            .into_iter()
+            .map(|status| (status, Vec::new()))
+            .collect();
+
+        for (index, stderr) in stderr {
+            if let Some(stage) = stages.get_mut(index) {
+                stage.1 = stderr;
+            }
+        }
+
+        Ok((output, stages))
+    }
+
+    /// Reads the output to the end while copying it to the writer
+    ///
+    /// The output is read completely even if the writer fails, so that the command is not blocked;
+    /// it is kept up to the limit. Reading stops once the output exceeds `OutputLimit::Fail`.
+    fn tee(
+        mut stdout: std::process::ChildStdout,
+        tee: &mut (dyn std::io::Write + Send),
+        limiter: limits::Limiter,
+    ) -> Result<Vec<u8>, ExecError> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 8192];
+        let mut res = Ok(());
+
+        loop {
+            let len = std::io::Read::read(&mut stdout, &mut chunk)?;
+
+            if len == 0 {
+                break;
+            }
+
+            if res.is_ok() {
+                res = tee.write_all(&chunk[..len]);
+            }
+
+            limiter.extend(&mut buffer, &chunk[..len]);
+
+            if limiter.exceeded() {
+                break;
+            }
+        }
+
+        res.and_then(|_| tee.flush())?;
+        Ok(buffer)
+    }
+
+    /// Reads the stderr of a command up to the limit; with `tee`, it is also passed through to
+    /// the own stderr
+    pub(crate) fn read_stderr(
+        mut pipe: std::process::ChildStderr,
+        tee: bool,
+        limiter: limits::Limiter,
+    ) -> std::thread::JoinHandle<Result<Vec<u8>, ExecError>> {
+        std::thread::spawn(move || {
+            if !tee {
+                return Ok(limiter.read(&mut pipe)?.0);
+            }
+
+            let mut buffer = Vec::new();
+            let mut chunk = [0u8; 8192];
+
+            loop {
+                let len = std::io::Read::read(&mut pipe, &mut chunk)?;
+
+                if len == 0 || limiter.exceeded() {
+                    return Ok(buffer);
+                }
+
+                // a closed stderr of the caller must not fail the command
+                let _ = std::io::Write::write_all(&mut std::io::stderr(), &chunk[..len]);
+                limiter.extend(&mut buffer, &chunk[..len]);
+            }
+        })
+    }
+
+    /// Converts the captured output into text
+    fn text(&self, output: Vec<u8>) -> Result<String, ExecError> {
+        Ok(self.postprocess(self.decoding.decode(output)?))
+    }
+
+    fn postprocess(&self, output: String) -> String {
+        match self.strip_ansi {
+            true => strip_ansi(&output),
+            false => output,
+        }
+    }
+
+    fn process_tree(&self) -> Result<tree::ProcessTree, ExecError> {
+        tree::ProcessTree::new(
+            self.kills_tree(),
+            self.output_limit,
+            #[cfg(windows)]
+            &self.job_limits,
+        )
+    }
+
+    /// Checks whether executions may have to be killed, including the processes they started
+    fn kills_tree(&self) -> bool {
+        self.timeout.is_some()
+            || self.cancellation.is_some()
+            || matches!(self.output_limit, Some(OutputLimit::Fail(_)))
+    }
+
+    pub(crate) fn run_single(
+        &mut self,
+        spec: &CommandSpec,
+        pre: Option<&mut std::process::Child>,
+        capture: bool,
+        input: bool,
+    ) -> Result<std::process::Child, ExecError> {
+        let mut com = self.command(spec)?;
+
+        if input && spec.get_stdin().is_none() {
+            com.stdin(std::process::Stdio::piped());
+        }
+
+        if let Some(child) = pre {
+            // the output of the preceding command may have been redirected to a file
+            match child.stdout.take() {
+                _ if spec.get_stdin().is_some() => &mut com,
+                Some(stdout) => com.stdin(stdout),
+                None => com.stdin(std::process::Stdio::null()),
+            };
+        }
+
+        let remote = spec.get_context().and_then(Context::ssh_host).is_some();
+
+        tree::prepare(self.kills_tree(), spec, &mut com);
+
+        // the stderr of remote commands is classified and the one of traced commands is emitted
+        CommandExec::pipe_output(
+            spec,
+            &mut com,
+            capture || remote || cfg!(feature = "tracing"),
+        );
+        com.spawn().map_err(ExecError::Io)
+    }
+
+    /// Pipes stdout and optionally stderr of the command, unless the spec redirects them to files
+    pub(crate) fn pipe_output(spec: &CommandSpec, com: &mut std::process::Command, stderr: bool) {
+        if spec.get_stdout().is_none() && spec.get_stdout_fifo().is_none() {
+            com.stdout(std::process::Stdio::piped());
+        }
+
+        if stderr && spec.get_stderr().is_none() {
+            com.stderr(std::process::Stdio::piped());
+        }
+    }
+
+    #[cfg(unix)]
+    fn start_new_session(com: &mut std::process::Command) {
+        // SAFETY: setsid is async-signal-safe and the closure does not allocate
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(com, || match libc::setsid() {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            })
+        };
+    }
+
+    #[cfg(windows)]
+    fn start_new_session(com: &mut std::process::Command) {
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+        std::os::windows::process::CommandExt::creation_flags(com, CREATE_NEW_PROCESS_GROUP);
+    }
+
+    /// Checks whether commands in the context are spawned without wrapping
+    /// Checks whether commands of other users are run by switching the ids instead of escalating
+    fn switches_user(&self) -> bool {
+        #[cfg(unix)]
+        return self.switch_user && users::get_effective_uid() == 0;
+        #[cfg(not(unix))]
+        false
+    }
+
+    fn is_direct(&self, context: Option<&Context>) -> bool {
+        match context {
+            None => true,
+            Some(Context::Local { user }) => {
+                !self.force_escalation && CommandExec::is_effective_user(user)
+            }
+            Some(Context::Chain(contexts)) if contexts.is_empty() => true,
+            Some(Context::Remote { .. })
+            | Some(Context::Docker { .. })
+            | Some(Context::Wsl { .. })
+            | Some(Context::Kubernetes { .. })
+            | Some(Context::Chain(_)) => false,
+        }
+    }
+
+    fn build_command<S: AsRef<std::ffi::OsStr>>(
+        &self,
+        command: impl AsRef<std::ffi::OsStr>,
+        args: &[S],
+        context: Option<&Context>,
+    ) -> Result<std::process::Command, ExecError> {
+        Ok(match context {
+            #[cfg(unix)]
+            Some(Context::Local { user }) if !self.is_direct(context) && self.switches_user() => {
+                let mut com = std::process::Command::new(command);
+
+                com.args(args);
+                escalation::switch_user(&mut com, user)?;
+                com
+            }
+            Some(Context::Local { user }) if !self.is_direct(context) => {
+                let args: Vec<&std::ffi::OsStr> = args.iter().map(|a| a.as_ref()).collect();
+
+                if let (Escalation::Sudo, Some(auth)) = (&self.escalation, &self.sudo_auth) {
+                    return Ok(auth.command(user, command.as_ref(), &args));
+                }
+
+                let argv = self.escalation.wrap(user, command.as_ref(), &args)?;
+                let mut com = std::process::Command::new(&argv[0]);
+
+                com.args(&argv[1..]);
+                com
+            }
+            Some(
+                remote @ Context::Remote {
+                    host,
+                    config,
+                    proxy,
+                    settings,
+                },
+            ) => {
+                let mut com =
+                    self.ssh_command(config.as_deref(), proxy.as_ref(), settings.as_deref());
+
+                if let Some(option) = self.control_option(remote) {
+                    com.arg("-o").arg(option);
+                }
+
+                let parts =
+                    std::iter::once(command.as_ref()).chain(args.iter().map(|a| a.as_ref()));
+
+                // the remote shell splits and interprets the command line again
+                com.arg(host)
+                    .arg(match settings.as_ref().is_some_and(|s| s.windows_shell) {
+                        true => quote::join_windows_os(parts)?,
+                        false => quote::join_os(parts)?,
+                    });
+                com
+            }
+            Some(Context::Docker { container, user }) => {
+                let mut com = std::process::Command::new("docker");
+
+                // stdin is kept open, so that the command can be part of a pipeline
+                com.args(["exec", "-i"]);
+
+                if let Some(user) = user {
+                    com.arg("-u").arg(user);
+                }
+
+                com.arg(container).arg(command).args(args);
+                com
+            }
+            Some(Context::Wsl { distribution, user }) => {
+                let mut com = std::process::Command::new("wsl.exe");
+
+                if let Some(distribution) = distribution {
+                    com.arg("-d").arg(distribution);
+                }
+
+                if let Some(user) = user {
+                    com.arg("-u").arg(user);
+                }
+
+                com.arg("--").arg(command).args(args);
+                com
+            }
+            Some(Context::Kubernetes {
+                namespace,
+                pod,
+                container,
+                kubeconfig,
+            }) => {
+                let mut com = std::process::Command::new("kubectl");
+
+                if let Some(kubeconfig) = kubeconfig {
+                    com.arg("--kubeconfig").arg(kubeconfig);
+                }
+
+                // stdin is kept open, so that the command can be part of a pipeline
+                com.args(["exec", "-i"]);
+
+                if let Some(namespace) = namespace {
+                    com.arg("-n").arg(namespace);
+                }
+
+                com.arg(pod);
+
+                if let Some(container) = container {
+                    com.arg("-c").arg(container);
+                }
+
+                com.arg("--").arg(command).args(args);
+                com
+            }
+            Some(Context::Chain(contexts)) => {
+                let mut argv: Vec<std::ffi::OsString> = std::iter::once(command.as_ref())
+                    .chain(args.iter().map(|a| a.as_ref()))
+                    .map(|part| part.to_os_string())
+                    .collect();
+                let Some((outer, inner)) = contexts.split_first() else {
+                    return self.build_command(command, args, None);
+                };
+
+                // the innermost context is applied first
+                for context in inner.iter().rev() {
+                    argv = self.wrap_context(context, argv)?;
+                }
+
+                self.build_command(&argv[0], &argv[1..], Some(outer))?
+            }
+            Some(Context::Local { .. }) | None => {
+                let mut com = std::process::Command::new(command);
+
+                com.args(args);
+                com
+            }
+        })
+    }
+
+    /// Returns the command line running the command line in a context entered from another one
+    fn wrap_context(
+        &self,
+        context: &Context,
+        argv: Vec<std::ffi::OsString>,
+    ) -> Result<Vec<std::ffi::OsString>, ExecError> {
+        match context {
+            // the effective user of the calling process is irrelevant within another context
+            Context::Local { user } => {
+                let args: Vec<&std::ffi::OsStr> = argv[1..].iter().map(|a| a.as_os_str()).collect();
+
+                self.escalation.wrap(user, &argv[0], &args)
+            }
+            context => {
+                let com = self.build_command(&argv[0], &argv[1..], Some(context))?;
+
+                Ok(std::iter::once(com.get_program())
+                    .chain(com.get_args())
+                    .map(|part| part.to_os_string())
+                    .collect())
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn is_effective_user(user: &str) -> bool {
+        users::get_effective_username().is_some_and(|name| name == user)
+    }
+
+    #[cfg(windows)]
+    fn is_effective_user(user: &str) -> bool {
+        // user names are case-insensitive on Windows
+        std::env::var("USERNAME").is_ok_and(|name| name.eq_ignore_ascii_case(user))
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn is_effective_user(_user: &str) -> bool {
+        false
+    }
+
+    /// Checks whether the exit status is a success for the spec
+    pub(crate) fn accepts(&self, spec: Option<&CommandSpec>, code: i32) -> bool {
+        match spec.and_then(CommandSpec::get_accepted_codes) {
+            Some(codes) => codes.contains(&code),
+            None => self.exit_policy.accepts(code),
+        }
+    }
+
+    /// Returns stdout if the exit status is accepted for the spec; the error is classified otherwise
+    pub(crate) fn check_status(
+        &self,
+        spec: Option<&CommandSpec>,
+        output: std::process::Output,
+    ) -> Result<Vec<u8>, ExecError> {
+        if output
+            .status
+            .code()
+            .is_some_and(|code| code != 0 && self.accepts(spec, code))
+        {
+            return Ok(output.stdout);
+        }
+
+        CommandExec::check_output(output).map_err(|e| match spec {
+            Some(spec) => spec.classify(e),
+            None => e,
+        })
+    }
+
+    /// Returns stdout of a successful command; the buffers are moved, not copied
+    pub(crate) fn check_output(output: std::process::Output) -> Result<Vec<u8>, ExecError> {
+        match output.status.code() {
+            Some(code) => {
+                if code == 0 {
+                    Ok(output.stdout)
+                } else {
+                    match String::from_utf8(output.stderr) {
+                        Ok(s) => Err(ExecError::TerminationWithError(code, s)),
+                        Err(_) => Err(ExecError::TerminationWithErrorCode(code)),
+                    }
+                }
+            }
+            None => Err(ExecError::TerminationBySignal),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mockall", unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example() {
+        let mut mock = MockExec::new();
+
+        mock.expect_exec()
+            .once()
+            .returning(|_command, _args, _context| Ok("ok".to_string()));
+
+        let res = mock
+            .exec(
+                "",
+                &[""],
+                Some(&Context::Local {
+                    user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(res, "ok");
+    }
+
+    #[test]
+    fn run() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec(
+                "ls",
+                &["Cargo.toml"],
+                Some(&Context::Local {
+                    user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
+                })
+            )
+            .unwrap(),
+            "Cargo.toml\n"
+        );
+    }
+
+    #[test]
+    fn run_piped() {
+        let mut com = CommandExec::new();
+        let context = Context::Local {
+            user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
+        };
+
+        assert_eq!(
+            com.run_piped(&[
+                ("cat", &["Cargo.toml"], Some(&context)),
+                ("grep", &["name"], Some(&context)),
+            ])
+            .unwrap(),
+            "name = \"exec-rs\"\n"
+        );
+    }
+
+    #[test]
+    fn run_piped_mixed_context() {
+        let mut com = CommandExec::new();
+        let context = Context::Local {
+            user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
+        };
+
+        assert_eq!(
+            com.run_piped(&[
+                ("cat", &["Cargo.toml"], Some(&context)),
+                ("grep", &["name"], None),
+            ])
+            .unwrap(),
+            "name = \"exec-rs\"\n"
+        );
+    }
+
+    #[test]
+    fn run_piped_spawn_failure() {
+        let mut com = CommandExec::new();
+
+        assert!(matches!(
+            com.run_piped(&[
+                ("sleep", &["10"], None),
+                ("exec-rs-missing-command", &[], None),
+            ]),
+            Err(ExecError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn prepared_command() {
+        let mut com = CommandExec::new();
+        let prepared = PreparedCommand::new("echo").arg("-n").param("text");
+
+        assert_eq!(
+            prepared.exec(&mut com, &[("text", "a; b")], None).unwrap(),
+            "a; b"
+        );
+        assert!(matches!(
+            prepared.exec(&mut com, &[("text", "a\nb")], None),
+            Err(ExecError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            prepared.exec(&mut com, &[], None),
+            Err(ExecError::UnboundParameter(_))
+        ));
+    }
+
+    #[test]
+    fn quote_posix() {
+        assert_eq!(quote::quote("plain/path.txt"), "plain/path.txt");
+        assert_eq!(quote::quote("a b"), "'a b'");
+        assert_eq!(quote::quote("it's"), "'it'\\''s'");
+        assert_eq!(quote::quote(""), "''");
+    }
+
+    #[test]
+    fn remote_arguments_are_quoted() {
+        let com = CommandExec::new()
+            .build_command(
+                "grep",
+                &["foo;rm -rf /", "a b"],
+                Some(&Context::Remote {
+                    host: "host".to_string(),
+                    config: None,
+                    proxy: None,
+                    settings: None,
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            com.get_args().collect::<Vec<_>>(),
+            ["host", "grep 'foo;rm -rf /' 'a b'"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn remote_arguments_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let res = CommandExec::new().build_command(
+            std::ffi::OsStr::new("ls"),
+            &[std::ffi::OsStr::from_bytes(b"file\xff")],
+            Some(&Context::Remote {
+                host: "host".to_string(),
+                config: None,
+                proxy: None,
+                settings: None,
+            }),
+        );
+
+        assert!(matches!(res, Err(ExecError::NonUtf8Argument(_))));
+    }
+
+    #[test]
+    fn local_context_of_effective_user() {
+        let context = Context::Local {
+            user: String::from(users::get_effective_username().unwrap().to_str().unwrap()),
+        };
+        let com = CommandExec::new()
+            .build_command("ls", &[] as &[&str], Some(&context))
+            .unwrap();
+
+        assert_eq!(com.get_program(), "ls");
+
+        let com = CommandExec::new()
+            .force_escalation(true)
+            .build_command("ls", &[] as &[&str], Some(&context))
+            .unwrap();
+
+        assert_eq!(com.get_program(), "sudo");
+    }
+
+    #[test]
+    fn escalation_wrap() {
+        let wrap = |escalation: Escalation| {
+            escalation
+                .wrap("user", "ls".as_ref(), &["a b".as_ref()])
+                .unwrap()
+        };
+
+        assert_eq!(
+            wrap(Escalation::Sudo),
+            ["sudo", "-nu", "user", "--", "ls", "a b"]
+        );
+        assert_eq!(
+            wrap(Escalation::Doas),
+            ["doas", "-n", "-u", "user", "--", "ls", "a b"]
+        );
+        assert_eq!(wrap(Escalation::Su), ["su", "user", "-c", "ls 'a b'"]);
+        assert_eq!(
+            wrap(Escalation::Pkexec),
+            ["pkexec", "--user", "user", "ls", "a b"]
+        );
+        assert_eq!(
+            wrap(Escalation::Runas),
+            ["runas", "/user:user", "ls \"a b\""]
+        );
+        assert_eq!(
+            wrap(Escalation::PowerShell),
+            [
+                "powershell",
+                "-NoProfile",
+                "-Command",
+                "$process = Start-Process -FilePath 'ls' -Credential 'user' -Wait -NoNewWindow -PassThru -ArgumentList '\"a b\"'; exit $process.ExitCode"
+            ]
+        );
+    }
+
+    #[test]
+    fn quote_windows() {
+        assert_eq!(quote::quote_windows("plain"), "plain");
+        assert_eq!(quote::quote_windows("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(quote::quote_windows("dir\\ x\\"), "\"dir\\ x\\\\\"");
+    }
+
+    #[test]
+    fn pool() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = created.clone();
+        let pool = ExecPool::new(1, move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(CommandExec::new())
+        })
+        .unwrap();
+
+        {
+            let mut exec = pool.get().unwrap();
+
+            assert_eq!(exec.exec("echo", &["pooled"], None).unwrap(), "pooled\n");
+            assert!(pool.try_get().is_none());
+            exec.mark_unhealthy();
+        }
+
+        assert_eq!(pool.idle(), 0);
+        assert!(pool.get().is_ok());
+        assert_eq!(pool.idle(), 1);
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn spec_from_command() {
+        let mut command = std::process::Command::new("ls");
+
+        command.arg("-l").env("LC_ALL", "C").current_dir("/tmp");
+
+        let spec = CommandSpec::from(command).context(Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        });
+
+        assert_eq!(spec.get_program(), "ls");
+        assert_eq!(spec.get_args(), ["-l"]);
+        assert_eq!(spec.get_envs(), [("LC_ALL".into(), Some("C".into()))]);
+
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(command.get_program(), "ssh");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "host",
+                "env LC_ALL=C sh -c 'cd -- \"$1\" && shift && exec \"$@\"' sh /tmp ls -l"
+            ]
+        );
+        assert_eq!(command.get_current_dir(), None);
+    }
+
+    #[test]
+    fn exec_spec() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec_spec(
+                &CommandSpec::new("sh")
+                    .args(["-c", "echo $VALUE"])
+                    .env("VALUE", "spec")
+            )
+            .unwrap(),
+            "spec\n"
+        );
+    }
+
+    #[test]
+    fn spec_configure() {
+        let mut com = CommandExec::new();
+        let spec = CommandSpec::new("pwd").configure(|command| {
+            command.current_dir("/");
+        });
+
+        assert_eq!(com.exec_spec(&spec).unwrap(), "/\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spec_pre_exec() {
+        let mut com = CommandExec::new();
+        // SAFETY: the closures neither allocate nor lock
+        let ok = unsafe { CommandSpec::new("true").pre_exec(|| Ok(())) };
+        let failing = unsafe {
+            CommandSpec::new("true")
+                .pre_exec(|| Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied)))
+        };
+
+        assert!(com.exec_spec(&ok).is_ok());
+        assert!(matches!(com.exec_spec(&failing), Err(ExecError::Io(_))));
+    }
+
+    #[test]
+    fn exec_events() {
+        let mut com = CommandExec::new();
+        let events = com
+            .exec_events(
+                &CommandSpec::new("sh").args(["-c", "echo out; sleep 0.1; echo err >&2; exit 3"]),
+            )
+            .unwrap();
+
+        assert!(matches!(&events[0], OutputEvent::Stdout { data, .. } if data == b"out\n"));
+        assert!(matches!(&events[1], OutputEvent::Stderr { data, .. } if data == b"err\n"));
+        assert!(matches!(events[2], OutputEvent::Exit { code: Some(3), .. }));
+        assert!(events[0].elapsed() <= events[1].elapsed());
+
+        let start = std::time::Instant::now();
+
+        assert!(matches!(
+            CommandExec::new()
+                .timeout(std::time::Duration::from_millis(200))
+                .exec_events(&CommandSpec::new("sh").args(["-c", "echo started; sleep 5"])),
+            Err(ExecError::Timeout(_))
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn stream() {
+        let mut com = CommandExec::new();
+        let lines: Vec<_> = com
+            .stream(&CommandSpec::new("sh").args(["-c", "echo a; sleep 0.1; echo b >&2; exit 2"]))
+            .unwrap()
+            .collect();
+
+        assert!(
+            matches!(&lines[0], Ok(OutputLine { stream: Stream::Stdout, line, .. }) if line == "a")
+        );
+        assert!(
+            matches!(&lines[1], Ok(OutputLine { stream: Stream::Stderr, line, .. }) if line == "b")
+        );
+        assert!(matches!(
+            lines[2],
+            Err(ExecError::TerminationWithErrorCode(2))
+        ));
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn ansi() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31mred\x1b[0m \x1b]0;title\x07text\x1b(B"),
+            "red text"
+        );
+
+        let mut com = CommandExec::new().strip_ansi(true);
+
+        assert_eq!(
+            com.exec("printf", &["\x1b[32mok\x1b[0m"], None).unwrap(),
+            "ok"
+        );
+    }
+
+    #[test]
+    fn color_mode() {
+        let mut com = CommandExec::new().color(ColorMode::Never);
+
+        assert_eq!(
+            com.exec("sh", &["-c", "echo $NO_COLOR"], None).unwrap(),
+            "1\n"
+        );
+
+        let spec = CommandSpec::new("ls").context(Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        });
+        let command = CommandExec::new()
+            .color(ColorMode::Always)
+            .command(&spec)
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "env CLICOLOR_FORCE=1 FORCE_COLOR=1 ls"]
+        );
+    }
+
+    #[test]
+    fn exec_report() {
+        let mut com = CommandExec::new();
+        let report = com
+            .exec_report(&CommandSpec::new("sh").args(["-c", "echo out; echo err >&2"]))
+            .unwrap();
+
+        assert_eq!(report.argv, ["sh", "-c", "echo out; echo err >&2"]);
+        assert_eq!(report.context, "local");
+        assert_eq!(report.stdout, b"out\n");
+        assert_eq!(report.stderr, b"err\n");
+        assert_eq!(report.status, Some(0));
+        #[cfg(unix)]
+        assert!(report.rusage.is_some());
+
+        match com.exec_report(&CommandSpec::new("sh").args(["-c", "exit 4"])) {
+            Err(ExecError::Failed(report)) => assert_eq!(report.status, Some(4)),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let start = std::time::Instant::now();
+
+        assert!(matches!(
+            CommandExec::new()
+                .timeout(std::time::Duration::from_millis(200))
+                .exec_report(&CommandSpec::new("sh").args(["-c", "echo started; sleep 5"])),
+            Err(ExecError::Timeout(_))
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn fleet_report() {
+        let user = String::from(users::get_current_username().unwrap().to_str().unwrap());
+        let contexts = [
+            Context::Local { user: user.clone() },
+            Context::Local { user },
+        ];
+        let result = CommandExec::new().run_fleet(
+            &contexts,
+            &CommandSpec::new("sh").args(["-c", "echo \"bad \\\"thing\\\"\" >&2; exit 1"]),
+            2,
+        );
+
+        assert!(!result.success());
+        assert_eq!(result.hosts.len(), 2);
+        assert_eq!(result.hosts[0].status(), "exit 1");
+        assert!(result.render_table().starts_with("HOST"));
+        assert!(result.render_table().contains("bad \"thing\""));
+        assert!(result
+            .render_json()
+            .contains("\"status\":\"exit 1\",\"success\":false"));
+        assert!(result
+            .render_json()
+            .contains("\"error\":\"bad \\\"thing\\\"\""));
+    }
+
+    #[test]
+    fn fleet_junit() {
+        let user = String::from(users::get_current_username().unwrap().to_str().unwrap());
+        let contexts = [
+            Context::Local { user: user.clone() },
+            Context::Local { user },
+        ];
+        let result = CommandExec::new().run_fleet(
+            &contexts,
+            &CommandSpec::new("sh").args(["-c", "echo '<oops>' >&2; exit 2"]),
+            1,
+        );
+        let xml = result.render_junit("smoke & tests");
+
+        assert!(xml.contains(
+            "<testsuite name=\"smoke &amp; tests\" tests=\"2\" failures=\"2\" errors=\"0\""
+        ));
+        assert!(xml.contains("<failure message=\"command `sh -c"));
+        assert!(xml.contains("&lt;oops&gt;"));
+    }
+
+    #[test]
+    fn exec_replace_failure() {
+        let com = CommandExec::new();
+
+        assert!(matches!(
+            com.exec_replace(&CommandSpec::new("exec-rs-missing-command")),
+            ExecError::Io(_)
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn new_session() {
+        let mut com = CommandExec::new();
+        let script = "read -r pid comm state ppid pgrp sid rest < /proc/self/stat; echo $sid $$";
+        let ids = com
+            .exec_spec(
+                &CommandSpec::new("sh")
+                    .args(["-c", script])
+                    .new_session(true),
+            )
+            .unwrap();
+        let ids: Vec<&str> = ids.split_whitespace().collect();
+
+        assert_eq!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn launch_detached() {
+        let dir = std::env::temp_dir().join(format!("exec-rs-detach-{}", std::process::id()));
+        let detach = Detach {
+            stdout: Some(dir.join("out")),
+            stderr: Some(dir.join("err")),
+        };
+
+        std::fs::create_dir_all(&dir).unwrap();
+        CommandExec::new()
+            .launch_detached(
+                &CommandSpec::new("sh").args(["-c", "pwd; echo err >&2"]),
+                &detach,
+            )
+            .unwrap();
+
+        for _ in 0..100 {
+            if std::fs::read_to_string(dir.join("err")).unwrap_or_default() == "err\n" {
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        assert_eq!(std::fs::read_to_string(dir.join("out")).unwrap(), "/\n");
+        assert_eq!(std::fs::read_to_string(dir.join("err")).unwrap(), "err\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deterministic_exec() {
+        let mut com = DeterministicExec::new(CommandExec::new());
+
+        assert_eq!(
+            com.exec(
+                "sh",
+                &["-c", "echo $LC_ALL $TZ $COLUMNS ${LANG:-unset}"],
+                None
+            )
+            .unwrap(),
+            "C UTC 200 unset\n"
+        );
+        assert_eq!(
+            com.exec_piped(&[
+                ("echo", &["b\na"], None),
+                ("sh", &["-c", "sort; echo $TZ"], None)
+            ])
+            .unwrap(),
+            "a\nb\nUTC\n"
+        );
+    }
+
+    #[test]
+    fn retry_policy() {
+        let ms = std::time::Duration::from_millis;
+        let error = ExecError::Chaining;
+        let policy = RetryPolicy::new(5).backoff(ExponentialBackoff {
+            initial: ms(10),
+            max: ms(30),
+        });
+
+        assert_eq!(policy.delay(0, ms(0), &error), ms(10));
+        assert_eq!(policy.delay(1, ms(10), &error), ms(20));
+        assert_eq!(policy.delay(5, ms(30), &error), ms(30));
+
+        let jittered = policy
+            .clone()
+            .jitter(Jitter::Equal)
+            .delay(1, ms(10), &error);
+
+        assert!(jittered >= ms(10));
+        assert!(jittered <= ms(20));
+
+        let decorrelated = DecorrelatedBackoff {
+            base: ms(10),
+            max: ms(100),
+        }
+        .delay(1, ms(20), &error);
+
+        assert!(decorrelated >= ms(10));
+        assert!(decorrelated <= ms(60));
+
+        let custom = RetryPolicy::new(2).backoff(
+            move |attempt: u32, _: std::time::Duration, _: &ExecError| ms(attempt as u64 + 7),
+        );
+
+        assert_eq!(custom.delay(1, ms(0), &error), ms(8));
+
+        let mut com = CommandExec::new().retry_policy(policy.clone());
+
+        assert!(matches!(
+            com.exec_report(&CommandSpec::new("exec-rs-missing-command")),
+            Err(ExecError::Io(_))
+        ));
+
+        let mut com = CommandExec::new().retry_policy(policy.retryable(|_| true));
+
+        match com.exec_report(&CommandSpec::new("false")) {
+            Err(ExecError::Failed(report)) => assert_eq!(report.retries, 4),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut com = CommandExec::new().retry_policy(
+            RetryPolicy::new(5)
+                .backoff(FixedBackoff(ms(10)))
+                .budget(ms(25))
+                .retryable(|_| true),
+        );
+
+        match com.exec_report(&CommandSpec::new("false")) {
+            Err(ExecError::Failed(report)) => assert_eq!(report.retries, 2),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut com = CommandExec::new();
+        let per_call = RetryPolicy::new(3)
+            .backoff(FixedBackoff(ms(1)))
+            .retryable(|_| true);
+
+        match com.with_retry_policy(per_call, |exec| {
+            exec.exec_report(&CommandSpec::new("false"))
+        }) {
+            Err(ExecError::Failed(report)) => assert_eq!(report.retries, 2),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match com.exec_report(&CommandSpec::new("false")) {
+            Err(ExecError::Failed(report)) => assert_eq!(report.retries, 0),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn scheduling() {
+        let mut com = CommandExec::new();
+        let policy = com
+            .exec_spec(
+                &CommandSpec::new("chrt")
+                    .args(["-p", &std::process::id().to_string()])
+                    .scheduling(Scheduling::Idle),
+            )
+            .unwrap();
+
+        assert!(!policy.contains("SCHED_IDLE"));
+
+        let policy = com
+            .exec_spec(
+                &CommandSpec::new("sh")
+                    .args(["-c", "chrt -p $$"])
+                    .scheduling(Scheduling::Idle),
+            )
+            .unwrap();
+
+        assert!(policy.contains("SCHED_IDLE"));
+
+        let command = com
+            .command(
+                &CommandSpec::new("make")
+                    .scheduling(Scheduling::Fifo(10))
+                    .context(Context::Remote {
+                        host: "host".to_string(),
+                        config: None,
+                        proxy: None,
+                        settings: None,
+                    }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "chrt --fifo 10 make"]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn oom_score_adj() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec_spec(
+                &CommandSpec::new("cat")
+                    .arg("/proc/self/oom_score_adj")
+                    .oom_score_adj(500)
+            )
+            .unwrap(),
+            "500\n"
+        );
+
+        let command = com
+            .command(
+                &CommandSpec::new("make")
+                    .oom_score_adj(-100)
+                    .context(Context::Remote {
+                        host: "host".to_string(),
+                        config: None,
+                        proxy: None,
+                        settings: None,
+                    }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "choom -n -100 -- make"]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "requires privileges for creating mount namespaces"]
+    fn isolation() {
+        let mut com = CommandExec::new();
+        let spec = CommandSpec::new("sh")
+            .args([
+                "-c",
+                "touch /tmp/exec-rs-isolation && ls /tmp && ! touch /exec-rs-isolation",
+            ])
+            .isolation(Isolation {
+                private_tmp: true,
+                read_only_root: true,
+            });
+
+        assert_eq!(com.exec_spec(&spec).unwrap(), "exec-rs-isolation\n");
+        assert!(!std::path::Path::new("/tmp/exec-rs-isolation").exists());
+    }
+
+    #[test]
+    fn isolation_wrap() {
+        let isolation = Isolation {
+            private_tmp: true,
+            read_only_root: false,
+        };
+
+        assert_eq!(
+            isolation.wrap(vec!["make".into(), "test".into()]),
+            [
+                "unshare",
+                "--mount",
+                "--",
+                "sh",
+                "-c",
+                "set -e; mount -t tmpfs tmpfs /tmp; exec \"$@\"",
+                "sh",
+                "make",
+                "test"
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn user_namespace() {
+        let mut com = CommandExec::new().escalation(Escalation::UserNamespace);
+        let nobody = users::get_user_by_name("nobody").unwrap();
+
+        assert_eq!(
+            com.exec(
+                "id",
+                &["-u"],
+                Some(&Context::Local {
+                    user: "nobody".to_string()
+                })
+            )
+            .unwrap(),
+            format!("{}\n", nobody.uid())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn switch_user() {
+        if users::get_effective_uid() != 0 {
+            return;
+        }
+
+        let mut com = CommandExec::new().switch_user(true);
+        let nobody = users::get_user_by_name("nobody").unwrap();
+        let context = Context::Local {
+            user: "nobody".to_string(),
+        };
+
+        assert_eq!(
+            com.exec("sh", &["-c", "id -u; id -g; echo $USER"], Some(&context))
+                .unwrap(),
+            format!("{}\n{}\nnobody\n", nobody.uid(), nobody.primary_group_id())
+        );
+        assert!(matches!(
+            com.exec(
+                "id",
+                &[],
+                Some(&Context::Local {
+                    user: "exec-rs-missing".to_string()
+                })
+            ),
+            Err(ExecError::UnknownUser(user)) if user == "exec-rs-missing"
+        ));
+    }
+
+    #[test]
+    fn tmux_session() {
+        let mut com = CommandExec::new();
+        let session = TmuxSession::new("exec-rs-test", None)
+            .socket(&format!("exec-rs-test-{}", std::process::id()));
+
+        assert_eq!(session.state(&mut com).unwrap(), SessionState::Missing);
+        session
+            .start(&mut com, "sh", &["-c", "echo 'hello tmux'; exit 3"])
+            .unwrap();
+
+        let mut state = SessionState::Running;
+
+        for _ in 0..100 {
+            state = session.state(&mut com).unwrap();
+
+            if state != SessionState::Running {
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        assert_eq!(state, SessionState::Exited(Some(3)));
+        assert!(session.capture(&mut com).unwrap().contains("hello tmux"));
+        session.kill(&mut com).unwrap();
+        assert_eq!(session.state(&mut com).unwrap(), SessionState::Missing);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn interaction() {
+        let mut com = CommandExec::new();
+        let spec = CommandSpec::new("sh").args([
+            "-c",
+            "[ -t 0 ] && printf 'Name? ' && read -r name && echo \"hello $name\"",
+        ]);
+        let output = com
+            .exec_interaction(
+                &spec,
+                &Interaction::new().expect(r"Name\? $", "world\n").unwrap(),
+            )
+            .unwrap();
+
+        assert!(output.contains("hello world"));
+        assert!(matches!(
+            com.exec_interaction(
+                &CommandSpec::new("sleep").arg("10"),
+                &Interaction::new()
+                    .expect_within("never", "", std::time::Duration::from_millis(100))
+                    .unwrap()
+            ),
+            Err(ExecError::ExpectTimeout(_))
+        ));
+        assert!(matches!(
+            com.exec_interaction(
+                &CommandSpec::new("true"),
+                &Interaction::new().expect("never", "").unwrap()
+            ),
+            Err(ExecError::ExpectEof(_))
+        ));
+        assert!(matches!(
+            Interaction::new().expect("(", ""),
+            Err(ExecError::InvalidPattern(_))
+        ));
+    }
+
+    #[test]
+    fn shell_session() {
+        let mut session = ShellSession::start(&mut CommandExec::new(), "sh", None).unwrap();
+
+        assert_eq!(session.run("cd / && export VALUE=kept").unwrap(), "");
+        assert_eq!(session.run("pwd; printf $VALUE").unwrap(), "/\nkept");
+        assert_eq!(session.exec("echo", &["a b"]).unwrap(), "a b\n");
+        assert!(matches!(
+            session.run("echo out; echo err >&2; false"),
+            Err(ExecError::TerminationWithError(1, stderr)) if stderr == "err\n"
+        ));
+        assert!(matches!(
+            session.run("exit 2"),
+            Err(ExecError::SessionTerminated)
+        ));
+        assert!(matches!(
+            session.run("true"),
+            Err(ExecError::SessionTerminated)
+        ));
+    }
+
+    #[test]
+    fn redirect_output() {
+        let dir = std::env::temp_dir().join(format!("exec-rs-redirect-{}", std::process::id()));
+        let mut com = CommandExec::new();
+
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let spec = CommandSpec::new("sh")
+            .args(["-c", "echo out; echo err >&2"])
+            .stdout_to(dir.join("out"), true)
+            .stderr_to(dir.join("err"), false);
+
+        assert_eq!(com.exec_spec(&spec).unwrap(), "");
+        assert!(com.exec_report(&spec).unwrap().stdout.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out")).unwrap(),
+            "out\nout\n"
+        );
+        assert_eq!(std::fs::read_to_string(dir.join("err")).unwrap(), "err\n");
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let command = com
+            .command(
+                &CommandSpec::new("make")
+                    .stdout_to("/var/log/make log", true)
+                    .stderr_to("err", false)
+                    .context(Context::Remote {
+                        host: "host".to_string(),
+                        config: None,
+                        proxy: None,
+                        settings: None,
+                    }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "host",
+                "sh -c 'exec \"$@\" 1>> '\\''/var/log/make log'\\'' 2> err' sh make"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifo() {
+        let dir = std::env::temp_dir().join(format!("exec-rs-fifo-{}", std::process::id()));
+        let fifo = dir.join("pipe");
+
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let reader = {
+            let fifo = fifo.clone();
+
+            std::thread::spawn(move || {
+                while !fifo.exists() {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+
+                std::fs::read_to_string(fifo).unwrap()
+            })
+        };
+
+        assert_eq!(
+            CommandExec::new()
+                .exec_specs_piped(&[
+                    CommandSpec::new("echo").arg("through fifo"),
+                    CommandSpec::new("cat").stdout_to_fifo(&fifo),
+                ])
+                .unwrap(),
+            ""
+        );
+        assert_eq!(reader.join().unwrap(), "through fifo\n");
+        assert!(!fifo.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn stdin_from() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec_specs_piped(&[
+                CommandSpec::new("echo").arg("discarded"),
+                CommandSpec::new("grep")
+                    .arg("name")
+                    .stdin_from("Cargo.toml"),
+            ])
+            .unwrap(),
+            "name = \"exec-rs\"\n"
+        );
+
+        let command = com
+            .command(
+                &CommandSpec::new("psql")
+                    .stdin_from("dump file.sql")
+                    .context(Context::Remote {
+                        host: "host".to_string(),
+                        config: None,
+                        proxy: None,
+                        settings: None,
+                    }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "host",
+                "sh -c 'exec \"$@\" < '\\''dump file.sql'\\''' sh psql"
+            ]
+        );
+    }
+
+    #[test]
+    fn task_graph() {
+        let mut graph = TaskGraph::new();
+        let a = graph.add("a", CommandSpec::new("echo").arg("a"), &[]);
+        let b = graph.add("b", CommandSpec::new("false"), &[a]);
+        let c = graph.add_pipeline(
+            "c",
+            vec![
+                CommandSpec::new("echo").arg("c"),
+                CommandSpec::new("tr").args(["c", "C"]),
+            ],
+            &[a],
+        );
+        let d = graph.add("d", CommandSpec::new("true"), &[b, c]);
+        let e = graph.add("e", CommandSpec::new("true"), &[d]);
+        let result = graph.run(&CommandExec::new(), 4);
+
+        assert!(!result.success());
+        assert!(
+            matches!(&result.get(a).unwrap().outcome, TaskOutcome::Succeeded(out) if out == "a\n")
+        );
+        assert!(matches!(
+            result.get(b).unwrap().outcome,
+            TaskOutcome::Failed(_)
+        ));
+        assert!(
+            matches!(&result.get(c).unwrap().outcome, TaskOutcome::Succeeded(out) if out == "C\n")
+        );
+        assert!(matches!(
+            result.get(d).unwrap().outcome,
+            TaskOutcome::Skipped
+        ));
+        assert!(matches!(
+            result.get(e).unwrap().outcome,
+            TaskOutcome::Skipped
+        ));
+        assert_eq!(result.get(e).unwrap().name, "e");
+        assert!(result.get(e).unwrap().duration.is_none());
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut com = CommandExec::new();
+        let local = com.snapshot(None).unwrap();
+
+        assert_eq!(
+            local.user,
+            users::get_effective_username().unwrap().to_str().unwrap()
+        );
+        assert_eq!(
+            local.cwd,
+            std::env::current_dir().unwrap().to_str().unwrap()
+        );
+        assert!(!local.limits.is_empty());
+        assert_eq!(local.env.get("PATH"), std::env::var("PATH").ok().as_ref());
+
+        let mut other = local.clone();
+
+        other.cwd = "/".to_string();
+        other.env.remove("PATH");
+        other.env.insert("EXEC_RS".to_string(), "a\nb".to_string());
+
+        assert_eq!(
+            local.diff(&other),
+            [
+                Difference {
+                    key: "cwd".to_string(),
+                    left: Some(local.cwd.clone()),
+                    right: Some("/".to_string()),
+                },
+                Difference {
+                    key: "env.EXEC_RS".to_string(),
+                    left: None,
+                    right: Some("a\nb".to_string()),
+                },
+                Difference {
+                    key: "env.PATH".to_string(),
+                    left: local.env.get("PATH").cloned(),
+                    right: None,
+                },
+            ]
+        );
+    }
+    #[test]
+    fn progress() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut com = CommandExec::new().progress(move |event| {
+            let _ = tx.send(event.clone());
+        });
+
+        com.exec_piped(&[("echo", &["abc"], None), ("cat", &[], None)])
+            .unwrap();
+
+        let events: Vec<ProgressEvent> = rx.try_iter().collect();
+
+        assert_eq!(events[0], ProgressEvent::BatchStarted { commands: 2 });
+        assert_eq!(events[1], ProgressEvent::CommandStarted { index: 0 });
+        assert_eq!(events[2], ProgressEvent::CommandStarted { index: 1 });
+        assert_eq!(
+            events[3],
+            ProgressEvent::StageFinished {
+                index: 0,
+                bytes: None,
+                status: Some(0)
+            }
+        );
+        assert_eq!(
+            events[4],
+            ProgressEvent::StageFinished {
+                index: 1,
+                bytes: Some(4),
+                status: Some(0)
+            }
+        );
+        assert!(matches!(
+            events[5],
+            ProgressEvent::BatchFinished {
+                summary: BatchSummary {
+                    commands: 2,
+                    failed: 0,
+                    ..
+                }
+            }
+        ));
+
+        com.run_fleet(
+            &[Context::Local {
+                user: String::from(users::get_current_username().unwrap().to_str().unwrap()),
+            }],
+            &CommandSpec::new("false"),
+            1,
+        );
+
+        let events: Vec<ProgressEvent> = rx.try_iter().collect();
+
+        assert_eq!(events.len(), 4);
+        assert!(matches!(
+            events[3],
+            ProgressEvent::BatchFinished {
+                summary: BatchSummary {
+                    commands: 1,
+                    failed: 1,
+                    ..
+                }
+            }
+        ));
+    }
+    #[test]
+    fn strict_stderr() {
+        let mut com = CommandExec::new().strict_stderr(true);
+
+        assert_eq!(com.exec("echo", &["quiet"], None).unwrap(), "quiet\n");
+        assert!(matches!(
+            com.exec("sh", &["-c", "echo out; echo complaint >&2"], None),
+            Err(ExecError::Stderr(stderr)) if stderr == "complaint\n"
+        ));
+        assert!(matches!(
+            com.exec_piped(&[
+                ("sh", &["-c", "echo early >&2; echo out"], None),
+                ("cat", &[], None)
+            ]),
+            Err(ExecError::Stderr(stderr)) if stderr == "early\n"
+        ));
+        assert!(matches!(
+            com.exec("sh", &["-c", "echo broken >&2; exit 2"], None),
+            Err(ExecError::TerminationWithError(2, stderr)) if stderr == "broken\n"
+        ));
+    }
+    #[test]
+    fn stderr_warnings() {
+        let spec = CommandSpec::new("sh").args([
+            "-c",
+            "echo 'warning: deprecated' >&2; echo; echo 'notice: slow' >&2",
+        ]);
+
+        assert!(CommandExec::new()
+            .exec_report(&spec)
+            .unwrap()
+            .warnings
+            .is_empty());
+        assert_eq!(
+            CommandExec::new()
+                .stderr_warnings(true)
+                .exec_report(&spec)
+                .unwrap()
+                .warnings,
+            ["warning: deprecated", "notice: slow"]
+        );
+    }
+
+    #[test]
+    fn port_forward() {
+        let context = Context::Remote {
+            host: "host".to_string(),
+            config: Some("ssh_config".to_string()),
+            proxy: None,
+            settings: None,
+        };
+        let command = forward::PortForward::command(
+            &CommandExec::new(),
+            &context,
+            &[
+                Forward::Local {
+                    bind_port: 15432,
+                    host: "localhost".to_string(),
+                    port: 5432,
+                },
+                Forward::Remote {
+                    bind_port: 8080,
+                    host: "127.0.0.1".to_string(),
+                    port: 80,
+                },
+            ],
+            std::path::Path::new("/tmp/forward.sock"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-F",
+                "ssh_config",
+                "-f",
+                "-N",
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-o",
+                "ControlMaster=yes",
+                "-o",
+                "ControlPath=/tmp/forward.sock",
+                "-L",
+                "15432:localhost:5432",
+                "-R",
+                "8080:127.0.0.1:80",
+                "host"
+            ]
+        );
+        assert!(matches!(
+            CommandExec::new().with_forwards(
+                &Context::Local {
+                    user: "root".to_string()
+                },
+                &[],
+                |_| Ok(())
+            ),
+            Err(ExecError::Execution(_))
+        ));
+    }
+    #[test]
+    fn remote_builder() {
+        let spec = CommandSpec::new("ls").context(
+            Context::remote("host")
+                .user("admin")
+                .port(2222)
+                .identity_file("/keys/id_ed25519")
+                .option("ServerAliveInterval", "30")
+                .build(),
+        );
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-o",
+                "User=admin",
+                "-o",
+                "Port=2222",
+                "-o",
+                "IdentityFile=/keys/id_ed25519",
+                "-o",
+                "ServerAliveInterval=30",
+                "host",
+                "ls"
+            ]
+        );
+    }
+
+    #[test]
+    fn proxy() {
+        let spec = CommandSpec::new("ls").context(Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: Some(ProxyConfig::Socks5 {
+                host: "proxy".to_string(),
+                port: 1080,
+            }),
+            settings: None,
+        });
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-o",
+                "ProxyCommand=nc -X 5 -x proxy:1080 %h %p",
+                "host",
+                "ls"
+            ]
+        );
+        assert_eq!(
+            ProxyConfig::Command("corkscrew proxy 3128 %h %p".to_string()).command(),
+            "corkscrew proxy 3128 %h %p"
+        );
+
+        let jump = |proxy: Option<ProxyConfig>| {
+            let remote = Context::remote("host").jump_hosts(["bastion", "admin@inner:2222"]);
+            let remote = match proxy {
+                Some(proxy) => remote.config("conf.d/100%").proxy(proxy),
+                None => remote,
+            };
+            let spec = CommandSpec::new("ls").context(remote.build());
+            let command = CommandExec::new().command(&spec).unwrap();
+
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(jump(None), ["-J", "bastion,admin@inner:2222", "host", "ls"]);
+        assert_eq!(
+            Context::from(Context::remote("host")),
+            Context::Remote {
+                host: "host".to_string(),
+                config: None,
+                proxy: None,
+                settings: None,
+            }
+        );
+        assert_eq!(
+            jump(Some(ProxyConfig::Socks5 {
+                host: "proxy".to_string(),
+                port: 1080,
+            })),
+            [
+                "-F",
+                "conf.d/100%",
+                "-o",
+                concat!(
+                    "ProxyCommand=ssh -F conf.d/100%% -o 'ProxyCommand=ssh -F conf.d/100%%%% ",
+                    "-o '\\''ProxyCommand=nc -X 5 -x proxy:1080 %%%%h %%%%p'\\'' -W %%h:%%p ",
+                    "ssh://bastion' -W %h:%p ssh://admin@inner:2222"
+                ),
+                "host",
+                "ls"
+            ]
+        );
+    }
+    #[test]
+    fn known_hosts() {
+        let path = std::env::temp_dir().join(format!("exec-rs-known-hosts-{}", std::process::id()));
+        let known_hosts = KnownHosts::new(&path);
+        let key = |host: &str, key: &str| HostKey {
+            host: host.to_string(),
+            key_type: "ssh-ed25519".to_string(),
+            key: key.to_string(),
+        };
+
+        assert!(known_hosts.keys().unwrap().is_empty());
+        known_hosts
+            .add(&[key("a", "AAAA1"), key("b", "AAAA2"), key("a", "AAAA1")])
+            .unwrap();
+        known_hosts.pin("a", &[key("a", "AAAA3")]).unwrap();
+        assert_eq!(
+            known_hosts.keys().unwrap(),
+            [key("b", "AAAA2"), key("a", "AAAA3")]
+        );
+        assert_eq!(HostKey::parse("# comment"), None);
+        assert_eq!(
+            HostKey::parse("[h]:2222 ssh-ed25519 AAAA4 comment"),
+            Some(key("[h]:2222", "AAAA4"))
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let spec = CommandSpec::new("ls").context(Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        });
+        let command = CommandExec::new()
+            .known_hosts(known_hosts)
+            .command(&spec)
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-o",
+                &format!("UserKnownHostsFile={}", path.to_str().unwrap()),
+                "-o",
+                "StrictHostKeyChecking=yes",
+                "host",
+                "ls"
+            ]
+        );
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                255,
+                "@@@ WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED! @@@".to_string()
+            )),
+            ExecError::HostKeyMismatch(host) if host == "host"
+        ));
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                255,
+                "No ED25519 host key is known for host and you have requested strict checking."
+                    .to_string()
+            )),
+            ExecError::HostKeyUnknown(_)
+        ));
+    }
+    #[test]
+    fn ssh_settings() {
+        let spec = CommandSpec::new("ls").context(Context::Remote {
+            host: "db".to_string(),
+            config: None,
+            proxy: None,
+            settings: Some(Box::new(SshSettings {
+                host_name: Some("10.0.0.5".to_string()),
+                user: Some("admin".to_string()),
+                port: Some(2222),
+                identity_file: Some("id_ed25519".into()),
+                ciphers: vec!["aes256-gcm@openssh.com".to_string()],
+                forwards: vec![Forward::Local {
+                    bind_port: 15432,
+                    host: "localhost".to_string(),
+                    port: 5432,
+                }],
+                jump_hosts: Vec::new(),
+                options: vec![("ServerAliveInterval".to_string(), "30".to_string())],
+                windows_shell: false,
+            })),
+        });
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-o",
+                "HostName=10.0.0.5",
+                "-o",
+                "User=admin",
+                "-o",
+                "Port=2222",
+                "-o",
+                "IdentityFile=id_ed25519",
+                "-o",
+                "Ciphers=aes256-gcm@openssh.com",
+                "-o",
+                "LocalForward=15432 localhost:5432",
+                "-o",
+                "ServerAliveInterval=30",
+                "db",
+                "ls"
+            ]
+        );
+
+        let spec = CommandSpec::new("dir")
+            .arg("C:\\Program Files")
+            .context(Context::Remote {
+                host: "win".to_string(),
+                config: None,
+                proxy: None,
+                settings: Some(Box::new(SshSettings {
+                    windows_shell: true,
+                    ..SshSettings::default()
+                })),
+            });
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["win", "dir \"C:\\Program Files\""]
+        );
+    }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn platform() {
+        let mut com = CommandExec::new();
+        let platform = com.detect_platform(None).unwrap();
+
+        assert_eq!(platform.os, OsFamily::Linux);
+        assert_eq!(platform.arch, std::env::consts::ARCH);
+        assert_eq!(platform.gnu_tool("stat"), "stat");
+        assert_eq!(com.detect_platform(None).unwrap(), platform);
+
+        let mut com = com.resolver(|platform, spec| match platform.os {
+            OsFamily::Linux => CommandSpec::new("echo").arg("linux"),
+            _ => spec,
+        });
+
+        assert_eq!(com.exec("echo", &["other"], None).unwrap(), "linux\n");
+        assert_eq!(
+            Platform {
+                os: OsFamily::MacOs,
+                arch: "arm64".to_string(),
+                shell: "sh".to_string()
+            }
+            .gnu_tool("stat"),
+            "gstat"
+        );
+    }
+
+    #[test]
+    fn transport_error() {
+        let spec = CommandSpec::new("ls").context(Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        });
+
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                255,
+                "ssh: connect to host host port 22: Connection refused\n".to_string()
+            )),
+            ExecError::Transport(host, message)
+                if host == "host" && message == "ssh: connect to host host port 22: Connection refused"
+        ));
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                255,
+                "no such file\n".to_string()
+            )),
+            ExecError::TerminationWithError(255, _)
+        ));
+        assert!(matches!(
+            CommandSpec::new("ls").classify(ExecError::TerminationWithError(
+                255,
+                "Connection reset by peer\n".to_string()
+            )),
+            ExecError::TerminationWithError(255, _)
+        ));
+    }
+
+    #[test]
+    fn exec_status_only() {
+        let mut com = CommandExec::new();
+        let status = com
+            .exec_status_only(&CommandSpec::new("sh").args(["-c", "yes | head -c 1000000; exit 3"]))
+            .unwrap();
+
+        assert_eq!(status.status, Some(3));
+        assert!(!status.success());
+        assert!(com
+            .exec_status_only(&CommandSpec::new("true"))
+            .unwrap()
+            .success());
+
+        let start = std::time::Instant::now();
+        let mut com = CommandExec::new().timeout(std::time::Duration::from_millis(100));
+
+        assert!(matches!(
+            com.exec_status_only(&CommandSpec::new("sleep").arg("5")),
+            Err(ExecError::Timeout(_))
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_exec() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut com = AsyncCommandExec::new();
+
+        runtime.block_on(async {
+            assert_eq!(com.exec("echo", &["hello"], None).await.unwrap(), "hello\n");
+            assert_eq!(
+                com.exec_piped(&[("cat", &["Cargo.toml"], None), ("grep", &["^name"], None)])
+                    .await
+                    .unwrap(),
+                "name = \"exec-rs\"\n"
+            );
+            assert!(matches!(
+                com.exec("sh", &["-c", "exit 2"], None).await,
+                Err(ExecError::TerminationWithError(2, _))
+            ));
+        });
+    }
+
+    #[test]
+    fn exec_stream() {
+        let mut com = CommandExec::new();
+        let mut lines = com
+            .exec_stream("sh", &["-c", "echo first; sleep 0.2; echo second"], None)
+            .unwrap();
+        let start = std::time::Instant::now();
+
+        assert_eq!(lines.next().unwrap().unwrap(), "first");
+        assert!(start.elapsed() < std::time::Duration::from_millis(200));
+        assert_eq!(lines.next().unwrap().unwrap(), "second");
+        assert!(lines.next().is_none());
+
+        let lines: Vec<_> = com
+            .exec_stream_piped(&[
+                ("printf", &["a\\nb\\nc\\n"], None),
+                ("grep", &["-v", "b"], None),
+            ])
+            .unwrap()
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].as_ref().unwrap(), "c");
+        assert!(matches!(
+            com.exec_stream("sh", &["-c", "exit 4"], None)
+                .unwrap()
+                .last(),
+            Some(Err(ExecError::TerminationWithErrorCode(4)))
+        ));
+    }
+
+    #[test]
+    fn exec_full() {
+        let mut com = CommandExec::new();
+        let output = com
+            .exec_full("sh", &["-c", "echo out; echo warning >&2"], None)
+            .unwrap();
+
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "warning\n");
+        assert!(output.success());
+
+        let output = com
+            .exec_piped_full(&[
+                ("sh", &["-c", "echo first >&2; echo data"], None),
+                ("sh", &["-c", "cat; echo second >&2; exit 3"], None),
+            ])
+            .unwrap();
+
+        assert_eq!(output.stdout, "data\n");
+        assert_eq!(output.stderr, "first\nsecond\n");
+        assert_eq!(output.status, 3);
+    }
+
+    #[test]
+    fn exec_bytes() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec_bytes("printf", &["\\377\\000\\001"], None)
+                .unwrap(),
+            [0xff, 0x00, 0x01]
+        );
+        assert_eq!(
+            com.exec_piped_bytes(&[
+                ("printf", &["\\377abc"], None),
+                ("tail", &["-c", "2"], None)
+            ])
+            .unwrap(),
+            b"bc"
+        );
+        assert!(matches!(
+            com.exec("printf", &["\\377"], None),
+            Err(ExecError::Utf8(_))
+        ));
+    }
+
+    #[test]
+    fn pipeline() {
+        let mut com = CommandExec::new();
+        let mut pipeline = Pipeline::new().pipe(Cmd::new("printf").arg("b\\na\\nc\\n"));
+
+        pipeline.push(Cmd::new("sort").env("LC_ALL", "C"));
+        assert_eq!(pipeline.len(), 2);
+        assert_eq!(com.run(&pipeline).unwrap(), "a\nb\nc\n");
+
+        let mut deterministic = DeterministicExec::new(CommandExec::new());
+
+        assert_eq!(
+            deterministic
+                .run(&Pipeline::from(Cmd::new("sh").args(["-c", "echo $LC_ALL"])))
+                .unwrap(),
+            "C\n"
+        );
+    }
+
+    #[test]
+    fn input() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec_with_input("tr", &["a-z", "A-Z"], None, b"hello")
+                .unwrap(),
+            "HELLO"
+        );
+        assert_eq!(
+            com.run(
+                &Pipeline::new()
+                    .pipe(Cmd::new("sort"))
+                    .pipe(Cmd::new("head").args(["-n", "1"]))
+                    .input("b\na\n")
+            )
+            .unwrap(),
+            "a\n"
+        );
+
+        // more input than fits into the pipe, not read completely by the command
+        let input = vec![b'x'; 1 << 20];
+
+        assert_eq!(
+            com.exec_piped_from(&[("head", &["-c", "3"], None)], input.as_slice())
+                .unwrap(),
+            "xxx"
+        );
+    }
+
+    #[test]
+    fn env_in_context() {
+        let spec = CommandSpec::new("make")
+            .env("LC_ALL", "C")
+            .env_remove("MAKEFLAGS")
+            .env("LC_ALL", "C.UTF-8")
+            .context(Context::Remote {
+                host: "host".to_string(),
+                config: None,
+                proxy: None,
+                settings: None,
+            });
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "env -u MAKEFLAGS LC_ALL=C.UTF-8 make"]
+        );
+        assert_eq!(command.get_envs().count(), 0);
+
+        let user = users::get_effective_username().unwrap();
+        let spec = CommandSpec::new("sh")
+            .args(["-c", "echo $VALUE"])
+            .env("VALUE", "set")
+            .context(Context::Local {
+                user: user.to_str().unwrap().to_string(),
+            });
+        let command = CommandExec::new()
+            .escalation(Escalation::Su)
+            .force_escalation(true)
+            .command(&spec)
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                user.to_str().unwrap(),
+                "-c",
+                "env VALUE=set sh -c 'echo $VALUE'"
+            ]
+        );
+    }
+
+    #[test]
+    fn current_dir_in_context() {
+        let user = users::get_effective_username().unwrap();
+        let spec = CommandSpec::new("pwd")
+            .current_dir("/tmp")
+            .context(Context::Local {
+                user: user.to_str().unwrap().to_string(),
+            });
+        let command = CommandExec::new()
+            .escalation(Escalation::Su)
+            .force_escalation(true)
+            .command(&spec)
+            .unwrap();
+
+        assert_eq!(command.get_current_dir(), None);
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                user.to_str().unwrap(),
+                "-c",
+                "sh -c 'cd -- \"$1\" && shift && exec \"$@\"' sh /tmp pwd"
+            ]
+        );
+        assert_eq!(
+            CommandExec::new()
+                .exec_spec(&CommandSpec::new("pwd").current_dir("/tmp"))
+                .unwrap(),
+            "/tmp\n"
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let mut com = CommandExec::new().timeout(std::time::Duration::from_millis(200));
+        let start = std::time::Instant::now();
+
+        assert!(matches!(
+            com.exec_piped(&[("sleep", &["10"], None), ("cat", &[], None)]),
+            Err(ExecError::Timeout(timeout)) if timeout == std::time::Duration::from_millis(200)
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(com.exec("echo", &["fast"], None).unwrap(), "fast\n");
+    }
+
+    #[test]
+    fn docker_context() {
+        let context = Context::Docker {
+            container: "web".to_string(),
+            user: Some("www-data".to_string()),
+        };
+        let spec = CommandSpec::new("ls")
+            .arg("-l")
+            .current_dir("/srv")
+            .stdout_to("/tmp/out", false)
+            .context(context.clone());
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(context.to_string(), "docker container web");
+        assert_eq!(command.get_program(), "docker");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "exec",
+                "-i",
+                "-u",
+                "www-data",
+                "web",
+                "sh",
+                "-c",
+                "cd -- \"$1\" && shift && exec \"$@\"",
+                "sh",
+                "/srv",
+                "sh",
+                "-c",
+                "exec \"$@\" 1> /tmp/out",
+                "sh",
+                "ls",
+                "-l"
+            ]
+        );
+    }
+
+    #[test]
+    fn kubernetes_context() {
+        let context = Context::Kubernetes {
+            namespace: Some("prod".to_string()),
+            pod: "web-0".to_string(),
+            container: Some("app".to_string()),
+            kubeconfig: Some("/etc/kube/config".to_string()),
+        };
+        let spec = CommandSpec::new("ls").arg("-l").context(context.clone());
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(context.to_string(), "kubernetes pod prod/web-0");
+        assert_eq!(command.get_program(), "kubectl");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "--kubeconfig",
+                "/etc/kube/config",
+                "exec",
+                "-i",
+                "-n",
+                "prod",
+                "web-0",
+                "-c",
+                "app",
+                "--",
+                "ls",
+                "-l"
+            ]
+        );
+    }
+
+    #[test]
+    fn context_chain() {
+        let remote = Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        };
+        let context = Context::Chain(vec![
+            remote.clone(),
+            Context::Local {
+                user: "root".to_string(),
+            },
+        ]);
+        let spec = CommandSpec::new("ls")
+            .arg("my dir")
+            .context(context.clone());
+        let command = CommandExec::new()
+            .escalation(Escalation::Sudo)
+            .command(&spec)
+            .unwrap();
+
+        assert_eq!(context.to_string(), "remote on host > local as root");
+        assert_eq!(command.get_program(), "ssh");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "sudo -nu root -- ls 'my dir'"]
+        );
+
+        let spec = CommandSpec::new("ls").context(Context::Chain(vec![
+            remote,
+            Context::Docker {
+                container: "web".to_string(),
+                user: None,
+            },
+        ]));
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "docker exec -i web ls"]
+        );
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                255,
+                "Connection closed by 10.0.0.1 port 22\n".to_string()
+            )),
+            ExecError::Transport(host, _) if host == "host"
+        ));
+    }
+
+    #[test]
+    fn connection_reuse() {
+        let context = Context::Remote {
+            host: "host".to_string(),
+            config: Some("ssh_config".to_string()),
+            proxy: None,
+            settings: None,
+        };
+        let exec = CommandExec::new();
+        let command = exec
+            .master_command(&context, std::path::Path::new("/tmp/host.sock"))
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-F",
+                "ssh_config",
+                "-N",
+                "-o",
+                "ControlMaster=yes",
+                "-o",
+                "ControlPath=/tmp/host.sock",
+                "host"
+            ]
+        );
+        assert!(!exec.is_connected(&context));
+        assert!(exec.control_option(&context).is_none());
+
+        let dir = multiplex::socket_dir().unwrap();
+
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(
+                &std::fs::metadata(&dir).unwrap().permissions()
+            ) & 0o777,
+            0o700
+        );
+        let other = multiplex::socket_dir().unwrap();
+
+        assert_ne!(other, dir);
+        std::fs::remove_dir(dir).unwrap();
+        std::fs::remove_dir(other).unwrap();
+        assert!(matches!(
+            exec.connect(&Context::Local {
+                user: "root".to_string()
+            }),
+            Err(ExecError::Execution(_))
+        ));
+        exec.disconnect(&context);
+    }
+
+    #[cfg(feature = "ssh2")]
+    #[test]
+    fn native_ssh() {
+        let mut exec = SshExec::new().connect_timeout(std::time::Duration::from_secs(2));
+
+        assert_eq!(
+            exec.exec_piped(&[("echo", &["test"], None), ("tr", &["t", "T"], None)])
+                .unwrap(),
+            "TesT\n"
+        );
+
+        let unreachable = Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: Some(Box::new(SshSettings {
+                host_name: Some("127.0.0.1".to_string()),
+                port: Some(1),
+                ..SshSettings::default()
+            })),
+        };
+
+        assert!(matches!(
+            exec.exec("ls", &[], Some(&unreachable)),
+            Err(ExecError::Transport(host, _)) if host == "host"
+        ));
+        assert!(matches!(
+            CommandSpec::new("ls").context(unreachable).classify(
+                ExecError::TerminationWithError(
+                    255,
+                    "user@host: Permission denied (publickey).\n".to_string()
+                )
+            ),
+            ExecError::Authentication(host) if host == "host"
+        ));
+
+        let settings = |user: &str| SshSettings {
+            user: Some(user.to_string()),
+            ..SshSettings::default()
+        };
+
+        assert_ne!(
+            native_ssh::session_key("host", Some(&settings("a"))),
+            native_ssh::session_key("host", Some(&settings("b")))
+        );
+        assert!(matches!(
+            SshExec::from(CommandExec::new().timeout(std::time::Duration::from_millis(100))).exec(
+                "sleep",
+                &["5"],
+                None
+            ),
+            Err(ExecError::Timeout(_))
+        ));
+    }
+
+    #[test]
+    fn quoting() {
+        let spec = CommandSpec::new("ls")
+            .args(["$HOME", "|", "wc", "-l"])
+            .quoting(Quoting::None)
+            .context(Context::Remote {
+                host: "host".to_string(),
+                config: None,
+                proxy: None,
+                settings: None,
+            });
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "sh -c 'ls $HOME | wc -l'"]
+        );
+        assert_eq!(
+            CommandExec::new()
+                .exec_spec(&CommandSpec::new("echo $((1 + 1))").quoting(Quoting::None))
+                .unwrap(),
+            "2\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_description() {
+        let description: PipelineDescription = serde_json::from_str(
+            r#"{
+                "stages": [
+                    {
+                        "command": "cat",
+                        "context": {"Remote": {"host": "host", "settings": {"port": 2222}}}
+                    },
+                    {"command": "tr", "args": ["a-z", "A-Z"], "env": {"LC_ALL": "C"}}
+                ],
+                "input": "test"
+            }"#,
+        )
+        .unwrap();
+        let context = Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: Some(Box::new(SshSettings {
+                port: Some(2222),
+                ..SshSettings::default()
+            })),
+        };
+
+        assert_eq!(description.stages[0].context, Some(context.clone()));
+        assert_eq!(
+            serde_json::from_value::<Context>(serde_json::to_value(&context).unwrap()).unwrap(),
+            context
+        );
+
+        let pipeline = Pipeline::from(description);
+
+        assert_eq!(pipeline.get_input(), Some(&b"test"[..]));
+        assert_eq!(
+            pipeline.stages()[1],
+            CommandSpec::new("tr")
+                .args(["a-z", "A-Z"])
+                .env("LC_ALL", "C")
+        );
+    }
+
+    #[test]
+    fn dry_run() {
+        let remote = Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        };
+        let mut exec = DryRunExec::new();
+
+        assert_eq!(
+            exec.exec_piped(&[("ls", &["a b"], Some(&remote)), ("wc", &["-l"], None)])
+                .unwrap(),
+            "ssh host 'ls '\\''a b'\\''' | wc -l\n"
+        );
+        assert_eq!(exec.commands(), ["ssh host 'ls '\\''a b'\\''' | wc -l"]);
+        assert_eq!(
+            CommandExec::new()
+                .render(
+                    &CommandSpec::new("make")
+                        .env("CC", "clang")
+                        .current_dir("/src")
+                        .stdout_to("build.log", true)
+                )
+                .unwrap(),
+            "cd /src && env CC=clang make 1>> build.log"
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl Visit for Recorder {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                span.record(&mut Recorder(self.0.clone()));
+                Id::from_u64(1)
+            }
+
+            fn record(&self, _: &Id, values: &Record<'_>) {
+                values.record(&mut Recorder(self.0.clone()));
+            }
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &tracing::Event<'_>) {
+                event.record(&mut Recorder(self.0.clone()));
+            }
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+
+        tracing::subscriber::with_default(Recorder(records.clone()), || {
+            CommandExec::new()
+                .exec("sh", &["-c", "echo warning >&2"], None)
+                .unwrap();
+        });
+
+        let records = records.lock().unwrap();
+
+        assert!(records.contains(&"command=sh -c 'echo warning >&2'".to_string()));
+        assert!(records.contains(&"context=local".to_string()));
+        assert!(records.contains(&"status=0".to_string()));
+        assert!(records.contains(&"stderr=warning".to_string()));
+        assert!(records
+            .iter()
+            .any(|record| record.starts_with("duration_ms=")));
+    }
+
+    #[test]
+    fn exit_policy() {
+        let no_match = CommandSpec::new("grep").arg("missing").arg("Cargo.toml");
+
+        assert!(matches!(
+            CommandExec::new().exec_spec(&no_match),
+            Err(ExecError::TerminationWithErrorCode(1) | ExecError::TerminationWithError(1, _))
+        ));
+        assert_eq!(
+            CommandExec::new()
+                .accept_codes(&[0, 1])
+                .exec_spec(&no_match)
+                .unwrap(),
+            ""
+        );
+        assert_eq!(
+            CommandExec::new()
+                .exec_spec(&no_match.clone().accept_codes(&[1]))
+                .unwrap(),
+            ""
+        );
+        assert!(CommandExec::new()
+            .exit_policy(ExitPolicy::new(|code| code < 2))
+            .exec_report(&no_match)
+            .is_ok());
+        assert!(CommandExec::new()
+            .accept_codes(&[0, 1])
+            .exec_spec(&no_match.accept_codes(&[0]))
+            .is_err());
+    }
+
+    #[test]
+    fn exec_parallel() {
+        let start = std::time::Instant::now();
+        let results = CommandExec::new().exec_parallel(
+            &[
+                Cmd::new("sh").args(["-c", "sleep 0.3; echo first"]),
+                Cmd::new("false"),
+                Cmd::new("sh").args(["-c", "sleep 0.3; echo third"]),
+            ],
+            3,
+        );
+
+        assert!(start.elapsed() < std::time::Duration::from_millis(550));
+        assert_eq!(results[0].as_ref().unwrap(), "first\n");
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap(), "third\n");
+    }
+
+    #[test]
+    fn dataflow() {
+        let mut flow = DataFlow::new();
+        let first = flow.add(CommandSpec::new("printf").arg("b\\na\\n"), &[]);
+        let second = flow.add(CommandSpec::new("printf").arg("c\\n"), &[]);
+        let sorted = flow.add(CommandSpec::new("sort"), &[first, second]);
+        let counted = flow.add(CommandSpec::new("wc").arg("-l"), &[first]);
+        let result = flow.run(&mut CommandExec::new()).unwrap();
+
+        assert_eq!(result.get(sorted), Some("a\nb\nc\n"));
+        assert_eq!(result.get(counted).map(str::trim), Some("2"));
+        assert_eq!(result.get(first), None);
+
+        let mut flow = DataFlow::new();
+        let failing = flow.add(CommandSpec::new("false"), &[]);
+
+        flow.add(CommandSpec::new("cat"), &[failing]);
+
+        assert!(flow.run(&mut CommandExec::new()).is_err());
+    }
+
+    #[test]
+    fn exec_tee() {
+        let mut log = Vec::new();
+        let output = CommandExec::new()
+            .exec_tee(
+                &Pipeline::new()
+                    .pipe(Cmd::new("cat"))
+                    .pipe(Cmd::new("tr").args(["a-z", "A-Z"]))
+                    .input("backup done\n"),
+                &mut log,
+            )
+            .unwrap();
+
+        assert_eq!(output, "BACKUP DONE\n");
+        assert_eq!(log, b"BACKUP DONE\n");
+    }
+
+    #[test]
+    fn exec_with_callback() {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let output = CommandExec::new()
+            .exec_with_callback(
+                "sh",
+                &["-c", "echo 10%; echo warning >&2; echo 100%"],
+                None,
+                |line| stdout.push(line.to_string()),
+                |line| stderr.push(line.to_string()),
+            )
+            .unwrap();
+
+        assert_eq!(output, "10%\n100%\n");
+        assert_eq!(stdout, ["10%", "100%"]);
+        assert_eq!(stderr, ["warning"]);
+
+        let latin1 = |mut exec: CommandExec| {
+            exec.exec_with_callback("printf", &["caf\\351\\n"], None, |_| {}, |_| {})
+        };
+
+        assert!(matches!(
+            latin1(CommandExec::new()),
+            Err(ExecError::Utf8(_))
+        ));
+        assert_eq!(
+            latin1(CommandExec::new().decoding(Decoding::Latin1)).unwrap(),
+            "café\n"
+        );
+
+        let start = std::time::Instant::now();
+
+        assert!(matches!(
+            CommandExec::new()
+                .timeout(std::time::Duration::from_millis(200))
+                .exec_with_callback("sh", &["-c", "echo started; sleep 5"], None, |_| {}, |_| {}),
+            Err(ExecError::Timeout(_))
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn cancellation() {
+        let cancellation = Cancellation::new();
+        let mut exec = CommandExec::new().cancellation(cancellation.clone());
+        let start = std::time::Instant::now();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            cancellation.cancel();
+        });
+
+        assert!(matches!(
+            exec.exec_piped(&[("sleep", &["5"], None), ("cat", &[], None)]),
+            Err(ExecError::Cancelled)
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        canceller.join().unwrap();
+        assert!(matches!(
+            exec.exec("true", &[], None),
+            Err(ExecError::Cancelled)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_tty() {
+        assert_eq!(
+            CommandExec::new()
+                .exec_tty(&CommandSpec::new("sh").args(["-c", "test -t 1 && echo terminal"]))
+                .unwrap(),
+            "terminal\r\n"
+        );
+
+        let context = interaction::request_tty(&Context::Chain(vec![
+            Context::Remote {
+                host: "host".to_string(),
+                config: None,
+                proxy: None,
+                settings: None,
+            },
+            Context::Local {
+                user: "root".to_string(),
+            },
+        ]));
+        let command = CommandExec::new()
+            .command(&CommandSpec::new("ls").context(context))
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().take(3).collect::<Vec<_>>(),
+            ["-o", "RequestTTY=force", "host"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_tty_report() {
+        let spec = CommandSpec::new("sh").args(["-c", "test -t 1 && echo terminal"]);
+        let report = CommandExec::new().exec_tty_report(&spec).unwrap();
+
+        assert!(report.tty);
+        assert_eq!(report.stdout, b"terminal\r\n");
+
+        let report = CommandExec::new()
+            .exec_report(&CommandSpec::new("sh").args(["-c", "test -t 1 || echo pipe"]))
+            .unwrap();
+
+        assert!(!report.tty);
+        assert_eq!(report.stdout, b"pipe\n");
+        assert!(!interaction::passes_tty(Some(&Context::Chain(vec![
+            Context::Local {
+                user: "root".to_string(),
+            },
+            Context::Docker {
+                container: "app".to_string(),
+                user: None,
+            },
+        ]))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spawn_terminate() {
+        let mut exec = CommandExec::new();
+        let mut running = exec
+            .spawn_pipeline(&Pipeline::new().pipe(CommandSpec::new("sleep").arg("5")))
+            .unwrap();
+        let start = std::time::Instant::now();
+
+        assert_eq!(running.pids().len(), 1);
+        running
+            .terminate(std::time::Duration::from_secs(1))
+            .unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert!(matches!(
+            running.wait(),
+            Err(ExecError::TerminationBySignal)
+        ));
+
+        let mut running = exec
+            .spawn_pipeline(&Pipeline::new().pipe(CommandSpec::new("cat")).input("hello"))
+            .unwrap();
+
+        running.send_signal(Signal::Continue).unwrap();
+        assert_eq!(running.wait().unwrap(), "hello");
+    }
+
+    #[test]
+    fn spawn() {
+        let mut exec = CommandExec::new();
+        let mut handle = exec
+            .spawn(
+                "sh",
+                &["-c", "sleep 0.2; echo out; echo err >&2; exit 3"],
+                None,
+            )
+            .unwrap();
+
+        assert!(handle.pids()[0] > 0);
+        assert!(handle.try_wait().unwrap().is_none());
+
+        let output = handle.wait_full().unwrap();
+
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert_eq!(output.status, 3);
+        assert!(matches!(
+            exec.spawn("sh", &["-c", "exit 3"], None).unwrap().wait(),
+            Err(ExecError::TerminationWithError(3, _))
+        ));
+
+        let mut handle = exec.spawn("echo", &["streamed"], None).unwrap();
+        let mut stdout = String::new();
+
+        std::io::Read::read_to_string(&mut handle.take_stdout().unwrap(), &mut stdout).unwrap();
+        assert_eq!(stdout, "streamed\n");
+        assert_eq!(handle.wait().unwrap(), "");
+
+        let mut running = exec
+            .spawn_pipeline(
+                &Pipeline::new()
+                    .pipe(CommandSpec::new("sort"))
+                    .pipe(CommandSpec::new("sh").args(["-c", "cat; echo err >&2"]))
+                    .input("b\na\n"),
+            )
+            .unwrap();
+        let mut stderr = String::new();
+
+        std::io::Read::read_to_string(&mut running.take_stderr().unwrap(), &mut stderr).unwrap();
+        assert_eq!(stderr, "err\n");
+
+        let output = running.wait_full().unwrap();
+
+        assert_eq!(output.stdout, "a\nb\n");
+        assert_eq!(output.stderr, "");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resource_limits() {
+        assert_eq!(
+            CommandExec::new()
+                .exec_spec(
+                    &CommandSpec::new("sh")
+                        .args(["-c", "ulimit -n; ulimit -t; nice"])
+                        .resource_limits(ResourceLimits {
+                            niceness: Some(19),
+                            io_priority: Some(IoPriority::Idle),
+                            cpu_time: Some(std::time::Duration::from_millis(1500)),
+                            open_files: Some(64),
+                            ..Default::default()
+                        })
+                )
+                .unwrap(),
+            "64\n2\n19\n"
+        );
+    }
+
+    #[test]
+    fn wsl_context() {
+        let context = Context::Wsl {
+            distribution: Some("Ubuntu".to_string()),
+            user: Some("dev".to_string()),
+        };
+        let spec = CommandSpec::new("ls")
+            .arg("-l")
+            .env("LC_ALL", "C")
+            .context(context.clone());
+        let command = CommandExec::new().command(&spec).unwrap();
+
+        assert_eq!(context.to_string(), "wsl distribution Ubuntu");
+        assert_eq!(command.get_program(), "wsl.exe");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-d", "Ubuntu", "-u", "dev", "--", "env", "LC_ALL=C", "ls", "-l"]
+        );
+    }
+
+    #[test]
+    fn exec_shell() {
+        let mut exec = CommandExec::new();
+
+        assert_eq!(
+            exec.exec_shell("echo a b | tr ' ' '\\n' | grep -c . 2>/dev/null", None)
+                .unwrap(),
+            "2\n"
+        );
+
+        let command = exec
+            .command(
+                &CommandSpec::new("ls *.log > list")
+                    .quoting(Quoting::None)
+                    .context(Context::Remote {
+                        host: "host".to_string(),
+                        config: None,
+                        proxy: None,
+                        settings: None,
+                    }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "sh -c 'ls *.log > list'"]
+        );
+    }
+
+    #[test]
+    fn transfer() {
+        let dir = std::env::temp_dir().join(format!("exec-rs-transfer-{}", std::process::id()));
+        let user = String::from(users::get_current_username().unwrap().to_str().unwrap());
+        let context = Context::Local { user };
+        let mut exec = CommandExec::new();
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a"), "content").unwrap();
+        exec.upload(&dir.join("a"), &dir.join("b"), Some(&context))
+            .unwrap();
+        exec.download(&dir.join("b"), &dir.join("c"), None).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("c")).unwrap(), "content");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let command = exec
+            .scp_command(
+                Some(&Context::Remote {
+                    host: "host".to_string(),
+                    config: Some("config".to_string()),
+                    proxy: None,
+                    settings: Some(Box::new(SshSettings {
+                        port: Some(2222),
+                        ..SshSettings::default()
+                    })),
+                }),
+                "script.sh".into(),
+                "host:/tmp/script.sh".into(),
+            )
+            .unwrap();
+
+        assert_eq!(command.get_program(), "scp");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-F",
+                "config",
+                "-o",
+                "Port=2222",
+                "--",
+                "script.sh",
+                "host:/tmp/script.sh"
+            ]
+        );
+    }
+
+    #[test]
+    fn output_limit() {
+        let spec = CommandSpec::new("sh").args(["-c", "head -c 100000 /dev/zero | tr '\\0' a"]);
+        let mut exec = CommandExec::new().output_limit(OutputLimit::Truncate(10));
+
+        assert_eq!(exec.exec_spec(&spec).unwrap(), "aaaaaaaaaa");
+
+        let report = exec.exec_report(&spec).unwrap();
+
+        assert_eq!(report.stdout, b"aaaaaaaaaa");
+        assert!(report.stdout_truncated);
+        assert!(!report.stderr_truncated);
+
+        let mut exec = CommandExec::new().output_limit(OutputLimit::Fail(10));
+
+        assert!(matches!(
+            exec.exec_spec(&spec),
+            Err(ExecError::OutputTooLarge(10))
+        ));
+        assert_eq!(exec.exec("echo", &["short"], None).unwrap(), "short\n");
+
+        // the command is terminated as soon as the limit is exceeded
+        let start = std::time::Instant::now();
+
+        assert!(matches!(
+            exec.exec_spec(&CommandSpec::new("sh").args(["-c", "echo 0123456789ab; sleep 5"])),
+            Err(ExecError::OutputTooLarge(10))
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+        let mut exec = CommandExec::new().output_limit(OutputLimit::Truncate(10));
+        let mut log = Vec::new();
+
+        assert_eq!(
+            exec.exec_tee(&Pipeline::from(spec.clone()), &mut log)
+                .unwrap(),
+            "aaaaaaaaaa"
+        );
+        assert_eq!(log.len(), 100000);
+
+        let output = exec
+            .exec_piped_full(&[
+                (
+                    "sh",
+                    &["-c", "head -c 100000 /dev/zero | tr '\\0' e >&2"],
+                    None,
+                ),
+                ("cat", &[], None),
+            ])
+            .unwrap();
+
+        assert_eq!(output.stderr, "eeeeeeeeee");
+    }
+
+    #[test]
+    fn decoding() {
+        let spec = CommandSpec::new("printf").arg("caf\\351");
+
+        assert!(matches!(
+            CommandExec::new().exec_spec(&spec),
+            Err(ExecError::Utf8(_))
+        ));
+        assert_eq!(
+            CommandExec::new()
+                .decoding(Decoding::Lossy)
+                .exec_spec(&spec)
+                .unwrap(),
+            "caf\u{fffd}"
+        );
+        assert_eq!(
+            CommandExec::new()
+                .decoding(Decoding::Latin1)
+                .exec_spec(&spec)
+                .unwrap(),
+            "café"
+        );
+    }
+
+    #[test]
+    fn pipeline_stderr() {
+        let mut com = CommandExec::new();
+
+        match com.exec_piped(&[("cat", &["missing-file"], None), ("grep", &["x"], None)]) {
+            Err(ExecError::TerminationWithError(1, stderr)) => {
+                assert!(stderr.contains("missing-file"))
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn pipefail() {
+        let commands: &[(&str, &[&str], Option<&Context>)] = &[
+            ("sh", &["-c", "echo failed >&2; exit 3"], None),
+            ("cat", &[], None),
+        ];
+
+        assert_eq!(CommandExec::new().exec_piped(commands).unwrap(), "");
+        assert!(matches!(
+            CommandExec::new().pipefail(true).exec_piped(commands),
+            Err(ExecError::TerminationWithError(3, stderr)) if stderr == "failed\n"
+        ));
+
+        let output = CommandExec::new()
+            .run_stages(
+                &Pipeline::new()
+                    .pipe(CommandSpec::new("sh").args(["-c", "echo a; echo warn >&2; exit 2"]))
+                    .pipe(CommandSpec::new("cat")),
+            )
+            .unwrap();
+
+        assert_eq!(output.stdout, "a\n");
+        assert_eq!(
+            output.stages,
+            [
+                StageStatus {
+                    status: Some(2),
+                    stderr: "warn\n".to_string()
+                },
+                StageStatus {
+                    status: Some(0),
+                    stderr: String::new()
+                }
+            ]
+        );
+        assert!(!output.success());
+    }
+
+    #[test]
+    fn forward_env() {
+        std::env::set_var("EXEC_RS_FORWARD_A", "a");
+        std::env::set_var("EXEC_RS_FORWARD_B", "b");
+
+        let context = Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        };
+        let command = CommandExec::new()
+            .forward_env(&["EXEC_RS_FORWARD_*", "EXEC_RS_MISSING"])
+            .command(
+                &CommandSpec::new("ls")
+                    .env("EXEC_RS_FORWARD_B", "spec")
+                    .context(context),
+            )
+            .unwrap();
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["host", "env EXEC_RS_FORWARD_A=a EXEC_RS_FORWARD_B=spec ls"]
+        );
+    }
+
+    #[test]
+    fn sudo_keep_alive_stops_on_drop() {
+        let refreshes = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = refreshes.clone();
+        let keep_alive =
+            SudoKeepAlive::refreshing(std::time::Duration::from_millis(10), move || {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Err(ExecError::Execution("stub".into()))
+            });
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        drop(keep_alive);
+
+        let stopped = refreshes.load(std::sync::atomic::Ordering::SeqCst);
+
+        // failed refreshes do not stop the thread
+        assert!(stopped > 1);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), stopped);
+    }
+
+    #[test]
+    fn sudo_auth() {
+        let spec = CommandSpec::new("ls").arg("a b").context(Context::Local {
+            user: "user".to_string(),
+        });
+        let command = CommandExec::new()
+            .escalation(Escalation::Sudo)
+            .sudo_auth(SudoAuth::Askpass("/usr/bin/askpass".into()))
+            .force_escalation(true)
+            .command(&spec)
+            .unwrap();
+
+        assert_eq!(command.get_program(), "sudo");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-Au", "user", "--", "ls", "a b"]
+        );
+        assert!(command.get_envs().any(|(key, value)| key == "SUDO_ASKPASS"
+            && value == Some(std::ffi::OsStr::new("/usr/bin/askpass"))));
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                1,
+                "sudo: a password is required\n".to_string()
+            )),
+            ExecError::SudoAuthenticationRequired(user) if user == "user"
+        ));
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                1,
+                "sudo: 1 incorrect password attempt\n".to_string()
+            )),
+            ExecError::SudoAuthenticationFailed(user) if user == "user"
+        ));
+        assert!(matches!(
+            spec.classify(ExecError::TerminationWithError(
+                1,
+                "ls: a b: not found\n".to_string()
+            )),
+            ExecError::TerminationWithError(1, _)
+        ));
+    }
+
+    #[test]
+    fn check() {
+        let user = users::get_effective_username().unwrap();
+        let info = CommandExec::new().check(None).unwrap();
+
+        assert_eq!(info.user, user.to_str().unwrap());
+
+        let context = Context::Local {
+            user: "exec-rs-missing".to_string(),
+        };
+
+        match CommandExec::new()
+            .escalation(Escalation::Su)
+            .check(Some(&context))
+        {
+            Err(ExecError::Unavailable(context, reason)) => {
+                assert_eq!(context, "local as exec-rs-missing");
+                assert!(reason.starts_with("su:"));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|info| info.user)),
+        }
+    }
+
+    #[test]
+    fn fleet_exec_on_all() {
+        let user = String::from(users::get_current_username().unwrap().to_str().unwrap());
+        let contexts = [
+            Context::Local { user: user.clone() },
+            Context::Local {
+                user: "exec-rs-missing".to_string(),
+            },
+        ];
+        let results = CommandExec::new().escalation(Escalation::Su).exec_on_all(
+            &contexts,
+            "echo",
+            &["hello"],
+            2,
+        );
+        let output = results[&format!("local as {}", user)].as_ref().unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(output.stdout, "hello\n");
+        assert!(output.success());
+        assert!(!results["local as exec-rs-missing"]
+            .as_ref()
+            .unwrap()
+            .success());
+
+        let contexts = [contexts[0].clone(), contexts[0].clone()];
+        let results = fleet::exec_on_all(&CommandExec::new(), &contexts, "echo", &["hello"], 2);
+        let mut keys: Vec<&String> = results.keys().collect();
+
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                &format!("local as {}#0", user),
+                &format!("local as {}#1", user)
+            ]
+        );
+    }
+
+    #[test]
+    fn record_replay() {
+        let mut recorder = RecorderExec::new(CommandExec::new());
+
+        assert_eq!(recorder.exec("echo", &["a b"], None).unwrap(), "a b\n");
+        assert!(recorder
+            .exec("sh", &["-c", "echo oops >&2; exit 3"], None)
+            .is_err());
+        assert_eq!(
+            recorder
+                .exec_full("sh", &["-c", "exit 4"], None)
+                .unwrap()
+                .status,
+            4
+        );
+
+        let recordings = recorder.into_recordings();
+
+        assert_eq!(recordings.len(), 3);
+        assert_eq!(recordings[1].status, 3);
+        assert_eq!(recordings[1].stderr, "oops\n");
+
+        #[cfg(feature = "serde")]
+        let recordings: Vec<Recording> =
+            serde_json::from_str(&serde_json::to_string(&recordings).unwrap()).unwrap();
+        let mut replay = ReplayExec::new(recordings);
+
+        assert!(matches!(
+            replay.exec("sh", &["-c", "echo oops >&2; exit 3"], None),
+            Err(ExecError::TerminationWithError(3, stderr)) if stderr == "oops\n"
+        ));
+        assert_eq!(replay.exec("echo", &["a b"], None).unwrap(), "a b\n");
+        assert!(matches!(
+            replay.exec("echo", &["a b"], None),
+            Err(ExecError::UnexpectedCommand(line)) if line == "echo 'a b'"
+        ));
+        assert_eq!(replay.remaining().len(), 1);
+    }
+
+    #[test]
+    fn fake_exec() {
+        let mut fake = FakeExec::new()
+            .on_glob(
+                "systemctl status *",
+                FakeResponse::new("inactive\n").status(3),
+            )
+            .unwrap()
+            .on("^uname", FakeResponse::new("Linux\n"))
+            .unwrap();
+
+        assert_eq!(fake.exec("uname", &["-s"], None).unwrap(), "Linux\n");
+        assert!(matches!(
+            fake.exec("systemctl", &["status", "nginx"], None),
+            Err(ExecError::TerminationWithError(3, _))
+        ));
+        assert_eq!(
+            fake.exec_full("systemctl", &["status", "sshd"], None)
+                .unwrap()
+                .stdout,
+            "inactive\n"
+        );
+        assert!(matches!(
+            fake.exec_piped(&[("ls", &["a b"], None), ("wc", &["-l"], None)]),
+            Err(ExecError::UnexpectedCommand(line)) if line == "ls 'a b' | wc -l"
+        ));
+        assert_eq!(fake.invocations().len(), 4);
+        fake.assert_invoked(&["^uname", "nginx$", "^ls"]);
+    }
+
+    #[test]
+    fn exec_ext() {
+        let mut fake = FakeExec::new()
+            .on_glob("id *", FakeResponse::new("  root \n"))
+            .unwrap()
+            .on_glob("ls", FakeResponse::new("a\nb\n"))
+            .unwrap()
+            .on_glob("test *", FakeResponse::new("partial\n").status(1))
+            .unwrap();
+
+        assert_eq!(fake.exec_trimmed("id", &["-un"], None).unwrap(), "root");
+        assert_eq!(fake.exec_lines("ls", &[], None).unwrap(), ["a", "b"]);
+        assert!(fake.exec_ok("id", &["-un"], None).unwrap());
+        assert!(!fake.exec_ok("test", &["-d", "/x"], None).unwrap());
+        assert_eq!(
+            fake.exec_ignore_status("test", &["-d", "/x"], None)
+                .unwrap(),
+            "partial\n"
+        );
+        assert!(fake.exec_ok("missing", &[], None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn exec_json() {
+        #[derive(serde::Deserialize)]
+        struct Device {
+            name: String,
+            size: u64,
+        }
+
+        let mut fake = FakeExec::new()
+            .on_glob(
+                "lsblk *",
+                FakeResponse::new(r#"[{"name": "sda", "size": 512}]"#),
+            )
+            .unwrap()
+            .on_glob("docker *", FakeResponse::new("Error: not json\n"))
+            .unwrap();
+        let devices: Vec<Device> = fake.exec_json("lsblk", &["-J"], None).unwrap();
+
+        assert_eq!(devices[0].name, "sda");
+        assert_eq!(devices[0].size, 512);
+        assert!(matches!(
+            fake.exec_json::<Vec<Device>>("docker", &["ps"], None),
+            Err(ExecError::Parse(_, output)) if output == "Error: not json\n"
+        ));
+    }
+
+    #[test]
+    fn forwarding_exec() {
+        fn hostname(mut exec: impl Exec) -> String {
+            exec.exec("hostname", &[], None).unwrap()
+        }
+
+        let mut fake = FakeExec::new()
+            .on_glob("hostname", FakeResponse::new("host\n"))
+            .unwrap();
+
+        assert_eq!(hostname(&mut fake), "host\n");
+        assert_eq!(fake.invocations(), ["hostname"]);
+
+        let boxed: Box<dyn Exec> = Box::new(fake);
+
+        assert_eq!(hostname(boxed), "host\n");
+    }
+
+    #[test]
+    fn hooks() {
+        use std::sync::{Arc, Mutex};
+
+        struct Audit(Arc<Mutex<Vec<String>>>);
+
+        impl Hook for Audit {
+            fn before(&self, spec: CommandSpec) -> Result<CommandSpec, ExecError> {
+                if spec.get_program() == "rm" {
+                    return Err(ExecError::Execution("not confirmed".to_string()));
+                }
+
+                Ok(spec.arg("rewritten"))
+            }
+
+            fn after(&self, spec: &CommandSpec, result: &Result<ExecOutput, ExecError>) {
+                self.0.lock().unwrap().push(format!(
+                    "{:?} {:?}",
+                    spec.get_args(),
+                    result.as_ref().map(|output| output.stdout.clone()).ok()
+                ));
+            }
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut exec = CommandExec::new().hook(Audit(log.clone()));
+
+        assert_eq!(exec.exec("echo", &["a"], None).unwrap(), "a rewritten\n");
+        assert_eq!(
+            exec.exec_full("sh", &["-c", "echo $0; exit 2"], None)
+                .unwrap()
+                .status,
+            2
+        );
+        assert!(matches!(
+            exec.exec("rm", &["-rf", "/tmp/exec-rs-hooks"], None),
+            Err(ExecError::Execution(message)) if message == "not confirmed"
+        ));
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "[\"a\", \"rewritten\"] Some(\"a rewritten\\n\")",
+                "[\"-c\", \"echo $0; exit 2\", \"rewritten\"] Some(\"rewritten\\n\")"
+            ]
+        );
+    }
+
+    #[test]
+    fn spec_display() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let spec = CommandSpec::new("ls").arg("a b");
+        let remote = Context::Remote {
+            host: "host".to_string(),
+            config: None,
+            proxy: None,
+            settings: None,
+        };
+
+        assert_eq!(spec.to_string(), "ls 'a b'");
+        assert_eq!(
+            spec.clone().context(remote.clone()).to_string(),
+            r#"ssh host 'ls '\''a b'\'''"#
+        );
+        assert_eq!(
+            spec.clone()
+                .context(Context::Local {
+                    user: "exec-rs-missing".to_string(),
+                })
+                .to_string(),
+            "sudo -nu exec-rs-missing -- ls 'a b'"
+        );
+        // arguments that are not valid UTF-8 cannot be passed to ssh
+        assert_eq!(
+            spec.arg(std::ffi::OsStr::from_bytes(b"\xff"))
+                .context(remote.clone())
+                .to_string(),
+            "ls 'a b' '\u{fffd}'"
+        );
+
+        let configured = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = configured.clone();
+        let spec = CommandSpec::new("ls")
+            .stdout_to("/nonexistent/list", false)
+            .configure(move |_| flag.store(true, std::sync::atomic::Ordering::SeqCst));
+
+        assert_eq!(spec.to_string(), "ls 1> /nonexistent/list");
+        assert_eq!(
+            spec.context(remote).to_string(),
+            r#"ssh host 'sh -c '\''exec "$@" 1> /nonexistent/list'\'' sh ls'"#
+        );
+        assert!(!configured.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn backend() {
+        let mut com = CommandExec::new();
+        let pipeline = Pipeline::new()
+            .pipe(Cmd::new("sort"))
+            .pipe(Cmd::new("head").arg("-n2"))
+            .input("c\nb\na\n");
+        let failing = Pipeline::from(Cmd::new("sh").args(["-c", "exit 3"]));
+
+        for backend in [Backend::StdProcess, Backend::TokioProcess] {
+            assert_eq!(com.run_with(backend, &pipeline).unwrap(), "a\nb\n");
+            assert!(matches!(
+                com.run_with(backend, &failing),
+                Err(ExecError::TerminationWithError(3, _))
+            ));
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let marker = std::env::temp_dir().join(format!("exec-rs-backend-{}", std::process::id()));
+        let flaky = Pipeline::from(Cmd::new("sh").args([
+            "-c",
+            "test -e \"$1\" && echo done || { touch \"$1\"; exit 1; }",
+            "sh",
+            marker.to_str().unwrap(),
+        ]));
+        let mut retried = CommandExec::new().retry_policy(
+            RetryPolicy::new(2)
+                .backoff(FixedBackoff(std::time::Duration::ZERO))
+                .retryable(|_| true),
+        );
+
+        runtime.block_on(async {
+            for backend in [Backend::StdProcess, Backend::TokioProcess] {
+                assert_eq!(
+                    com.run_with_async(backend, &pipeline).await.unwrap(),
+                    "a\nb\n"
+                );
+                assert!(matches!(
+                    com.run_with_async(backend, &failing).await,
+                    Err(ExecError::TerminationWithError(3, _))
+                ));
+            }
+
+            assert!(matches!(
+                com.run_with(Backend::TokioProcess, &pipeline),
+                Err(ExecError::Execution(_))
+            ));
+            assert_eq!(
+                retried
+                    .run_with_async(Backend::TokioProcess, &flaky)
+                    .await
+                    .unwrap(),
+                "done\n"
+            );
+        });
+        std::fs::remove_file(&marker).unwrap();
+    }
+
+    #[test]
+    fn known_hosts_keep_lines() {
+        let path =
+            std::env::temp_dir().join(format!("exec-rs-known-hosts-lines-{}", std::process::id()));
+        let known_hosts = KnownHosts::new(&path);
+        let key = |host: &str, key: &str| HostKey {
+            host: host.to_string(),
+            key_type: "ssh-ed25519".to_string(),
+            key: key.to_string(),
+        };
+
+        std::fs::write(
+            &path,
+            "# managed by hand\n@revoked a ssh-ed25519 AAAA0\n@cert-authority *.example.com ssh-ed25519 AAAA9\na ssh-ed25519 AAAA1\n",
+        )
+        .unwrap();
+        known_hosts.add(&[key("b", "AAAA2")]).unwrap();
+        known_hosts.pin("a", &[key("a", "AAAA3")]).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# managed by hand\n@revoked a ssh-ed25519 AAAA0\n@cert-authority *.example.com ssh-ed25519 AAAA9\nb ssh-ed25519 AAAA2\na ssh-ed25519 AAAA3\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn timeout_kills_grandchildren() {
+        let path = std::env::temp_dir().join(format!("exec-rs-grandchild-{}", std::process::id()));
+        let script = format!("sleep 100 & echo $! > {}; wait", path.display());
+        let mut com = CommandExec::new().timeout(std::time::Duration::from_millis(500));
+
+        assert!(matches!(
+            com.exec("sh", &["-c", &script], None),
+            Err(ExecError::Timeout(_))
+        ));
+
+        let pid: i32 = std::fs::read_to_string(&path)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let start = std::time::Instant::now();
+
+        // the orphaned grandchild is reaped by init, which may take a moment
+        while std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .is_ok_and(|stat| !stat.contains(") Z "))
+        {
+            assert!(
+                start.elapsed() < std::time::Duration::from_secs(2),
+                "grandchild survived"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn hooks_entry_points() {
+        use std::sync::{Arc, Mutex};
+
+        struct Trace(Arc<Mutex<Vec<String>>>);
+
+        impl Hook for Trace {
+            fn before(&self, spec: CommandSpec) -> Result<CommandSpec, ExecError> {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("before {:?}", spec.get_program()));
+                Ok(spec)
+            }
+
+            fn after(&self, spec: &CommandSpec, result: &Result<ExecOutput, ExecError>) {
+                self.0.lock().unwrap().push(format!(
+                    "after {:?} {:?}",
+                    spec.get_program(),
+                    result.as_ref().map(|output| output.stdout.clone()).ok()
+                ));
+            }
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut exec = CommandExec::new().hook(Trace(log.clone()));
+        let echo = Cmd::new("echo").arg("a");
+        let take = || std::mem::take(&mut *log.lock().unwrap());
+        let hooked = ["before \"echo\"", "after \"echo\" Some(\"a\\n\")"];
+
+        exec.exec_report(&echo).unwrap();
+        assert_eq!(take(), hooked);
+
+        exec.exec_events(&echo).unwrap();
+        assert_eq!(take(), hooked);
+
+        exec.exec_spec_with_callback(&echo, |_| {}, |_| {}).unwrap();
+        assert_eq!(take(), hooked);
+
+        exec.exec_interaction(&echo, &Interaction::new()).unwrap();
+        assert_eq!(
+            take(),
+            ["before \"echo\"", "after \"echo\" Some(\"a\\r\\n\")"]
+        );
+
+        exec.run_stages(&Pipeline::from(echo.clone())).unwrap();
+        assert_eq!(take(), hooked);
+
+        let mut flow = DataFlow::new();
+
+        flow.add(echo.clone(), &[]);
+        flow.run(&mut exec).unwrap();
+        assert_eq!(take(), hooked);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime
+            .block_on(AsyncCommandExec::from(exec.clone()).run(&Pipeline::from(echo.clone())))
+            .unwrap();
+        assert_eq!(take(), hooked);
+
+        exec.exec_status_only(&echo).unwrap();
+        assert_eq!(take(), ["before \"echo\"", "after \"echo\" Some(\"\")"]);
+
+        // commands not waited for only pass through `before`
+        assert_eq!(exec.stream(&echo).unwrap().count(), 1);
+        assert_eq!(take(), ["before \"echo\""]);
+
+        assert_eq!(
+            exec.stream_piped(std::slice::from_ref(&echo))
+                .unwrap()
+                .count(),
+            1
+        );
+        assert_eq!(take(), ["before \"echo\""]);
+
+        exec.launch_detached(&Cmd::new("true"), &Detach::default())
+            .unwrap();
+        assert_eq!(take(), ["before \"true\""]);
+
+        ShellSession::start(&mut exec, "sh", None).unwrap();
+        assert_eq!(take(), ["before \"sh\""]);
+    }
+
+    #[test]
+    fn exec_default_methods() {
+        // implements only the methods of the first releases
+        struct Shell;
+
+        impl Exec for Shell {
+            fn exec<'a>(
+                &mut self,
+                command: &str,
+                args: &[&'a str],
+                context: Option<&'a Context>,
+            ) -> Result<String, ExecError> {
+                self.exec_piped(&[(command, args, context)])
+            }
+
+            fn exec_piped<'a>(
+                &mut self,
+                commands: &[(&'a str, &'a [&'a str], Option<&'a Context>)],
+            ) -> Result<String, ExecError> {
+                CommandExec::new().exec_piped(commands)
+            }
+        }
+
+        assert_eq!(
+            Shell
+                .run(
+                    &Pipeline::new()
+                        .pipe(Cmd::new("echo").arg("b a"))
+                        .pipe(Cmd::new("rev"))
+                )
+                .unwrap(),
+            "a b\n"
+        );
+        assert!(matches!(
+            Shell.run(&Pipeline::from(Cmd::new("cat")).input("in")),
+            Err(ExecError::Execution(_))
+        ));
+        assert_eq!(Shell.exec_bytes("echo", &["a"], None).unwrap(), b"a\n");
+
+        let output = Shell
+            .exec_full("sh", &["-c", "echo err >&2; exit 3"], None)
+            .unwrap();
+
+        assert_eq!(output.stderr, "err\n");
+        assert_eq!(output.status, 3);
+        assert!(Shell
+            .exec_piped_full(&[("echo", &["a"], None)])
+            .unwrap()
+            .success());
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec("cmd", &["/C", "echo hello"], None).unwrap(),
+            "hello\r\n"
+        );
+    }
+
+    #[test]
+    fn run_piped() {
+        let mut com = CommandExec::new();
+
+        assert_eq!(
+            com.exec_piped(&[
+                ("cmd", &["/C", "echo a& echo b"], None),
+                ("findstr", &["b"], None),
+            ])
+            .unwrap(),
+            "b\r\n"
+        );
+    }
+
+    #[test]
+    fn local_context_of_current_user() {
+        let user = std::env::var("USERNAME").unwrap();
+        let command = CommandExec::new()
+            .command(&CommandSpec::new("whoami").context(Context::Local { user }))
+            .unwrap();
+
+        assert_eq!(command.get_program(), "whoami");
+    }
+
+    #[test]
+    fn local_context_of_other_user() {
+        let command = CommandExec::new()
+            .command(&CommandSpec::new("whoami").context(Context::Local {
+                user: "exec-rs-other".to_string(),
+            }))
+            .unwrap();
+
+        assert_eq!(command.get_program(), "runas");
     }
 }
@@ -0,0 +1,195 @@
+use crate::ExecError;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// I/O scheduling class of the command (Linux)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IoPriority {
+    /// Served first, with a priority from 0 (highest) to 7; usually requires privileges
+    RealTime(u8),
+    /// Default class, with a priority from 0 (highest) to 7
+    BestEffort(u8),
+    /// Served only if no other process needs the disk
+    Idle,
+}
+
+/// Priority and resource limits of a locally spawned command (unix)
+///
+/// The settings are applied in the child before the command is executed, so that they are
+/// inherited by wrappers such as sudo. They are ignored for commands in other contexts.
+///
+/// * `niceness` - niceness from -20 (highest priority) to 19 (lowest priority)
+/// * `io_priority` - I/O scheduling class (Linux only)
+/// * `cpu_time` - CPU time after which the command is terminated; rounded up to whole seconds
+/// * `memory` - maximum size of the address space in bytes
+/// * `open_files` - maximum number of open file descriptors
+///
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct ResourceLimits {
+    pub niceness: Option<i32>,
+    pub io_priority: Option<IoPriority>,
+    pub cpu_time: Option<Duration>,
+    pub memory: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Sets the priorities and limits in the child before the command is executed
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, com: &mut std::process::Command) {
+        let limits = *self;
+
+        // SAFETY: setpriority, setrlimit and syscall are async-signal-safe and the closure does not allocate
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(com, move || {
+                if let Some(niceness) = limits.niceness {
+                    if libc::setpriority(libc::PRIO_PROCESS as _, 0, niceness) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+
+                #[cfg(target_os = "linux")]
+                if let Some(priority) = limits.io_priority {
+                    // see ioprio_set(2); the class is stored above the 13 bits of the priority
+                    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                    let value = match priority {
+                        IoPriority::RealTime(level) => 1 << 13 | level.min(7) as libc::c_int,
+                        IoPriority::BestEffort(level) => 2 << 13 | level.min(7) as libc::c_int,
+                        IoPriority::Idle => 3 << 13,
+                    };
+
+                    if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, value) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+
+                let cpu_time = limits
+                    .cpu_time
+                    .map(|time| time.as_secs() + u64::from(time.subsec_nanos() > 0));
+
+                for (resource, limit) in [
+                    (libc::RLIMIT_CPU, cpu_time),
+                    (libc::RLIMIT_AS, limits.memory),
+                    (libc::RLIMIT_NOFILE, limits.open_files),
+                ] {
+                    let Some(limit) = limit else {
+                        continue;
+                    };
+                    let limit = libc::rlimit {
+                        rlim_cur: limit as libc::rlim_t,
+                        rlim_max: limit as libc::rlim_t,
+                    };
+
+                    if libc::setrlimit(resource, &limit) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+
+                Ok(())
+            })
+        };
+    }
+}
+
+/// Maximum size of each captured output stream
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputLimit {
+    /// Output beyond the number of bytes is discarded
+    Truncate(usize),
+    /// Output beyond the number of bytes terminates the command and fails the execution with
+    /// `ExecError::OutputTooLarge`
+    Fail(usize),
+}
+
+impl OutputLimit {
+    /// Returns the number of bytes kept
+    pub fn bytes(&self) -> usize {
+        match self {
+            OutputLimit::Truncate(bytes) | OutputLimit::Fail(bytes) => *bytes,
+        }
+    }
+}
+
+/// Output limit shared by the readers of all streams captured from a process tree
+///
+/// Once a stream exceeds `OutputLimit::Fail`, the readers stop and the tree is terminated by the
+/// thread waiting for it.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Limiter {
+    limit: Option<OutputLimit>,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: Option<OutputLimit>) -> Self {
+        Limiter {
+            limit,
+            exceeded: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> Option<OutputLimit> {
+        self.limit
+    }
+
+    /// Appends the chunk to the buffer up to the limit; returns whether the chunk was kept completely
+    pub(crate) fn extend(&self, buffer: &mut Vec<u8>, chunk: &[u8]) -> bool {
+        let kept = match self.limit {
+            Some(limit) => chunk.len().min(limit.bytes().saturating_sub(buffer.len())),
+            None => chunk.len(),
+        };
+
+        buffer.extend_from_slice(&chunk[..kept]);
+
+        if kept < chunk.len() && matches!(self.limit, Some(OutputLimit::Fail(_))) {
+            self.exceeded.store(true, Ordering::SeqCst);
+        }
+
+        kept == chunk.len()
+    }
+
+    /// Checks whether a stream exceeded `OutputLimit::Fail`, after which reading stops
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `ExecError::OutputTooLarge` if a stream exceeded `OutputLimit::Fail`
+    pub(crate) fn check(&self) -> Result<(), ExecError> {
+        match self.limit {
+            Some(limit) if self.exceeded() => Err(ExecError::OutputTooLarge(limit.bytes())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the pipe to the end and keeps the output up to the limit
+    ///
+    /// With `OutputLimit::Truncate`, the output beyond the limit is read and discarded, so that
+    /// the command is not blocked. Returns the kept output and whether some was discarded.
+    pub(crate) fn read<R: Read>(&self, pipe: &mut R) -> std::io::Result<(Vec<u8>, bool)> {
+        let mut buffer = Vec::new();
+
+        if self.limit.is_none() {
+            pipe.read_to_end(&mut buffer)?;
+            return Ok((buffer, false));
+        }
+
+        let mut chunk = [0u8; 8192];
+        let mut discarded = false;
+
+        loop {
+            let len = pipe.read(&mut chunk)?;
+
+            if len == 0 {
+                return Ok((buffer, discarded));
+            }
+
+            discarded |= !self.extend(&mut buffer, &chunk[..len]);
+
+            if self.exceeded() {
+                return Ok((buffer, discarded));
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{ssh, CommandExec, Context, ExecError};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) type Masters = Arc<Mutex<Sessions>>;
+
+/// Time to wait for the master connection to be established
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+static SOCKETS: AtomicU64 = AtomicU64::new(0);
+
+/// Master connection providing the control socket for the commands in a remote context
+struct Master {
+    child: Child,
+    host: String,
+    dir: PathBuf,
+}
+
+impl Master {
+    fn exit(mut self) {
+        control(&self.dir.join(SOCKET), &self.host, "exit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Name of the control socket within the private directory of the master connection
+pub(crate) const SOCKET: &str = "socket";
+
+/// Master connections by remote context; they are closed once the last clone of the executor is
+/// dropped
+#[derive(Default)]
+pub(crate) struct Sessions {
+    masters: HashMap<String, Master>,
+}
+
+impl Drop for Sessions {
+    fn drop(&mut self) {
+        for (_, master) in self.masters.drain() {
+            master.exit();
+        }
+    }
+}
+
+pub(crate) fn control_path(path: &std::path::Path) -> String {
+    format!("ControlPath={}", path.to_string_lossy())
+}
+
+/// Returns the key of the master connection; contexts with other settings use other connections
+fn key(context: &Context) -> String {
+    format!("{:?}", context)
+}
+
+/// Sends the command (e.g. `check` or `exit`) to the master connection; returns whether it succeeded
+pub(crate) fn control(path: &std::path::Path, host: &str, command: &str) -> bool {
+    Command::new("ssh")
+        .arg("-o")
+        .arg(control_path(path))
+        .args(["-O", command])
+        .arg(host)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Creates a directory only accessible by the current user for the control socket, like `mkdtemp`
+pub(crate) fn socket_dir() -> Result<PathBuf, ExecError> {
+    loop {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let dir = std::env::temp_dir().join(format!(
+            "exec-rs-{}-{}-{}",
+            std::process::id(),
+            SOCKETS.fetch_add(1, Ordering::Relaxed),
+            nanos
+        ));
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut builder = std::fs::DirBuilder::new();
+
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+        // creating the directory fails if it exists, e.g. if it was prepared by another user
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+impl CommandExec {
+    /// Opens a master connection to the host, which is reused by all subsequent commands in the context
+    ///
+    /// The connection is shared through a control socket (`ControlMaster`/`ControlPath`) in a
+    /// directory only accessible by the current user, so that the commands skip the handshake and
+    /// the authentication. Only commands in the same context, including the config file, the proxy
+    /// and the settings, use the connection. It stays open until it is disconnected or the last
+    /// clone of the executor is dropped. Connecting a context with an open connection does nothing.
+    ///
+    /// * `context` - the remote context; its ssh settings apply to the master connection
+    ///
+    pub fn connect(&self, context: &Context) -> Result<(), ExecError> {
+        let Context::Remote { host, .. } = context else {
+            return Err(ExecError::Execution(
+                "connection reuse requires a remote context".into(),
+            ));
+        };
+
+        if self.lock_masters().masters.contains_key(&key(context)) {
+            return Ok(());
+        }
+
+        let dir = socket_dir()?;
+        let path = dir.join(SOCKET);
+        let spawned = self.master_command(context, &path).and_then(|mut com| {
+            Ok(com
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()?)
+        });
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(e);
+            }
+        };
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        let master = |child| Master {
+            child,
+            host: host.clone(),
+            dir: dir.clone(),
+        };
+
+        // ssh creates the socket once the connection is authenticated; it may not accept
+        // connections yet
+        while !(path.exists() && control(&path, host, "check")) {
+            if let Some(status) = child.try_wait()? {
+                let mut stderr = String::new();
+
+                if let Some(mut pipe) = child.stderr.take() {
+                    pipe.read_to_string(&mut stderr)?;
+                }
+
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(match status.code() {
+                    Some(code) => {
+                        ssh::classify(host, ExecError::TerminationWithError(code, stderr))
+                    }
+                    None => ExecError::TerminationBySignal,
+                });
+            }
+
+            if Instant::now() >= deadline {
+                master(child).exit();
+                return Err(ExecError::Execution(
+                    "master connection was not established in time".into(),
+                ));
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        // later messages are discarded; closing the pipe would terminate ssh
+        if let Some(mut pipe) = child.stderr.take() {
+            std::thread::spawn(move || std::io::copy(&mut pipe, &mut std::io::sink()));
+        }
+
+        let master = master(child);
+
+        // another clone may have connected in the meantime
+        if let Some(master) = self.lock_masters().masters.insert(key(context), master) {
+            master.exit();
+        }
+
+        Ok(())
+    }
+
+    /// Closes the master connection of the context; subsequent commands open their own connections
+    ///
+    /// * `context` - the remote context
+    ///
+    pub fn disconnect(&self, context: &Context) {
+        let master = self.lock_masters().masters.remove(&key(context));
+
+        if let Some(master) = master {
+            master.exit();
+        }
+    }
+
+    /// Checks whether a master connection of the context is open
+    ///
+    /// * `context` - the remote context
+    ///
+    pub fn is_connected(&self, context: &Context) -> bool {
+        self.lock_masters().masters.contains_key(&key(context))
+    }
+
+    pub(crate) fn master_command(
+        &self,
+        context: &Context,
+        path: &std::path::Path,
+    ) -> Result<Command, ExecError> {
+        let Context::Remote {
+            host,
+            config,
+            proxy,
+            settings,
+        } = context
+        else {
+            return Err(ExecError::Execution(
+                "connection reuse requires a remote context".into(),
+            ));
+        };
+        let mut com = self.ssh_command(config.as_deref(), proxy.as_ref(), settings.as_deref());
+
+        com.args(["-N", "-o", "ControlMaster=yes", "-o"])
+            .arg(control_path(path))
+            .arg(host);
+        Ok(com)
+    }
+
+    /// Returns the option selecting the control socket of an open master connection of the context
+    pub(crate) fn control_option(&self, context: &Context) -> Option<String> {
+        self.lock_masters()
+            .masters
+            .get(&key(context))
+            .map(|master| control_path(&master.dir.join(SOCKET)))
+    }
+
+    fn lock_masters(&self) -> std::sync::MutexGuard<'_, Sessions> {
+        self.masters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,501 @@
+use crate::{
+    hook, quote, CommandExec, CommandSpec, Context, Exec, ExecError, ExecOutput, Pipeline,
+    SshSettings,
+};
+use ssh2::{CheckResult, ErrorCode, KnownHostFileKind, Session};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// Error code of libssh2 signalling that a non-blocking operation would block
+const EAGAIN: i32 = -37;
+
+/// Output of a command or pipeline
+struct Finished {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    status: i32,
+}
+
+/// Executor running remote commands through libssh2 instead of the ssh binary (requires the `ssh2` feature)
+///
+/// One authenticated session is kept per host and settings (user, port, ...) and reused by
+/// subsequent commands with the same host and settings. The host key is
+/// verified against the known_hosts file of the `CommandExec` or `~/.ssh/known_hosts`;
+/// authentication uses the identity file of the `SshSettings`, the ssh agent or the default keys
+/// in `~/.ssh`. ssh config files, proxies and jump hosts are not supported. Commands in other
+/// contexts are run by the `CommandExec`; the stages of a pipeline are run one after the other.
+pub struct SshExec {
+    exec: CommandExec,
+    sessions: HashMap<String, (String, Session)>,
+    connect_timeout: Duration,
+}
+
+impl Default for SshExec {
+    fn default() -> Self {
+        SshExec {
+            exec: CommandExec::default(),
+            sessions: HashMap::new(),
+            connect_timeout: SshExec::DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+}
+
+impl From<CommandExec> for SshExec {
+    fn from(exec: CommandExec) -> Self {
+        SshExec {
+            exec,
+            ..SshExec::default()
+        }
+    }
+}
+
+impl SshExec {
+    /// Default time to wait for the connection to a host
+    pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time to wait for the connection to a host
+    ///
+    /// * `timeout` - maximum time for establishing the TCP connection and the ssh handshake
+    ///
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Closes the sessions to the host, whatever their settings; the next command on it opens a new one
+    ///
+    /// * `host` - the host of the remote context
+    ///
+    pub fn disconnect(&mut self, host: &str) {
+        self.sessions.retain(|_, (session_host, session)| {
+            if session_host != host {
+                return true;
+            }
+
+            let _ = session.disconnect(None, "", None);
+            false
+        });
+    }
+
+    fn run_specs(&mut self, specs: &[CommandSpec], input: &[u8]) -> Result<Finished, ExecError> {
+        let specs = self.exec.resolve(specs)?;
+        let start = Instant::now();
+        let result = self.run_resolved(&specs, input);
+
+        self.exec
+            .after_hooks_with(&specs, start, result, |finished| {
+                hook::output(
+                    String::from_utf8_lossy(&finished.stdout).into_owned(),
+                    String::from_utf8_lossy(&finished.stderr).into_owned(),
+                    finished.status,
+                )
+            })
+    }
+
+    fn run_resolved(&mut self, specs: &[CommandSpec], input: &[u8]) -> Result<Finished, ExecError> {
+        let mut finished = Finished {
+            stdout: input.to_vec(),
+            stderr: Vec::new(),
+            status: 0,
+        };
+
+        for spec in specs {
+            let stage = match spec.get_context() {
+                Some(Context::Remote {
+                    host,
+                    config,
+                    proxy,
+                    settings,
+                }) => {
+                    let jumps = settings
+                        .as_deref()
+                        .is_some_and(|settings| !settings.jump_hosts.is_empty());
+
+                    if config.is_some() || proxy.is_some() || jumps {
+                        return Err(ExecError::Execution(
+                            "ssh config files, proxies and jump hosts are not supported by the native backend"
+                                .into(),
+                        ));
+                    }
+
+                    self.run_remote(host, settings.as_deref(), spec, &finished.stdout)
+                }
+                _ => self.run_local(spec, &finished.stdout),
+            }?;
+
+            finished.stderr.extend(stage.stderr);
+            finished.stdout = stage.stdout;
+            finished.status = stage.status;
+        }
+
+        Ok(finished)
+    }
+
+    fn run_bytes(&mut self, specs: &[CommandSpec], input: &[u8]) -> Result<Vec<u8>, ExecError> {
+        let finished = self.run_specs(specs, input)?;
+
+        match finished.status {
+            code if self.exec.accepts(specs.last(), code) => Ok(finished.stdout),
+            code => {
+                let error = match String::from_utf8(finished.stderr) {
+                    Ok(s) => ExecError::TerminationWithError(code, s),
+                    Err(_) => ExecError::TerminationWithErrorCode(code),
+                };
+
+                Err(match specs.last() {
+                    Some(spec) => spec.classify(error),
+                    None => error,
+                })
+            }
+        }
+    }
+
+    fn run_text(&mut self, specs: &[CommandSpec], input: &[u8]) -> Result<String, ExecError> {
+        let output = self.run_bytes(specs, input)?;
+
+        self.exec.text(output)
+    }
+
+    fn run_full(&mut self, specs: &[CommandSpec]) -> Result<ExecOutput, ExecError> {
+        let start = Instant::now();
+        let finished = self.run_specs(specs, &[])?;
+
+        Ok(ExecOutput {
+            stdout: self.exec.text(finished.stdout)?,
+            stderr: self
+                .exec
+                .postprocess(String::from_utf8_lossy(&finished.stderr).into_owned()),
+            status: finished.status,
+            duration: start.elapsed(),
+        })
+    }
+
+    fn run_local(&self, spec: &CommandSpec, input: &[u8]) -> Result<Finished, ExecError> {
+        let mut com = self.exec.command(spec)?;
+        let mut tree = self.exec.process_tree()?;
+
+        com.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        tree.prepare(spec, &mut com);
+        tree.push(com.spawn()?)?;
+
+        let mut stdin = tree
+            .last_mut()
+            .and_then(|child| child.stdin.take())
+            .ok_or(ExecError::Chaining)?;
+        let (output, _) = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                // the command may exit without reading its input
+                let _ = stdin.write_all(input);
+            });
+            tree.wait_with_output_timeout(self.exec.timeout, self.exec.cancellation.as_ref())
+        })?;
+
+        Ok(Finished {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            status: output.status.code().ok_or(ExecError::TerminationBySignal)?,
+        })
+    }
+
+    fn run_remote(
+        &mut self,
+        host: &str,
+        settings: Option<&SshSettings>,
+        spec: &CommandSpec,
+        input: &[u8],
+    ) -> Result<Finished, ExecError> {
+        let line = quote::join_os(
+            self.exec
+                .context_argv(spec)?
+                .iter()
+                .map(|part| part.as_os_str()),
+        )?;
+        let deadline = self.exec.timeout.map(|timeout| Instant::now() + timeout);
+        let timeout = self.exec.timeout;
+        let session = self.session(host, settings)?;
+        let transport = |e: ssh2::Error| ExecError::Transport(host.to_string(), e.to_string());
+        let mut channel = session.channel_session().map_err(transport)?;
+
+        channel.exec(&line).map_err(transport)?;
+
+        // input and output are transferred together, so that neither side blocks the other
+        session.set_blocking(false);
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut written = 0;
+        let mut eof_sent = false;
+        let mut done = [false, false];
+        let res = loop {
+            let mut progress = false;
+
+            if written < input.len() {
+                match channel.write(&input[written..]) {
+                    Ok(len) => {
+                        written += len;
+                        progress = true;
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    // the command may exit without reading its input
+                    Err(_) => written = input.len(),
+                }
+            } else if !eof_sent {
+                match channel.send_eof() {
+                    Ok(()) => {
+                        eof_sent = true;
+                        progress = true;
+                    }
+                    Err(e) if e.code() == ErrorCode::Session(EAGAIN) => {}
+                    Err(_) => eof_sent = true,
+                }
+            }
+
+            for (index, buffer) in [&mut stdout, &mut stderr].into_iter().enumerate() {
+                let mut stream = channel.stream(index as i32);
+                let mut chunk = [0u8; 8192];
+
+                while !done[index] {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => done[index] = true,
+                        Ok(len) => {
+                            buffer.extend_from_slice(&chunk[..len]);
+                            progress = true;
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+
+            if done == [true, true] && channel.eof() {
+                break Ok(());
+            }
+
+            if let (Some(deadline), Some(timeout)) = (deadline, timeout) {
+                if Instant::now() >= deadline {
+                    break Err(ExecError::Timeout(timeout));
+                }
+            }
+
+            if !progress {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        session.set_blocking(true);
+
+        if let Err(e) = res {
+            let _ = channel.close();
+            return Err(e);
+        }
+
+        channel.wait_close().map_err(transport)?;
+
+        if channel
+            .exit_signal()
+            .map_err(transport)?
+            .exit_signal
+            .is_some()
+        {
+            return Err(ExecError::TerminationBySignal);
+        }
+
+        Ok(Finished {
+            stdout,
+            stderr,
+            status: channel.exit_status().map_err(transport)?,
+        })
+    }
+
+    /// Returns the session to the host, connecting and authenticating if necessary
+    ///
+    /// Sessions are only shared by commands with the same host and settings, since the settings
+    /// determine the address, the user and the authentication.
+    fn session(
+        &mut self,
+        host: &str,
+        settings: Option<&SshSettings>,
+    ) -> Result<&Session, ExecError> {
+        let key = session_key(host, settings);
+
+        if !self.sessions.contains_key(&key) {
+            let session = self.connect(host, settings)?;
+
+            self.sessions
+                .insert(key.clone(), (host.to_string(), session));
+        }
+
+        self.sessions
+            .get(&key)
+            .map(|(_, session)| session)
+            .ok_or(ExecError::Chaining)
+    }
+
+    fn connect(&self, host: &str, settings: Option<&SshSettings>) -> Result<Session, ExecError> {
+        let transport =
+            |e: &dyn std::fmt::Display| ExecError::Transport(host.into(), e.to_string());
+        let address = settings
+            .and_then(|settings| settings.host_name.as_deref())
+            .unwrap_or(host);
+        let port = settings.and_then(|settings| settings.port).unwrap_or(22);
+        let socket = (address, port)
+            .to_socket_addrs()
+            .map_err(|e| transport(&e))?
+            .next()
+            .ok_or_else(|| transport(&"Could not resolve hostname"))?;
+        let stream =
+            TcpStream::connect_timeout(&socket, self.connect_timeout).map_err(|e| transport(&e))?;
+        let mut session = Session::new().map_err(|e| transport(&e))?;
+
+        session.set_timeout(self.connect_timeout.as_millis() as u32);
+        session.set_tcp_stream(stream);
+        session.handshake().map_err(|e| transport(&e))?;
+        self.verify_host_key(&session, host, address, port)?;
+
+        let user = match settings.and_then(|settings| settings.user.clone()) {
+            Some(user) => user,
+            None => SshExec::current_user()?,
+        };
+        let identity_file = settings.and_then(|settings| settings.identity_file.as_deref());
+
+        match identity_file {
+            Some(identity_file) => {
+                let _ = session.userauth_pubkey_file(&user, None, identity_file, None);
+            }
+            None => {
+                if session.userauth_agent(&user).is_err() {
+                    for key in ["id_ed25519", "id_ecdsa", "id_rsa"] {
+                        let path = SshExec::home()?.join(".ssh").join(key);
+
+                        if path.exists()
+                            && session
+                                .userauth_pubkey_file(&user, None, &path, None)
+                                .is_ok()
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if !session.authenticated() {
+            return Err(ExecError::Authentication(host.to_string()));
+        }
+
+        // commands may run for longer than the connection takes
+        session.set_timeout(0);
+        Ok(session)
+    }
+
+    fn verify_host_key(
+        &self,
+        session: &Session,
+        host: &str,
+        address: &str,
+        port: u16,
+    ) -> Result<(), ExecError> {
+        let path = match &self.exec.known_hosts {
+            Some(known_hosts) => known_hosts.path().to_path_buf(),
+            None => SshExec::home()?.join(".ssh").join("known_hosts"),
+        };
+        let mut known_hosts = session
+            .known_hosts()
+            .map_err(|e| ExecError::Transport(host.to_string(), e.to_string()))?;
+
+        // a missing file does not contain the key either
+        let _ = known_hosts.read_file(&path, KnownHostFileKind::OpenSSH);
+
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| ExecError::HostKeyUnknown(host.to_string()))?;
+
+        match known_hosts.check_port(address, port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(ExecError::HostKeyMismatch(host.to_string())),
+            CheckResult::NotFound | CheckResult::Failure => {
+                Err(ExecError::HostKeyUnknown(host.to_string()))
+            }
+        }
+    }
+
+    fn home() -> Result<PathBuf, ExecError> {
+        std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .ok_or_else(|| ExecError::Execution("home directory is not set".into()))
+    }
+
+    fn current_user() -> Result<String, ExecError> {
+        std::env::var("USER")
+            .map_err(|_| ExecError::Execution("user for the ssh connection is not set".into()))
+    }
+}
+
+impl Exec for SshExec {
+    fn exec(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        self.run_text(&CommandExec::specs(&[(command, args, context)]), &[])
+    }
+
+    fn exec_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        self.run_text(&CommandExec::specs(commands), &[])
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        self.run_text(pipeline.stages(), pipeline.get_input().unwrap_or_default())
+    }
+
+    fn exec_bytes(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        self.run_bytes(&CommandExec::specs(&[(command, args, context)]), &[])
+    }
+
+    fn exec_piped_bytes(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        self.run_bytes(&CommandExec::specs(commands), &[])
+    }
+
+    fn exec_full(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        self.run_full(&CommandExec::specs(&[(command, args, context)]))
+    }
+
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        self.run_full(&CommandExec::specs(commands))
+    }
+}
+
+/// Returns the key of the session to the host with the settings
+pub(crate) fn session_key(host: &str, settings: Option<&SshSettings>) -> String {
+    format!("{:?}", (host, settings))
+}
@@ -0,0 +1,42 @@
+use std::ffi::OsString;
+
+/// Prefixes the command line with the corresponding `choom` invocation
+pub(crate) fn wrap(adjustment: i16, argv: Vec<OsString>) -> Vec<OsString> {
+    [
+        "choom".into(),
+        "-n".into(),
+        adjustment.to_string().into(),
+        "--".into(),
+    ]
+    .into_iter()
+    .chain(argv)
+    .collect()
+}
+
+/// Sets the adjustment in the child before the command is executed; it is inherited by wrappers such as sudo
+#[cfg(target_os = "linux")]
+pub(crate) fn apply(adjustment: i16, com: &mut std::process::Command) {
+    // formatted up front, as the child must not allocate
+    let value = adjustment.to_string().into_bytes();
+
+    // SAFETY: open, write and close are async-signal-safe and the closure does not allocate
+    unsafe {
+        std::os::unix::process::CommandExt::pre_exec(com, move || {
+            let fd = libc::open(c"/proc/self/oom_score_adj".as_ptr(), libc::O_WRONLY);
+
+            if fd == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            let res = libc::write(fd, value.as_ptr() as *const libc::c_void, value.len());
+            let error = std::io::Error::last_os_error();
+
+            libc::close(fd);
+
+            match res {
+                -1 => Err(error),
+                _ => Ok(()),
+            }
+        })
+    };
+}
@@ -0,0 +1,67 @@
+use crate::{Cmd, CommandExec, ExecError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+impl CommandExec {
+    /// Runs independent commands concurrently and returns their results in the given order
+    ///
+    /// Every worker thread uses a clone of the executor; hence, all settings apply to each command.
+    ///
+    /// * `commands` - the commands to be executed
+    /// * `max_concurrency` - maximum number of commands running at the same time
+    ///
+    pub fn exec_parallel(
+        &self,
+        commands: &[Cmd],
+        max_concurrency: usize,
+    ) -> Vec<Result<String, ExecError>> {
+        run_bounded(self, commands, max_concurrency, |exec, _, command| {
+            exec.exec_spec(command)
+        })
+    }
+}
+
+/// Runs the function for all jobs on a bounded number of threads; returns the results in order
+///
+/// Every thread uses a clone of the executor.
+///
+/// * `exec` - the executor cloned for the threads
+/// * `jobs` - the jobs passed to the function with their index
+/// * `limit` - maximum number of threads
+/// * `run` - the function executing a job
+///
+pub(crate) fn run_bounded<J: Sync, T: Send>(
+    exec: &CommandExec,
+    jobs: &[J],
+    limit: usize,
+    run: impl Fn(&mut CommandExec, usize, &J) -> Result<T, ExecError> + Sync,
+) -> Vec<Result<T, ExecError>> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<T, ExecError>>>> =
+        Mutex::new((0..jobs.len()).map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..limit.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| {
+                let mut exec = exec.clone();
+
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(job) = jobs.get(index) else {
+                        return;
+                    };
+                    let result = run(&mut exec, index, job);
+
+                    results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|result| result.unwrap_or(Err(ExecError::Chaining)))
+        .collect()
+}
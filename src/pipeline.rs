@@ -0,0 +1,163 @@
+use crate::{hook, CommandExec, CommandSpec, ExecError};
+
+/// Short name of `CommandSpec` for building commands, e.g. `Cmd::new("grep").arg("name")`
+pub type Cmd = CommandSpec;
+
+/// Commands piping stdout of one command into stdin of the next
+///
+/// The pipeline can be built up incrementally with `push` or in one expression with `pipe`.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Pipeline {
+    stages: Vec<CommandSpec>,
+    input: Option<Vec<u8>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a command and returns the pipeline
+    ///
+    /// * `command` - the command reading the output of the preceding one
+    ///
+    pub fn pipe(mut self, command: CommandSpec) -> Self {
+        self.stages.push(command);
+        self
+    }
+
+    /// Appends a command
+    ///
+    /// * `command` - the command reading the output of the preceding one
+    ///
+    pub fn push(&mut self, command: CommandSpec) {
+        self.stages.push(command);
+    }
+
+    /// Sets the data written to stdin of the first command
+    ///
+    /// * `input` - the data; ignored if the first command reads from a file
+    ///
+    pub fn input(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.input = Some(input.into());
+        self
+    }
+
+    pub fn get_input(&self) -> Option<&[u8]> {
+        self.input.as_deref()
+    }
+
+    pub fn stages(&self) -> &[CommandSpec] {
+        &self.stages
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+impl From<CommandSpec> for Pipeline {
+    fn from(command: CommandSpec) -> Self {
+        Pipeline {
+            stages: vec![command],
+            input: None,
+        }
+    }
+}
+
+impl FromIterator<CommandSpec> for Pipeline {
+    fn from_iter<I: IntoIterator<Item = CommandSpec>>(iter: I) -> Self {
+        Pipeline {
+            stages: iter.into_iter().collect(),
+            input: None,
+        }
+    }
+}
+
+/// Outcome of a single stage of a pipeline
+///
+/// * `status` - exit code; `None` if the stage was terminated by a signal
+/// * `stderr` - captured standard error of the stage
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct StageStatus {
+    pub status: Option<i32>,
+    pub stderr: String,
+}
+
+/// Output of a pipeline together with the outcome of every stage
+///
+/// * `stdout` - captured standard output of the last stage
+/// * `stages` - outcome of every stage, in the order of the pipeline
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct PipelineOutput {
+    pub stdout: String,
+    pub stages: Vec<StageStatus>,
+}
+
+impl PipelineOutput {
+    /// Checks whether all stages finished with status code 0
+    pub fn success(&self) -> bool {
+        self.stages.iter().all(|stage| stage.status == Some(0))
+    }
+}
+
+impl CommandExec {
+    /// Runs the pipeline and returns the output of the last stage and the outcome of every stage
+    ///
+    /// The stderr of all stages is captured. Statuses other than 0 are not an error.
+    ///
+    /// * `pipeline` - the commands piped into each other
+    ///
+    pub fn run_stages(&mut self, pipeline: &Pipeline) -> Result<PipelineOutput, ExecError> {
+        let specs = self.resolve(pipeline.stages())?;
+        let start = std::time::Instant::now();
+        let result = self.run_stages_resolved(&specs, pipeline);
+
+        self.after_hooks_with(&specs, start, result, |output| {
+            hook::output(
+                output.stdout.clone(),
+                output
+                    .stages
+                    .iter()
+                    .map(|stage| stage.stderr.as_str())
+                    .collect(),
+                output
+                    .stages
+                    .last()
+                    .and_then(|stage| stage.status)
+                    .unwrap_or(-1),
+            )
+        })
+    }
+
+    fn run_stages_resolved(
+        &mut self,
+        specs: &[CommandSpec],
+        pipeline: &Pipeline,
+    ) -> Result<PipelineOutput, ExecError> {
+        let mut input = pipeline.get_input();
+        let (output, stages) =
+            self.run_specs_output(specs, true, input.as_mut().map(|input| input as _), None)?;
+        let stage = |status: std::process::ExitStatus, stderr: &[u8]| StageStatus {
+            status: status.code(),
+            stderr: self.postprocess(String::from_utf8_lossy(stderr).into_owned()),
+        };
+        let mut stages: Vec<StageStatus> = stages
+            .iter()
+            .map(|(status, stderr)| stage(*status, stderr))
+            .collect();
+
+        stages.push(stage(output.status, &output.stderr));
+
+        Ok(PipelineOutput {
+            stdout: self.text(output.stdout)?,
+            stages,
+        })
+    }
+}
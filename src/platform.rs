@@ -0,0 +1,179 @@
+use crate::{CommandExec, CommandSpec, Context, ExecError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub(crate) type Resolver = Arc<dyn Fn(&Platform, CommandSpec) -> CommandSpec + Send + Sync>;
+pub(crate) type Cache = Arc<Mutex<HashMap<String, Platform>>>;
+
+/// Operating system family of a context
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OsFamily {
+    Linux,
+    MacOs,
+    FreeBsd,
+    OpenBsd,
+    NetBsd,
+    Windows,
+    /// Any other system, named as reported by `uname -s`
+    Other(String),
+}
+
+/// Platform commands are executed on in a context
+///
+/// * `os` - the operating system family
+/// * `arch` - machine hardware name, e.g. `x86_64` or `arm64`; empty if unknown
+/// * `shell` - shell available for command lines, e.g. `bash`, `sh` or `cmd`
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Platform {
+    pub os: OsFamily,
+    pub arch: String,
+    pub shell: String,
+}
+
+impl Platform {
+    /// Checks whether the system is a BSD descendant, whose tools differ from their GNU counterparts
+    pub fn is_bsd(&self) -> bool {
+        matches!(
+            self.os,
+            OsFamily::MacOs | OsFamily::FreeBsd | OsFamily::OpenBsd | OsFamily::NetBsd
+        )
+    }
+
+    /// Returns the name of the GNU variant of a tool on the platform
+    ///
+    /// On BSD descendants, GNU tools are usually installed with a `g` prefix (e.g. `gstat`).
+    ///
+    /// * `tool` - name of the tool on GNU systems
+    ///
+    pub fn gnu_tool(&self, tool: &str) -> String {
+        match self.is_bsd() {
+            true => format!("g{}", tool),
+            false => tool.to_string(),
+        }
+    }
+
+    fn parse_uname(output: &str) -> Self {
+        let mut lines = output.lines().map(str::trim);
+        let os = match lines.next().unwrap_or_default() {
+            "Linux" => OsFamily::Linux,
+            "Darwin" => OsFamily::MacOs,
+            "FreeBSD" => OsFamily::FreeBsd,
+            "OpenBSD" => OsFamily::OpenBsd,
+            "NetBSD" => OsFamily::NetBsd,
+            os if os.starts_with("MINGW") || os.starts_with("MSYS") || os.starts_with("CYGWIN") => {
+                OsFamily::Windows
+            }
+            os => OsFamily::Other(os.to_string()),
+        };
+        let arch = lines.next().unwrap_or_default().to_string();
+        let shell = match lines.next() {
+            Some(path) if path.ends_with("/bash") => "bash",
+            _ => "sh",
+        };
+
+        Platform {
+            os,
+            arch,
+            shell: shell.to_string(),
+        }
+    }
+}
+
+impl CommandExec {
+    /// Determines the platform of the context; the result is cached per context
+    ///
+    /// POSIX systems are identified by `uname`. If that fails, the context is assumed to be a
+    /// Windows system, which is confirmed with `cmd /c ver`.
+    ///
+    /// * `context` - the context to be inspected
+    ///
+    pub fn detect_platform(&mut self, context: Option<&Context>) -> Result<Platform, ExecError> {
+        let key = format!("{:?}", context);
+
+        if let Some(platform) = self.lock_platforms().get(&key) {
+            return Ok(platform.clone());
+        }
+
+        let with_context = |spec: CommandSpec| match context {
+            Some(context) => spec.context(context.clone()),
+            None => spec,
+        };
+        // resolvers and retries are bypassed; detection must not depend on its own result
+        let uname = self
+            .run_specs_once(
+                &[with_context(
+                    CommandSpec::new("sh").args(["-c", "uname -s; uname -m; command -v bash"]),
+                )],
+                None,
+                None,
+            )
+            .and_then(|output| self.text(output));
+        let platform = match uname {
+            Ok(output) => Platform::parse_uname(&output),
+            Err(e) => {
+                let ver = self
+                    .run_specs_once(
+                        &[with_context(CommandSpec::new("cmd").args(["/c", "ver"]))],
+                        None,
+                        None,
+                    )
+                    .and_then(|output| self.text(output));
+
+                match ver {
+                    Ok(output) if output.contains("Windows") => Platform {
+                        os: OsFamily::Windows,
+                        arch: String::new(),
+                        shell: "cmd".to_string(),
+                    },
+                    _ => return Err(e),
+                }
+            }
+        };
+
+        self.lock_platforms().insert(key, platform.clone());
+        Ok(platform)
+    }
+
+    /// Adapts every command to the platform of its context before it is executed
+    ///
+    /// The platform is detected once per context. Applies to all executions of specs and
+    /// pipelines, including the ones of the `Exec` trait.
+    ///
+    /// * `resolver` - returns the command to be executed on the platform instead of the given one
+    ///
+    pub fn resolver(
+        mut self,
+        resolver: impl Fn(&Platform, CommandSpec) -> CommandSpec + Send + Sync + 'static,
+    ) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Applies the resolver and the `before` callbacks of the hooks to the specs
+    pub(crate) fn resolve(&mut self, specs: &[CommandSpec]) -> Result<Vec<CommandSpec>, ExecError> {
+        let Some(resolver) = self.resolver.clone() else {
+            return self.before_hooks(specs.to_vec());
+        };
+
+        let specs = specs
+            .iter()
+            .map(|spec| {
+                let platform = self.detect_platform(spec.get_context())?;
+
+                Ok(resolver(&platform, spec.clone()))
+            })
+            .collect::<Result<Vec<_>, ExecError>>()?;
+
+        self.before_hooks(specs)
+    }
+
+    /// Applies the resolver and the `before` callbacks of the hooks to a single spec
+    pub(crate) fn resolve_one(&mut self, spec: &CommandSpec) -> Result<CommandSpec, ExecError> {
+        Ok(self.resolve(std::slice::from_ref(spec))?.remove(0))
+    }
+
+    fn lock_platforms(&self) -> std::sync::MutexGuard<'_, HashMap<String, Platform>> {
+        self.platforms.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{CommandExec, ExecError, SudoKeepAlive};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+
+type Factory = Box<dyn Fn() -> Result<CommandExec, ExecError> + Send + Sync>;
+
+struct State {
+    idle: Vec<CommandExec>,
+    missing: usize,
+}
+
+/// Pool of prepared executors for concurrent work
+///
+/// The executors are created up front by a factory, which is the place to establish expensive
+/// per-host resources. Executors are handed out one at a time and return to the pool when the
+/// guard is dropped. Executors marked as unhealthy are replaced by a fresh one.
+pub struct ExecPool {
+    state: Mutex<State>,
+    available: Condvar,
+    factory: Factory,
+    _sudo: Option<SudoKeepAlive>,
+}
+
+impl ExecPool {
+    /// Creates a pool and all of its executors
+    ///
+    /// * `size` - number of executors in the pool
+    /// * `factory` - creates and prepares a single executor
+    ///
+    pub fn new(
+        size: usize,
+        factory: impl Fn() -> Result<CommandExec, ExecError> + Send + Sync + 'static,
+    ) -> Result<Self, ExecError> {
+        let idle = (0..size)
+            .map(|_| factory())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ExecPool {
+            state: Mutex::new(State { idle, missing: 0 }),
+            available: Condvar::new(),
+            factory: Box::new(factory),
+            _sudo: None,
+        })
+    }
+
+    /// Keeps the sudo credentials valid for as long as the pool exists
+    ///
+    /// * `sudo` - guard refreshing the sudo timestamp
+    ///
+    pub fn sudo_keep_alive(mut self, sudo: SudoKeepAlive) -> Self {
+        self._sudo = Some(sudo);
+        self
+    }
+
+    /// Takes an executor from the pool, waiting until one becomes available
+    pub fn get(&self) -> Result<PooledExec<'_>, ExecError> {
+        let mut state = self.lock();
+
+        loop {
+            if let Some(exec) = state.idle.pop() {
+                return Ok(PooledExec::new(self, exec));
+            }
+
+            if state.missing > 0 {
+                state.missing -= 1;
+                drop(state);
+                return self.replace();
+            }
+
+            state = self
+                .available
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Takes an executor from the pool if one is available immediately
+    pub fn try_get(&self) -> Option<PooledExec<'_>> {
+        self.lock()
+            .idle
+            .pop()
+            .map(|exec| PooledExec::new(self, exec))
+    }
+
+    /// Number of executors currently available
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    fn replace(&self) -> Result<PooledExec<'_>, ExecError> {
+        match (self.factory)() {
+            Ok(exec) => Ok(PooledExec::new(self, exec)),
+            Err(e) => {
+                self.release(None);
+                Err(e)
+            }
+        }
+    }
+
+    fn release(&self, exec: Option<CommandExec>) {
+        let mut state = self.lock();
+
+        match exec {
+            Some(exec) => state.idle.push(exec),
+            None => state.missing += 1,
+        }
+
+        self.available.notify_one();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Executor borrowed from an `ExecPool`
+pub struct PooledExec<'a> {
+    pool: &'a ExecPool,
+    exec: Option<CommandExec>,
+    healthy: bool,
+}
+
+impl<'a> PooledExec<'a> {
+    fn new(pool: &'a ExecPool, exec: CommandExec) -> Self {
+        PooledExec {
+            pool,
+            exec: Some(exec),
+            healthy: true,
+        }
+    }
+
+    /// Marks the executor as broken; it is replaced instead of being returned to the pool
+    pub fn mark_unhealthy(&mut self) {
+        self.healthy = false;
+    }
+}
+
+impl Deref for PooledExec<'_> {
+    type Target = CommandExec;
+
+    fn deref(&self) -> &Self::Target {
+        self.exec.as_ref().expect("executor is present until drop")
+    }
+}
+
+impl DerefMut for PooledExec<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.exec.as_mut().expect("executor is present until drop")
+    }
+}
+
+impl Drop for PooledExec<'_> {
+    fn drop(&mut self) {
+        let exec = self.exec.take().filter(|_| self.healthy);
+
+        self.pool.release(exec);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{Context, Exec, ExecError};
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Arg {
+    Fixed(String),
+    Param(String),
+}
+
+/// Command with fixed arguments and named parameters bound at execution time
+///
+/// Parameters are checked for control characters before they are passed on as single arguments;
+/// quoting for remote contexts is done by the executor. Hence, user-influenced values cannot change
+/// the structure of the command.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PreparedCommand {
+    program: String,
+    args: Vec<Arg>,
+}
+
+impl PreparedCommand {
+    /// Creates a prepared command
+    ///
+    /// * `program` - the program to be executed
+    ///
+    pub fn new(program: &str) -> Self {
+        PreparedCommand {
+            program: program.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Appends a fixed argument; it is passed on as is
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(Arg::Fixed(arg.to_string()));
+        self
+    }
+
+    /// Appends a placeholder for a parameter, which is bound when the command is executed
+    pub fn param(mut self, name: &str) -> Self {
+        self.args.push(Arg::Param(name.to_string()));
+        self
+    }
+
+    /// Binds the parameters and executes the command
+    ///
+    /// * `exec` - the executor running the command
+    /// * `params` - name-value pairs for all placeholders of the command
+    /// * `context` - either a local or a remote context
+    ///
+    pub fn exec<E: Exec + ?Sized>(
+        &self,
+        exec: &mut E,
+        params: &[(&str, &str)],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        let args = self.bind(params)?;
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+        exec.exec(&self.program, &args, context)
+    }
+
+    fn bind(&self, params: &[(&str, &str)]) -> Result<Vec<String>, ExecError> {
+        let params: HashMap<&str, &str> = params.iter().cloned().collect();
+
+        self.args
+            .iter()
+            .map(|arg| match arg {
+                Arg::Fixed(value) => Ok(value.clone()),
+                Arg::Param(name) => {
+                    let value = params
+                        .get(name.as_str())
+                        .ok_or_else(|| ExecError::UnboundParameter(name.clone()))?;
+
+                    if value.chars().any(|c| c.is_control()) {
+                        return Err(ExecError::InvalidParameter(name.clone()));
+                    }
+
+                    Ok(value.to_string())
+                }
+            })
+            .collect()
+    }
+}
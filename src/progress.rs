@@ -0,0 +1,38 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+pub(crate) type Callback = Arc<dyn Fn(&ProgressEvent) + Send + Sync>;
+
+/// Summary of a finished batch
+///
+/// * `commands` - number of commands in the batch
+/// * `failed` - number of commands that did not finish with status code 0
+/// * `duration` - wall-clock time of the batch
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BatchSummary {
+    pub commands: usize,
+    pub failed: usize,
+    pub duration: Duration,
+}
+
+/// Progress of a batch of commands, i.e., the stages of a pipeline or the hosts of a fleet run
+#[derive(Debug, PartialEq, Clone)]
+pub enum ProgressEvent {
+    /// The batch is about to start
+    BatchStarted { commands: usize },
+    /// The command with the index was started
+    CommandStarted { index: usize },
+    /// The command with the index terminated
+    ///
+    /// * `bytes` - size of the captured output; `None` if the output was passed on to the next stage
+    /// * `status` - exit code; `None` if the command was terminated by a signal or could not be run
+    ///
+    StageFinished {
+        index: usize,
+        bytes: Option<u64>,
+        status: Option<i32>,
+    },
+    /// All commands of the batch terminated
+    BatchFinished { summary: BatchSummary },
+}
@@ -0,0 +1,63 @@
+use crate::ExecError;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+/// Attaches the command to a new pseudo-terminal and returns the master side
+///
+/// The terminal becomes stdin, stdout and stderr as well as the controlling terminal of the
+/// command, so that programs reading from `/dev/tty` (e.g. `passwd`) interact with it, too.
+pub(crate) fn attach(com: &mut std::process::Command) -> Result<File, ExecError> {
+    let mut master: RawFd = -1;
+    let mut slave: RawFd = -1;
+
+    // SAFETY: the pointers are valid for the duration of the call; name, termios and size are optional
+    let res = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+
+    if res == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    // SAFETY: openpty returned two newly opened descriptors owned by nobody else
+    let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+    com.stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+
+    // SAFETY: setsid and ioctl are async-signal-safe and the closure does not allocate
+    unsafe {
+        std::os::unix::process::CommandExt::pre_exec(com, || {
+            // fails if the spec already started a new session, which is fine
+            libc::setsid();
+
+            match libc::ioctl(0, libc::TIOCSCTTY as _, 0) {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        })
+    };
+
+    Ok(master)
+}
+
+/// Reader of the master side reporting the end of the output once all processes closed the terminal
+pub(crate) struct PtyReader(pub(crate) File);
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf) {
+            // Linux signals a hung-up terminal with EIO instead of end of file
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            res => res,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::ExecError;
+use std::ffi::{OsStr, OsString};
+
+/// Treatment of the command and the arguments of a spec by shells within the context
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Quoting {
+    /// Every argument is passed as is; command lines for remote shells are quoted accordingly
+    #[default]
+    Posix,
+    /// The command and the arguments are joined with spaces and interpreted by `sh -c` in the
+    /// context, e.g. for intentional pipes, globs or variable expansions
+    None,
+}
+
+impl Quoting {
+    /// Returns the command line to be executed in the context
+    pub(crate) fn argv(&self, argv: Vec<OsString>) -> Vec<OsString> {
+        match self {
+            Quoting::Posix => argv,
+            Quoting::None => {
+                let mut script = OsString::new();
+
+                for (index, part) in argv.iter().enumerate() {
+                    if index > 0 {
+                        script.push(" ");
+                    }
+
+                    script.push(part);
+                }
+
+                vec!["sh".into(), "-c".into(), script]
+            }
+        }
+    }
+}
+
+/// Quotes a string for a POSIX shell
+///
+/// The string is wrapped in single quotes; embedded single quotes are closed, escaped and reopened.
+/// Strings consisting only of characters without special meaning are returned unchanged.
+pub fn quote(s: &str) -> String {
+    let is_plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+
+    if is_plain {
+        return s.to_string();
+    }
+
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Quotes all parts and joins them into a single command line
+pub fn join<'a>(parts: impl IntoIterator<Item = &'a str>) -> String {
+    parts.into_iter().map(quote).collect::<Vec<_>>().join(" ")
+}
+
+/// Quotes an OS string for a POSIX shell
+///
+/// POSIX shells offer no portable way of expressing arbitrary bytes in a quoted string. Hence,
+/// strings that are not valid UTF-8 are rejected instead of being converted lossily.
+pub fn quote_os(s: &OsStr) -> Result<String, ExecError> {
+    s.to_str()
+        .map(quote)
+        .ok_or_else(|| ExecError::NonUtf8Argument(s.to_os_string()))
+}
+
+/// Quotes all parts and joins them into a single command line; fails on parts that are not valid UTF-8
+pub fn join_os<'a>(parts: impl IntoIterator<Item = &'a OsStr>) -> Result<String, ExecError> {
+    Ok(parts
+        .into_iter()
+        .map(quote_os)
+        .collect::<Result<Vec<_>, _>>()?
+        .join(" "))
+}
+
+/// Quotes all parts following the Windows conventions and joins them into a single command line
+pub(crate) fn join_windows_os<'a>(
+    parts: impl IntoIterator<Item = &'a OsStr>,
+) -> Result<String, ExecError> {
+    Ok(parts
+        .into_iter()
+        .map(|part| {
+            part.to_str()
+                .map(quote_windows)
+                .ok_or_else(|| ExecError::NonUtf8Argument(part.to_os_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .join(" "))
+}
+
+/// Quotes a string for PowerShell
+///
+/// The string is wrapped in single quotes, within which only single quotes need to be doubled.
+pub(crate) fn quote_powershell(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Quotes a string following the conventions of the Microsoft C runtime for command lines
+pub fn quote_windows(s: &str) -> String {
+    if !s.is_empty() && !s.contains([' ', '\t', '"']) {
+        return s.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+
+    for c in s.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // backslashes preceding a quote and the quote itself need escaping
+                quoted.extend(std::iter::repeat_n('\\', 2 * backslashes + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+
+    // backslashes before the closing quote must not escape it
+    quoted.extend(std::iter::repeat_n('\\', 2 * backslashes));
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,72 @@
+use crate::{quote, ExecError};
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// File an output stream of a command is written to
+///
+/// Locally, the file is opened by the calling process, also for commands executed as a different
+/// user. For remote and container contexts, the redirection is performed by a shell within the
+/// context.
+///
+/// * `path` - path of the file
+/// * `append` - append to the file instead of truncating it
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Redirect {
+    pub path: PathBuf,
+    pub append: bool,
+}
+
+impl Redirect {
+    pub(crate) fn open(&self) -> Result<File, ExecError> {
+        Ok(std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(self.append)
+            .truncate(!self.append)
+            .open(&self.path)?)
+    }
+
+    pub(crate) fn shell(&self, fd: u8) -> Result<String, ExecError> {
+        let operator = match self.append {
+            true => ">>",
+            false => ">",
+        };
+
+        Ok(format!(
+            " {}{} {}",
+            fd,
+            operator,
+            quote::quote_os(self.path.as_os_str())?
+        ))
+    }
+}
+
+/// Prefixes the command line with a shell performing the redirections
+pub(crate) fn wrap(
+    stdin: Option<&Path>,
+    stdout: Option<&Redirect>,
+    stderr: Option<&Redirect>,
+    argv: Vec<OsString>,
+) -> Result<Vec<OsString>, ExecError> {
+    let mut script = String::from("exec \"$@\"");
+
+    if let Some(stdin) = stdin {
+        script.push_str(&format!(" < {}", quote::quote_os(stdin.as_os_str())?));
+    }
+
+    if let Some(stdout) = stdout {
+        script.push_str(&stdout.shell(1)?);
+    }
+
+    if let Some(stderr) = stderr {
+        script.push_str(&stderr.shell(2)?);
+    }
+
+    Ok(["sh", "-c", &script, "sh"]
+        .into_iter()
+        .map(OsString::from)
+        .chain(argv)
+        .collect())
+}
@@ -0,0 +1,333 @@
+use crate::{quote, Context, Exec, ExecError, ExecOutput, Pipeline};
+use std::time::Duration;
+
+/// Command of a recorded execution
+///
+/// * `command` - the program
+/// * `args` - the arguments of the program
+/// * `context` - the context the command was executed in
+///
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordedCommand {
+    pub command: String,
+    pub args: Vec<String>,
+    pub context: Option<Context>,
+}
+
+/// Execution of a command or a pipeline and its result, e.g. stored in a fixture file
+///
+/// Serializable with the `serde` feature, so that any format (JSON, YAML, ...) can be used for
+/// the fixtures.
+///
+/// * `commands` - the commands; more than one for pipelines
+/// * `stdout` - output of the last command; output recorded as bytes is converted lossily
+/// * `stderr` - error output, if returned by the executor
+/// * `status` - exit status of the last command
+/// * `error` - message of an error other than a failure with a status code
+///
+#[derive(Debug, Default, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Recording {
+    pub commands: Vec<RecordedCommand>,
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32,
+    pub error: Option<String>,
+}
+
+impl Recording {
+    fn new(commands: Vec<RecordedCommand>, result: Result<ExecOutput, &ExecError>) -> Self {
+        let recording = Recording {
+            commands,
+            ..Recording::default()
+        };
+
+        match result {
+            Ok(output) => Recording {
+                stdout: output.stdout,
+                stderr: output.stderr,
+                status: output.status,
+                ..recording
+            },
+            Err(ExecError::TerminationWithError(status, stderr)) => Recording {
+                stderr: stderr.clone(),
+                status: *status,
+                ..recording
+            },
+            Err(ExecError::TerminationWithErrorCode(status)) => Recording {
+                status: *status,
+                ..recording
+            },
+            Err(ExecError::Failed(report)) if report.status.is_some() => Recording {
+                stdout: String::from_utf8_lossy(&report.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&report.stderr).into_owned(),
+                status: report.status.unwrap_or_default(),
+                ..recording
+            },
+            Err(e) => Recording {
+                error: Some(e.to_string()),
+                ..recording
+            },
+        }
+    }
+
+    /// Returns the recorded output; failures with a status code are errors
+    fn text(self) -> Result<String, ExecError> {
+        let output = self.full()?;
+
+        match output.status {
+            0 => Ok(output.stdout),
+            status => Err(ExecError::TerminationWithError(status, output.stderr)),
+        }
+    }
+
+    /// Returns the recorded output; a status other than 0 is not an error
+    fn full(self) -> Result<ExecOutput, ExecError> {
+        match self.error {
+            Some(error) => Err(ExecError::Execution(error)),
+            None => Ok(ExecOutput {
+                stdout: self.stdout,
+                stderr: self.stderr,
+                status: self.status,
+                duration: Duration::ZERO,
+            }),
+        }
+    }
+}
+
+fn recorded(commands: &[(&str, &[&str], Option<&Context>)]) -> Vec<RecordedCommand> {
+    commands
+        .iter()
+        .map(|(command, args, context)| RecordedCommand {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            context: context.cloned(),
+        })
+        .collect()
+}
+
+/// Returns the stages of the pipeline as recorded commands; the input is not recorded
+fn recorded_pipeline(pipeline: &Pipeline) -> Vec<RecordedCommand> {
+    pipeline
+        .stages()
+        .iter()
+        .map(|spec| RecordedCommand {
+            command: spec.get_program().to_string_lossy().into_owned(),
+            args: spec
+                .get_args()
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            context: spec.get_context().cloned(),
+        })
+        .collect()
+}
+
+/// Executor recording every execution of the wrapped executor, e.g. to create fixtures for `ReplayExec`
+pub struct RecorderExec<E: Exec> {
+    inner: E,
+    recordings: Vec<Recording>,
+}
+
+impl<E: Exec> RecorderExec<E> {
+    /// Wraps an executor
+    pub fn new(inner: E) -> Self {
+        RecorderExec {
+            inner,
+            recordings: Vec::new(),
+        }
+    }
+
+    /// Returns the executions recorded so far, in the order they were executed
+    pub fn recordings(&self) -> &[Recording] {
+        &self.recordings
+    }
+
+    /// Returns the recorded executions and drops the wrapped executor
+    pub fn into_recordings(self) -> Vec<Recording> {
+        self.recordings
+    }
+
+    fn record<T>(
+        &mut self,
+        commands: Vec<RecordedCommand>,
+        result: Result<T, ExecError>,
+        output: impl Fn(&T) -> ExecOutput,
+    ) -> Result<T, ExecError> {
+        self.recordings
+            .push(Recording::new(commands, result.as_ref().map(output)));
+        result
+    }
+}
+
+impl<E: Exec> Exec for RecorderExec<E> {
+    fn exec(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        let result = self.inner.exec(command, args, context);
+
+        self.record(recorded(&[(command, args, context)]), result, |output| {
+            stdout(output)
+        })
+    }
+
+    fn exec_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        let result = self.inner.exec_piped(commands);
+
+        self.record(recorded(commands), result, |output| stdout(output))
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        let result = self.inner.run(pipeline);
+
+        self.record(recorded_pipeline(pipeline), result, |output| stdout(output))
+    }
+
+    fn exec_bytes(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<Vec<u8>, ExecError> {
+        let result = self.inner.exec_bytes(command, args, context);
+
+        self.record(recorded(&[(command, args, context)]), result, |bytes| {
+            stdout(&String::from_utf8_lossy(bytes))
+        })
+    }
+
+    fn exec_piped_bytes(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<Vec<u8>, ExecError> {
+        let result = self.inner.exec_piped_bytes(commands);
+
+        self.record(recorded(commands), result, |bytes| {
+            stdout(&String::from_utf8_lossy(bytes))
+        })
+    }
+
+    fn exec_full(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        let result = self.inner.exec_full(command, args, context);
+
+        self.record(recorded(&[(command, args, context)]), result, Clone::clone)
+    }
+
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        let result = self.inner.exec_piped_full(commands);
+
+        self.record(recorded(commands), result, Clone::clone)
+    }
+}
+
+/// Returns the output of a successful command, which only returned stdout
+fn stdout(stdout: &str) -> ExecOutput {
+    ExecOutput {
+        stdout: stdout.to_string(),
+        stderr: String::new(),
+        status: 0,
+        duration: Duration::ZERO,
+    }
+}
+
+/// Executor serving recorded executions instead of running the commands
+///
+/// Each execution is served by the first unused recording of the same commands. Commands
+/// without such a recording fail with `ExecError::UnexpectedCommand`. Failures with a status code
+/// are returned as `ExecError::TerminationWithError`, other errors as `ExecError::Execution`.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayExec {
+    recordings: Vec<Recording>,
+}
+
+impl ReplayExec {
+    /// Creates an executor serving the recordings
+    ///
+    /// * `recordings` - the executions, e.g. loaded from a fixture file
+    ///
+    pub fn new(recordings: Vec<Recording>) -> Self {
+        ReplayExec { recordings }
+    }
+
+    /// Returns the recordings not used so far
+    pub fn remaining(&self) -> &[Recording] {
+        &self.recordings
+    }
+
+    fn replay(&mut self, commands: Vec<RecordedCommand>) -> Result<Recording, ExecError> {
+        match self
+            .recordings
+            .iter()
+            .position(|recording| recording.commands == commands)
+        {
+            Some(index) => Ok(self.recordings.remove(index)),
+            None => Err(ExecError::UnexpectedCommand(
+                commands
+                    .iter()
+                    .map(|command| {
+                        std::iter::once(&command.command)
+                            .chain(&command.args)
+                            .map(|part| quote::quote(part))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" | "),
+            )),
+        }
+    }
+}
+
+impl Exec for ReplayExec {
+    fn exec(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<String, ExecError> {
+        self.replay(recorded(&[(command, args, context)]))?.text()
+    }
+
+    fn exec_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<String, ExecError> {
+        self.replay(recorded(commands))?.text()
+    }
+
+    fn run(&mut self, pipeline: &Pipeline) -> Result<String, ExecError> {
+        self.replay(recorded_pipeline(pipeline))?.text()
+    }
+
+    fn exec_full(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<ExecOutput, ExecError> {
+        self.replay(recorded(&[(command, args, context)]))?.full()
+    }
+
+    fn exec_piped_full(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<ExecOutput, ExecError> {
+        self.replay(recorded(commands))?.full()
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{hook, tree, CommandExec, CommandSpec, ExecError, OutputLimit};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// Resources consumed by a command (unix only)
+///
+/// * `user_time` - CPU time spent in user mode
+/// * `system_time` - CPU time spent in kernel mode
+/// * `max_rss` - maximum resident set size in kilobytes
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ResourceUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
+    pub max_rss: u64,
+}
+
+/// Everything known about a single execution
+///
+/// * `argv` - the effective command line, including wrappers such as `sudo` or `ssh`
+/// * `context` - description of the context the command was executed in
+/// * `stdout` - captured standard output
+/// * `stderr` - captured standard error
+/// * `status` - exit code; `None` if the command was terminated by a signal
+/// * `duration` - wall-clock time between spawning the command and its termination
+/// * `rusage` - consumed resources, if available on the platform
+/// * `retries` - number of attempts made in addition to the first one
+/// * `stdout_truncated` - whether stdout was cut off
+/// * `stderr_truncated` - whether stderr was cut off
+/// * `warnings` - non-empty lines of stderr of a successful command, if collected
+/// * `tty` - whether the command saw a terminal as stdout; only for `exec_tty_report`
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExecReport {
+    pub argv: Vec<String>,
+    pub context: String,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub status: Option<i32>,
+    pub duration: Duration,
+    pub rusage: Option<ResourceUsage>,
+    pub retries: u32,
+    pub stdout_truncated: bool,
+    pub stderr_truncated: bool,
+    pub warnings: Vec<String>,
+    pub tty: bool,
+}
+
+impl ExecReport {
+    /// Checks whether the command finished with status code 0
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// Output of an execution, including stderr and the exit status
+///
+/// * `stdout` - captured standard output of the last command
+/// * `stderr` - captured standard error of all commands
+/// * `status` - exit code of the last command
+/// * `duration` - wall-clock time of the execution
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExecOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32,
+    pub duration: Duration,
+}
+
+impl ExecOutput {
+    /// Checks whether the command finished with status code 0
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// Outcome of an execution whose output was discarded
+///
+/// * `status` - exit code; `None` if the command was terminated by a signal
+/// * `duration` - wall-clock time between spawning the command and its termination
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ExecStatus {
+    pub status: Option<i32>,
+    pub duration: Duration,
+}
+
+impl ExecStatus {
+    /// Checks whether the command finished with status code 0
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+impl std::fmt::Display for ExecReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` ({}) ", self.argv.join(" "), self.context)?;
+
+        match self.status {
+            Some(code) => write!(f, "finished with status code {}", code)?,
+            None => write!(f, "was terminated by signal")?,
+        }
+
+        write!(f, " after {:.3}s", self.duration.as_secs_f64())
+    }
+}
+
+impl CommandExec {
+    /// Runs the command and reports everything known about the execution
+    ///
+    /// A command finishing with a status not accepted by the exit policy results in
+    /// `ExecError::Failed`, which contains the report.
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    pub fn exec_report(&mut self, spec: &CommandSpec) -> Result<ExecReport, ExecError> {
+        self.hooked(spec, CommandExec::run_report, |report| {
+            hook::output(
+                String::from_utf8_lossy(&report.stdout).into_owned(),
+                String::from_utf8_lossy(&report.stderr).into_owned(),
+                report.status.unwrap_or(-1),
+            )
+        })
+    }
+
+    fn run_report(&mut self, spec: &CommandSpec) -> Result<ExecReport, ExecError> {
+        let (result, retries) = self.with_retry(|exec| exec.exec_report_once(spec));
+
+        match result {
+            Ok(report) => Ok(ExecReport { retries, ..report }),
+            Err(ExecError::Failed(report)) => Err(ExecError::Failed(Box::new(ExecReport {
+                retries,
+                ..*report
+            }))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs the command without capturing its output and returns the exit status
+    ///
+    /// stdout and stderr are connected to the null device, unless the spec redirects them, so
+    /// that output of any size costs nothing. A status other than 0 is not an error; the timeout
+    /// and the cancellation of the executor terminate the command.
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    pub fn exec_status_only(&mut self, spec: &CommandSpec) -> Result<ExecStatus, ExecError> {
+        self.hooked(spec, CommandExec::run_status_only, |status| {
+            hook::output(String::new(), String::new(), status.status.unwrap_or(-1))
+        })
+    }
+
+    fn run_status_only(&mut self, spec: &CommandSpec) -> Result<ExecStatus, ExecError> {
+        let mut com = self.command(spec)?;
+        let mut tree = self.process_tree()?;
+        let start = Instant::now();
+
+        if spec.get_stdout().is_none() && spec.get_stdout_fifo().is_none() {
+            com.stdout(Stdio::null());
+        }
+
+        if spec.get_stderr().is_none() {
+            com.stderr(Stdio::null());
+        }
+
+        tree.prepare(spec, &mut com);
+        tree.push(com.spawn()?)?;
+
+        let status = tree.wait_interruptible(start, self.timeout, self.cancellation.as_ref())?;
+
+        Ok(ExecStatus {
+            status: status.code(),
+            duration: start.elapsed(),
+        })
+    }
+
+    fn exec_report_once(&mut self, spec: &CommandSpec) -> Result<ExecReport, ExecError> {
+        let mut com = self.command(spec)?;
+        let mut tree = self.process_tree()?;
+        let argv = std::iter::once(com.get_program())
+            .chain(com.get_args())
+            .map(|part| part.to_string_lossy().into_owned())
+            .collect();
+        let start = Instant::now();
+
+        CommandExec::pipe_output(spec, &mut com, true);
+        tree.prepare(spec, &mut com);
+        tree.push(com.spawn()?)?;
+
+        let limiter = tree.limiter();
+        let child = tree.last_mut().ok_or(ExecError::Chaining)?;
+        // streams redirected to files are not piped and reported as empty
+        let stdout = tree::read_to_end(child.stdout.take(), limiter.clone());
+        let stderr = tree::read_to_end(child.stderr.take(), limiter);
+        // the command is killed if it is interrupted; the readers end with it
+        let (status, rusage) = loop {
+            let child = tree.last_mut().ok_or(ExecError::Chaining)?;
+
+            if let Some(finished) = try_wait_with_rusage(child)? {
+                break finished;
+            }
+
+            tree.check_interrupted(start, self.timeout, self.cancellation.as_ref())?;
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let join = |reader: std::thread::JoinHandle<std::io::Result<(Vec<u8>, bool)>>| {
+            reader.join().map_err(|_| ExecError::Chaining)
+        };
+        let (stdout, stdout_truncated) = join(stdout)??;
+        let (stderr, stderr_truncated) = join(stderr)??;
+
+        if let (Some(OutputLimit::Fail(bytes)), true) =
+            (self.output_limit, stdout_truncated || stderr_truncated)
+        {
+            return Err(ExecError::OutputTooLarge(bytes));
+        }
+
+        let mut report = ExecReport {
+            argv,
+            context: match spec.get_context() {
+                Some(context) => context.to_string(),
+                None => "local".to_string(),
+            },
+            stdout,
+            stderr,
+            status: status.code(),
+            duration: start.elapsed(),
+            rusage,
+            retries: 0,
+            stdout_truncated,
+            stderr_truncated,
+            warnings: Vec::new(),
+            tty: false,
+        };
+
+        let accepted = report
+            .status
+            .is_some_and(|code| self.accepts(Some(spec), code));
+
+        if self.stderr_warnings && accepted {
+            report.warnings = String::from_utf8_lossy(&report.stderr)
+                .lines()
+                .map(str::trim_end)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        match accepted {
+            true => Ok(report),
+            false => Err(spec.classify(ExecError::Failed(Box::new(report)))),
+        }
+    }
+}
+
+/// Returns the exit status and the consumed resources if the child exited, without waiting for it
+#[cfg(unix)]
+fn try_wait_with_rusage(
+    child: &mut std::process::Child,
+) -> Result<Option<(std::process::ExitStatus, Option<ResourceUsage>)>, ExecError> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: rusage is a plain C struct for which all-zero bytes are a valid value
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    loop {
+        // SAFETY: the pointers are valid for the duration of the call; the child has not been reaped yet
+        let res = unsafe {
+            libc::wait4(
+                child.id() as libc::pid_t,
+                &mut status,
+                libc::WNOHANG,
+                &mut usage,
+            )
+        };
+
+        match res {
+            0 => return Ok(None),
+            -1 => {}
+            _ => break,
+        }
+
+        let error = std::io::Error::last_os_error();
+
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(ExecError::Io(error));
+        }
+    }
+
+    let timeval = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+
+    Ok(Some((
+        std::process::ExitStatus::from_raw(status),
+        Some(ResourceUsage {
+            user_time: timeval(usage.ru_utime),
+            system_time: timeval(usage.ru_stime),
+            max_rss: usage.ru_maxrss as u64,
+        }),
+    )))
+}
+
+/// Returns the exit status if the child exited, without waiting for it
+#[cfg(not(unix))]
+fn try_wait_with_rusage(
+    child: &mut std::process::Child,
+) -> Result<Option<(std::process::ExitStatus, Option<ResourceUsage>)>, ExecError> {
+    Ok(child.try_wait()?.map(|status| (status, None)))
+}
@@ -0,0 +1,241 @@
+use crate::ExecError;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Computes the delay between two attempts (before jitter is applied)
+///
+/// Closures with the signature of `delay` implement the trait, which allows custom logic such as
+/// honoring retry hints printed by the command.
+pub trait BackoffStrategy: Send + Sync {
+    /// Returns the delay before the next attempt
+    ///
+    /// * `attempt` - zero-based number of the attempt that failed
+    /// * `previous` - delay before the failed attempt; zero for the first attempt
+    /// * `error` - the error of the failed attempt
+    ///
+    fn delay(&self, attempt: u32, previous: Duration, error: &ExecError) -> Duration;
+}
+
+impl<F: Fn(u32, Duration, &ExecError) -> Duration + Send + Sync> BackoffStrategy for F {
+    fn delay(&self, attempt: u32, previous: Duration, error: &ExecError) -> Duration {
+        self(attempt, previous, error)
+    }
+}
+
+/// Same delay after every attempt
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FixedBackoff(pub Duration);
+
+impl BackoffStrategy for FixedBackoff {
+    fn delay(&self, _attempt: u32, _previous: Duration, _error: &ExecError) -> Duration {
+        self.0
+    }
+}
+
+/// Delay doubling after every attempt, starting at `initial` and capped at `max`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ExponentialBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl BackoffStrategy for ExponentialBackoff {
+    fn delay(&self, attempt: u32, _previous: Duration, _error: &ExecError) -> Duration {
+        self.initial
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+}
+
+/// Random delay between `base` and three times the previous delay, capped at `max`
+///
+/// The delays of many clients decorrelate quickly while still growing on repeated failures.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DecorrelatedBackoff {
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl BackoffStrategy for DecorrelatedBackoff {
+    fn delay(&self, _attempt: u32, previous: Duration, _error: &ExecError) -> Duration {
+        let upper = (previous.max(self.base) * 3).min(self.max);
+
+        match upper > self.base {
+            true => self.base + (upper - self.base).mul_f64(random_fraction()),
+            false => upper,
+        }
+    }
+}
+
+/// Randomization of the delay, so that many clients retrying at once spread out
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Jitter {
+    /// Use the delay as is
+    #[default]
+    None,
+    /// Random delay between zero and the computed delay
+    Full,
+    /// Half of the computed delay plus a random share of the other half
+    Equal,
+}
+
+type Classifier = Arc<dyn Fn(&ExecError) -> bool + Send + Sync>;
+
+/// Policy for retrying failed executions
+///
+/// By default, `Io`, `Execution` and `Transport` errors are considered transient and retried,
+/// while commands finishing with a non-zero status code are not.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Arc<dyn BackoffStrategy>,
+    jitter: Jitter,
+    budget: Option<Duration>,
+    retryable: Classifier,
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("jitter", &self.jitter)
+            .field("budget", &self.budget)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy with one second between attempts and no jitter
+    ///
+    /// * `max_attempts` - maximum number of attempts, including the first one
+    ///
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff: Arc::new(FixedBackoff(Duration::from_secs(1))),
+            jitter: Jitter::None,
+            budget: None,
+            retryable: Arc::new(|e| {
+                matches!(
+                    e,
+                    ExecError::Io(_) | ExecError::Execution(_) | ExecError::Transport(..)
+                )
+            }),
+        }
+    }
+
+    /// Sets the strategy computing the delay between attempts
+    pub fn backoff(mut self, backoff: impl BackoffStrategy + 'static) -> Self {
+        self.backoff = Arc::new(backoff);
+        self
+    }
+
+    /// Sets the randomization of the delay
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Limits the total time spent waiting between attempts
+    ///
+    /// * `budget` - no further attempt is made if its delay would exceed the remaining budget
+    ///
+    pub fn budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Sets the function deciding whether an error is worth another attempt
+    pub fn retryable(
+        mut self,
+        retryable: impl Fn(&ExecError) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    /// Delay after the given (zero-based) attempt, including jitter
+    ///
+    /// * `attempt` - zero-based number of the attempt that failed
+    /// * `previous` - delay before the failed attempt; zero for the first attempt
+    /// * `error` - the error of the failed attempt
+    ///
+    pub fn delay(&self, attempt: u32, previous: Duration, error: &ExecError) -> Duration {
+        let delay = self.backoff.delay(attempt, previous, error);
+
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(random_fraction()),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(random_fraction()),
+        }
+    }
+
+    /// Runs the operation until it succeeds, fails permanently or the attempts are exhausted
+    ///
+    /// Returns the result together with the number of retries made.
+    pub(crate) fn run<T>(
+        &self,
+        mut operation: impl FnMut() -> Result<T, ExecError>,
+    ) -> (Result<T, ExecError>, u32) {
+        let mut waited = Duration::ZERO;
+        let mut previous = Duration::ZERO;
+        let mut attempt = 0;
+
+        loop {
+            let error = match operation() {
+                Ok(value) => return (Ok(value), attempt),
+                Err(e) => e,
+            };
+            let delay = match self.next_delay(attempt, previous, waited, &error) {
+                Some(delay) => delay,
+                None => return (Err(error), attempt),
+            };
+
+            std::thread::sleep(delay);
+            waited += delay;
+            previous = delay;
+            attempt += 1;
+        }
+    }
+
+    /// Returns the delay before the next attempt; `None` if no further attempt is made
+    ///
+    /// * `attempt` - zero-based number of the attempt that failed
+    /// * `previous` - delay before the failed attempt; zero for the first attempt
+    /// * `waited` - total time waited between the attempts so far
+    /// * `error` - the error of the failed attempt
+    ///
+    pub(crate) fn next_delay(
+        &self,
+        attempt: u32,
+        previous: Duration,
+        waited: Duration,
+        error: &ExecError,
+    ) -> Option<Duration> {
+        // a cancelled execution must not be started again, whatever the classifier says
+        if attempt + 1 >= self.max_attempts
+            || matches!(error, ExecError::Cancelled)
+            || !(self.retryable)(error)
+        {
+            return None;
+        }
+
+        let delay = self.delay(attempt, previous, error);
+
+        match self.budget.is_some_and(|budget| waited + delay > budget) {
+            true => None,
+            false => Some(delay),
+        }
+    }
+}
+
+/// Random number in [0, 1) without depending on a random number generator crate
+fn random_fraction() -> f64 {
+    use std::hash::BuildHasher;
+
+    // every RandomState is seeded with fresh keys
+    let random = std::collections::hash_map::RandomState::new().hash_one(std::time::Instant::now());
+
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
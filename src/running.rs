@@ -0,0 +1,309 @@
+use crate::tree::ProcessTree;
+use crate::{CommandExec, CommandSpec, Context, ExecError, ExecOutput, Pipeline};
+use std::process::{ChildStderr, ChildStdout, ExitStatus, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Signal sent to a running command (unix only)
+#[cfg(unix)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Signal {
+    Hangup,
+    Interrupt,
+    Quit,
+    Kill,
+    Terminate,
+    User1,
+    User2,
+    Continue,
+    Stop,
+}
+
+#[cfg(unix)]
+impl Signal {
+    fn number(self) -> i32 {
+        match self {
+            Signal::Hangup => libc::SIGHUP,
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Quit => libc::SIGQUIT,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Terminate => libc::SIGTERM,
+            Signal::User1 => libc::SIGUSR1,
+            Signal::User2 => libc::SIGUSR2,
+            Signal::Continue => libc::SIGCONT,
+            Signal::Stop => libc::SIGSTOP,
+        }
+    }
+}
+
+/// Thread reading an output stream of a command
+type Reader = JoinHandle<Result<Vec<u8>, ExecError>>;
+
+/// Handle of a command or pipeline running in the background
+///
+/// The output of the last command stays in the pipes until it is read, either through the
+/// readers taken from the handle or by `wait`. As a command blocks once a pipe is full, the
+/// readers should be taken for commands writing much output. The stderr of the other commands is
+/// discarded. Signals reach the processes spawned locally; in remote contexts, this is the ssh
+/// client, which closes the connection when terminated. Dropping the handle kills the commands.
+pub struct RunningCommand {
+    exec: CommandExec,
+    spec: Option<CommandSpec>,
+    tree: Option<ProcessTree>,
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    // stderr of the other stages, drained so that they do not block
+    drained: Vec<Reader>,
+    start: Instant,
+}
+
+impl RunningCommand {
+    /// Returns the process ids of the commands
+    pub fn pids(&self) -> Vec<u32> {
+        self.tree
+            .as_ref()
+            .map(ProcessTree::pids)
+            .unwrap_or_default()
+    }
+
+    /// Returns the exit status of the last command if it exited, without waiting for it
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, ExecError> {
+        match self.tree.as_mut().and_then(ProcessTree::last_mut) {
+            Some(child) => Ok(child.try_wait()?),
+            None => Err(ExecError::Chaining),
+        }
+    }
+
+    /// Takes the reader of stdout of the last command; its content is then missing from the
+    /// output returned by `wait`
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.stdout.take()
+    }
+
+    /// Takes the reader of stderr of the last command; its content is then missing from the
+    /// output returned by `wait`
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.stderr.take()
+    }
+
+    /// Sends the signal to all commands that are still running (unix only)
+    ///
+    /// * `signal` - the signal to be sent
+    ///
+    #[cfg(unix)]
+    pub fn send_signal(&mut self, signal: Signal) -> Result<(), ExecError> {
+        match &mut self.tree {
+            Some(tree) => tree.signal(signal.number()),
+            None => Ok(()),
+        }
+    }
+
+    /// Asks the commands to terminate and kills them if they are still running after the grace period
+    ///
+    /// On unix, `SIGTERM` is sent first; elsewhere, the commands are killed right away.
+    ///
+    /// * `grace` - time the commands get for shutting down
+    ///
+    pub fn terminate(&mut self, grace: Duration) -> Result<(), ExecError> {
+        let Some(tree) = &mut self.tree else {
+            return Ok(());
+        };
+
+        #[cfg(unix)]
+        {
+            tree.signal(libc::SIGTERM)?;
+
+            let deadline = Instant::now() + grace;
+
+            while Instant::now() < deadline {
+                if tree.try_wait_all()? {
+                    return Ok(());
+                }
+
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = grace;
+
+        tree.terminate();
+        Ok(())
+    }
+
+    /// Kills the commands immediately
+    pub fn kill(&mut self) {
+        if let Some(tree) = &mut self.tree {
+            tree.terminate();
+        }
+    }
+
+    /// Waits for the commands to finish and returns the output of the last one not read yet
+    ///
+    /// Like `Exec::exec`, a status not accepted by the exit policy is an error. Commands stopped
+    /// by a signal result in `ExecError::TerminationBySignal`.
+    pub fn wait(mut self) -> Result<String, ExecError> {
+        let output = self.finish()?;
+        let output = self.exec.check_status(self.spec.as_ref(), output)?;
+
+        self.exec.text(output)
+    }
+
+    /// Waits for the commands to finish and returns the output of the last one not read yet and
+    /// its exit status
+    ///
+    /// Like `Exec::exec_full`, a status other than 0 is not an error. Commands stopped by a signal
+    /// result in `ExecError::TerminationBySignal`.
+    pub fn wait_full(mut self) -> Result<ExecOutput, ExecError> {
+        let output = self.finish()?;
+
+        Ok(ExecOutput {
+            stdout: self.exec.text(output.stdout)?,
+            stderr: self
+                .exec
+                .postprocess(String::from_utf8_lossy(&output.stderr).into_owned()),
+            status: output.status.code().ok_or(ExecError::TerminationBySignal)?,
+            duration: self.start.elapsed(),
+        })
+    }
+
+    /// Reads the remaining output and waits for all commands
+    fn finish(&mut self) -> Result<std::process::Output, ExecError> {
+        let mut tree = self.tree.take().ok_or(ExecError::Chaining)?;
+        let limiter = tree.limiter();
+        let stderr = self
+            .stderr
+            .take()
+            .map(|pipe| CommandExec::read_stderr(pipe, false, limiter.clone()));
+        let stdout = match self.stdout.take() {
+            Some(mut pipe) => limiter.read(&mut pipe)?.0,
+            None => Vec::new(),
+        };
+
+        if let Err(e) = limiter.check() {
+            tree.terminate();
+            return Err(e);
+        }
+
+        let status = tree.wait()?;
+        let stderr = match stderr {
+            Some(reader) => reader.join().map_err(|_| ExecError::Chaining)??,
+            None => Vec::new(),
+        };
+
+        for reader in self.drained.drain(..) {
+            reader.join().map_err(|_| ExecError::Chaining)??;
+        }
+
+        limiter.check()?;
+
+        Ok(std::process::Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+impl Drop for RunningCommand {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+impl CommandExec {
+    /// Starts the command and returns a handle for stopping it or waiting for it
+    ///
+    /// The command reads its input from the null device.
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    pub fn spawn(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<RunningCommand, ExecError> {
+        let spec = CommandExec::specs(&[(command, args, context)]).remove(0);
+        let spec = self.resolve_one(&spec)?;
+        let mut com = self.command(&spec)?;
+        let mut tree = self.process_tree()?;
+        let start = Instant::now();
+
+        if spec.get_stdin().is_none() {
+            com.stdin(Stdio::null());
+        }
+
+        CommandExec::pipe_output(&spec, &mut com, true);
+        tree.prepare(&spec, &mut com);
+        tree.push(com.spawn()?)?;
+
+        let child = tree.last_mut().ok_or(ExecError::Chaining)?;
+
+        Ok(RunningCommand {
+            exec: self.clone(),
+            // streams redirected to files are not piped
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            spec: Some(spec),
+            tree: Some(tree),
+            drained: Vec::new(),
+            start,
+        })
+    }
+
+    /// Starts the pipeline and returns a handle for stopping it or waiting for it
+    ///
+    /// The input of the pipeline is written in the background.
+    ///
+    /// * `pipeline` - the commands piped into each other
+    ///
+    pub fn spawn_pipeline(&mut self, pipeline: &Pipeline) -> Result<RunningCommand, ExecError> {
+        let specs = self.resolve(pipeline.stages())?;
+        let input = pipeline.get_input();
+        let mut tree = self.process_tree()?;
+        let mut drained = Vec::new();
+        let start = Instant::now();
+
+        for (index, spec) in specs.iter().enumerate() {
+            let child = self
+                .run_single(spec, tree.last_mut(), true, input.is_some())
+                .and_then(|child| tree.push(child));
+
+            if let Err(e) = child {
+                tree.terminate();
+                return Err(e);
+            }
+
+            if let (0, Some(input)) = (index, input) {
+                if let Some(mut stdin) = tree.last_mut().and_then(|child| child.stdin.take()) {
+                    let input = input.to_vec();
+
+                    // a command not reading all of its input is not an error
+                    std::thread::spawn(move || std::io::Write::write_all(&mut stdin, &input));
+                }
+            }
+
+            if index + 1 == specs.len() {
+                break;
+            }
+
+            if let Some(pipe) = tree.last_mut().and_then(|child| child.stderr.take()) {
+                drained.push(CommandExec::read_stderr(pipe, false, tree.limiter()));
+            }
+        }
+
+        let child = tree.last_mut().ok_or(ExecError::Chaining)?;
+
+        Ok(RunningCommand {
+            exec: self.clone(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            spec: specs.last().cloned(),
+            tree: Some(tree),
+            drained,
+            start,
+        })
+    }
+}
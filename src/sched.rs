@@ -0,0 +1,60 @@
+use std::ffi::OsString;
+
+/// Scheduling policy of the command (Linux)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Scheduling {
+    /// Default time-sharing policy (`SCHED_OTHER`)
+    Other,
+    /// Time-sharing for CPU-bound batch jobs (`SCHED_BATCH`)
+    Batch,
+    /// Runs only if nothing else wants the CPU (`SCHED_IDLE`)
+    Idle,
+    /// Real-time first-in, first-out policy with a priority from 1 to 99 (`SCHED_FIFO`)
+    Fifo(u8),
+    /// Real-time round-robin policy with a priority from 1 to 99 (`SCHED_RR`)
+    RoundRobin(u8),
+}
+
+impl Scheduling {
+    /// Prefixes the command line with the corresponding `chrt` invocation
+    pub(crate) fn wrap(&self, argv: Vec<OsString>) -> Vec<OsString> {
+        let (policy, priority) = match self {
+            Scheduling::Other => ("--other", 0),
+            Scheduling::Batch => ("--batch", 0),
+            Scheduling::Idle => ("--idle", 0),
+            Scheduling::Fifo(priority) => ("--fifo", *priority),
+            Scheduling::RoundRobin(priority) => ("--rr", *priority),
+        };
+
+        ["chrt".into(), policy.into(), priority.to_string().into()]
+            .into_iter()
+            .chain(argv)
+            .collect()
+    }
+
+    /// Sets the policy in the child before the command is executed; it is inherited by wrappers such as sudo
+    #[cfg(target_os = "linux")]
+    pub(crate) fn apply(&self, com: &mut std::process::Command) {
+        let (policy, priority) = match self {
+            Scheduling::Other => (libc::SCHED_OTHER, 0),
+            Scheduling::Batch => (libc::SCHED_BATCH, 0),
+            Scheduling::Idle => (libc::SCHED_IDLE, 0),
+            Scheduling::Fifo(priority) => (libc::SCHED_FIFO, *priority as i32),
+            Scheduling::RoundRobin(priority) => (libc::SCHED_RR, *priority as i32),
+        };
+
+        // SAFETY: sched_setscheduler is async-signal-safe and the closure does not allocate
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(com, move || {
+                let param = libc::sched_param {
+                    sched_priority: priority,
+                };
+
+                match libc::sched_setscheduler(0, policy, &param) {
+                    -1 => Err(std::io::Error::last_os_error()),
+                    _ => Ok(()),
+                }
+            })
+        };
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{quote, CommandExec, CommandSpec, Context, Decoding, ExecError};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+use std::sync::mpsc;
+
+/// Shell kept alive for executing many commands, possibly over a single ssh connection
+///
+/// The commands run one after the other in the same shell process, so that changes of the
+/// environment or of the working directory carry over to subsequent commands. The output and the
+/// exit status of every command are separated by printing a unique marker after it. Commands read
+/// their input from the null device. Dropping the session closes the shell.
+pub struct ShellSession {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    stderr: mpsc::Receiver<Vec<u8>>,
+    marker: String,
+    count: u64,
+    strip_ansi: bool,
+    decoding: Decoding,
+}
+
+impl ShellSession {
+    /// Starts the shell in the context
+    ///
+    /// * `exec` - the executor providing the wrapping of the context and the output settings
+    /// * `shell` - POSIX compatible shell, e.g. `sh` or `bash`
+    /// * `context` - the context the shell runs in
+    ///
+    pub fn start(
+        exec: &mut CommandExec,
+        shell: &str,
+        context: Option<&Context>,
+    ) -> Result<Self, ExecError> {
+        let spec = match context {
+            Some(context) => CommandSpec::new(shell).context(context.clone()),
+            None => CommandSpec::new(shell),
+        };
+        let spec = exec.resolve_one(&spec)?;
+        let mut child = exec
+            .command(&spec)?
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(ExecError::Chaining)?;
+        let stdout = child.stdout.take().ok_or(ExecError::Chaining)?;
+        let stderr = child.stderr.take().ok_or(ExecError::Chaining)?;
+        let (tx, rx) = mpsc::channel();
+
+        // stderr is read concurrently, so that a command filling the pipe cannot block the shell
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stderr);
+
+            loop {
+                let mut line = Vec::new();
+
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {
+                        if tx.send(line).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        Ok(ShellSession {
+            child,
+            stdin: Some(stdin),
+            stdout: BufReader::new(stdout),
+            stderr: rx,
+            marker: format!("__exec_rs_{}_{}", std::process::id(), nanos),
+            count: 0,
+            strip_ansi: exec.strip_ansi,
+            decoding: exec.decoding,
+        })
+    }
+
+    /// Runs the command with the arguments in the shell
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command; they are quoted for the shell
+    ///
+    pub fn exec(&mut self, command: &str, args: &[&str]) -> Result<String, ExecError> {
+        self.run(&quote::join(
+            std::iter::once(command).chain(args.iter().copied()),
+        ))
+    }
+
+    /// Runs a command line in the shell; it is interpreted by the shell as is
+    ///
+    /// * `script` - command line, may contain several commands, redirections and assignments
+    ///
+    pub fn run(&mut self, script: &str) -> Result<String, ExecError> {
+        self.count += 1;
+
+        let marker = format!("{}_{}", self.marker, self.count);
+        let stdin = self.stdin.as_mut().ok_or(ExecError::SessionTerminated)?;
+
+        // the marker is preceded by a line break, as the output may not end with one
+        write!(
+            stdin,
+            "{{\n{}\n}} < /dev/null\nprintf '\\n%s %s\\n' {} \"$?\"\nprintf '\\n%s\\n' {} >&2\n",
+            script, marker, marker
+        )?;
+        stdin.flush()?;
+
+        let (mut stdout, status) = self.read_stdout(&marker)?;
+        let mut stderr = Vec::new();
+
+        for line in self.stderr.iter() {
+            if line.strip_suffix(b"\n") == Some(marker.as_bytes()) {
+                break;
+            }
+
+            stderr.extend(line);
+        }
+
+        // remove the line breaks printed before the markers
+        stdout.pop();
+        stderr.pop();
+
+        match status {
+            0 => {
+                let stdout = self.decoding.decode(stdout)?;
+
+                Ok(match self.strip_ansi {
+                    true => crate::strip_ansi(&stdout),
+                    false => stdout,
+                })
+            }
+            code => match String::from_utf8(stderr) {
+                Ok(s) => Err(ExecError::TerminationWithError(code, s)),
+                Err(_) => Err(ExecError::TerminationWithErrorCode(code)),
+            },
+        }
+    }
+
+    /// Reads the output until the marker and returns it together with the exit status
+    fn read_stdout(&mut self, marker: &str) -> Result<(Vec<u8>, i32), ExecError> {
+        let mut output = Vec::new();
+
+        loop {
+            let start = output.len();
+
+            if self.stdout.read_until(b'\n', &mut output)? == 0 {
+                // the shell exited, e.g. because the command line contained `exit`
+                self.stdin.take();
+                let _ = self.child.wait();
+                return Err(ExecError::SessionTerminated);
+            }
+
+            let line = String::from_utf8_lossy(&output[start..]);
+
+            if let Some(status) = line
+                .trim_end()
+                .strip_prefix(marker)
+                .and_then(|rest| rest.trim().parse().ok())
+            {
+                output.truncate(start);
+                return Ok((output, status));
+            }
+        }
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        // the shell terminates once its input is closed
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
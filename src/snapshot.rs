@@ -0,0 +1,171 @@
+use crate::{CommandExec, CommandSpec, Context, ExecError};
+use std::collections::BTreeMap;
+
+const SEPARATOR: &str = "--exec-rs--";
+
+/// Environment of a command as seen in a context
+///
+/// * `uid` - effective user id
+/// * `gid` - effective group id
+/// * `user` - name of the effective user
+/// * `groups` - names of all groups of the user
+/// * `cwd` - working directory
+/// * `limits` - resource limits as reported by `ulimit -a`, by name
+/// * `env` - environment variables
+///
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct EnvSnapshot {
+    pub uid: u32,
+    pub gid: u32,
+    pub user: String,
+    pub groups: Vec<String>,
+    pub cwd: String,
+    pub limits: BTreeMap<String, String>,
+    pub env: BTreeMap<String, String>,
+}
+
+/// Entry differing between two snapshots; `None` if the entry is missing on the respective side
+///
+/// * `key` - name of the entry, e.g. `uid`, `env.PATH` or `limit.open files`
+/// * `left` - value in the first snapshot
+/// * `right` - value in the second snapshot
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Difference {
+    pub key: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+impl EnvSnapshot {
+    /// Parses the output of the snapshot script
+    fn parse(output: &str) -> Result<Self, ExecError> {
+        let invalid = || ExecError::Execution("unexpected output of environment snapshot".into());
+        let mut lines = output.lines();
+        let mut next = || lines.next().ok_or_else(invalid);
+        let uid = next()?.trim().parse().map_err(|_| invalid())?;
+        let gid = next()?.trim().parse().map_err(|_| invalid())?;
+        let user = next()?.trim().to_string();
+        let groups = next()?.split_whitespace().map(String::from).collect();
+        let cwd = next()?.to_string();
+        let mut limits = BTreeMap::new();
+
+        loop {
+            let line = next()?;
+
+            if line == SEPARATOR {
+                break;
+            }
+
+            // the value is the last column; the name may contain units and options
+            if let Some((name, value)) = line.trim_end().rsplit_once(char::is_whitespace) {
+                limits.insert(name.trim().to_string(), value.to_string());
+            }
+        }
+
+        let mut env: BTreeMap<String, String> = BTreeMap::new();
+        let mut last: Option<String> = None;
+
+        for line in lines {
+            match (line.split_once('='), &last) {
+                (Some((key, value)), _) if !key.is_empty() && !key.contains(' ') => {
+                    env.insert(key.to_string(), value.to_string());
+                    last = Some(key.to_string());
+                }
+                // continuation of a value spanning several lines
+                (_, Some(key)) => {
+                    if let Some(value) = env.get_mut(key) {
+                        value.push('\n');
+                        value.push_str(line);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(EnvSnapshot {
+            uid,
+            gid,
+            user,
+            groups,
+            cwd,
+            limits,
+            env,
+        })
+    }
+
+    /// Returns all entries of the snapshot by the names used in differences
+    fn entries(&self) -> BTreeMap<String, String> {
+        let mut entries = BTreeMap::from([
+            ("uid".to_string(), self.uid.to_string()),
+            ("gid".to_string(), self.gid.to_string()),
+            ("user".to_string(), self.user.clone()),
+            ("groups".to_string(), self.groups.join(" ")),
+            ("cwd".to_string(), self.cwd.clone()),
+        ]);
+
+        entries.extend(
+            self.limits
+                .iter()
+                .map(|(name, value)| (format!("limit.{}", name), value.clone())),
+        );
+        entries.extend(
+            self.env
+                .iter()
+                .map(|(key, value)| (format!("env.{}", key), value.clone())),
+        );
+        entries
+    }
+
+    /// Returns the entries differing between the snapshots, ordered by key
+    ///
+    /// * `other` - the snapshot compared to
+    ///
+    pub fn diff(&self, other: &EnvSnapshot) -> Vec<Difference> {
+        let left = self.entries();
+        let mut right = other.entries();
+        let mut differences: Vec<Difference> = left
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let other = right.remove(&key);
+
+                match other.as_ref() == Some(&value) {
+                    true => None,
+                    false => Some(Difference {
+                        key,
+                        left: Some(value),
+                        right: other,
+                    }),
+                }
+            })
+            .collect();
+
+        differences.extend(right.into_iter().map(|(key, value)| Difference {
+            key,
+            left: None,
+            right: Some(value),
+        }));
+        differences.sort_by(|a, b| a.key.cmp(&b.key));
+        differences
+    }
+}
+
+impl CommandExec {
+    /// Captures the user, working directory, resource limits and environment seen by commands in the context
+    ///
+    /// * `context` - the context to be inspected
+    ///
+    pub fn snapshot(&mut self, context: Option<&Context>) -> Result<EnvSnapshot, ExecError> {
+        let script = format!(
+            "id -u; id -g; id -un; id -Gn; pwd; ulimit -a; echo {}; env",
+            SEPARATOR
+        );
+        let spec = CommandSpec::new("sh").args(["-c", &script]);
+        let spec = match context {
+            Some(context) => spec.context(context.clone()),
+            None => spec,
+        };
+
+        EnvSnapshot::parse(&self.exec_spec(&spec)?)
+    }
+}
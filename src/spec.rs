@@ -0,0 +1,458 @@
+use crate::{
+    quote, ssh, sudo, CommandExec, Context, ExecError, Isolation, Quoting, Redirect,
+    ResourceLimits, Scheduling,
+};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type Configure = Arc<dyn Fn(&mut std::process::Command) + Send + Sync>;
+
+/// Callbacks adjusting the command before it is spawned
+#[derive(Clone, Default)]
+struct Configurators(Vec<Configure>);
+
+impl std::fmt::Debug for Configurators {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} configurator(s)", self.0.len())
+    }
+}
+
+impl PartialEq for Configurators {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.0.iter().zip(&other.0).all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+/// Description of a command to be executed
+///
+/// A spec holds everything needed to run a command: program, arguments, environment, working
+/// directory and context. It can be created from an existing `std::process::Command`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CommandSpec {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Vec<(OsString, Option<OsString>)>,
+    cwd: Option<PathBuf>,
+    context: Option<Context>,
+    new_session: bool,
+    quoting: Quoting,
+    accepted_codes: Option<Vec<i32>>,
+    scheduling: Option<Scheduling>,
+    oom_score_adj: Option<i16>,
+    resource_limits: Option<ResourceLimits>,
+    isolation: Option<Isolation>,
+    stdin: Option<PathBuf>,
+    stdout: Option<Redirect>,
+    stderr: Option<Redirect>,
+    stdin_fifo: Option<PathBuf>,
+    stdout_fifo: Option<PathBuf>,
+    configurators: Configurators,
+}
+
+impl CommandSpec {
+    /// Creates a spec for running the program without arguments in the current context
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        CommandSpec {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            env: Vec::new(),
+            cwd: None,
+            context: None,
+            new_session: false,
+            quoting: Quoting::default(),
+            accepted_codes: None,
+            scheduling: None,
+            oom_score_adj: None,
+            resource_limits: None,
+            isolation: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            stdin_fifo: None,
+            stdout_fifo: None,
+            configurators: Configurators::default(),
+        }
+    }
+
+    /// Appends an argument
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Appends several arguments
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+        self
+    }
+
+    /// Sets an environment variable
+    ///
+    /// For remote and container contexts and for commands run as a different user, the variables
+    /// are set by prefixing the command with `env`, as neither ssh nor the escalation tools
+    /// forward them.
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.env.push((
+            key.as_ref().to_os_string(),
+            Some(value.as_ref().to_os_string()),
+        ));
+        self
+    }
+
+    /// Removes an environment variable
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.env.push((key.as_ref().to_os_string(), None));
+        self
+    }
+
+    /// Sets the working directory
+    ///
+    /// For remote and container contexts and for commands run as a different user, the directory
+    /// is changed by a shell prefixed to the command, since it refers to the file system of the
+    /// context.
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.cwd = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the context the command is executed in
+    pub fn context(mut self, context: Context) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Starts the command in a new session, detached from the controlling terminal
+    ///
+    /// Signals sent to the process group of the caller (e.g. by pressing Ctrl-C) do not reach the
+    /// command. On Windows, the command is started in a new process group instead.
+    ///
+    /// * `new_session` - start the command in a new session
+    ///
+    pub fn new_session(mut self, new_session: bool) -> Self {
+        self.new_session = new_session;
+        self
+    }
+
+    /// Sets how the command and the arguments are treated by shells
+    ///
+    /// By default, arguments containing spaces, quotes or `$` reach the command unchanged, also in
+    /// remote contexts. `Quoting::None` opts out for intentional shell snippets; note that the
+    /// arguments must then be quoted by the caller.
+    ///
+    /// * `quoting` - the quoting strategy
+    ///
+    pub fn quoting(mut self, quoting: Quoting) -> Self {
+        self.quoting = quoting;
+        self
+    }
+
+    /// Treats the listed exit statuses as success, overriding the exit policy of the executor
+    ///
+    /// * `codes` - the statuses that are a success, e.g. `&[0, 1]` for `grep`
+    ///
+    pub fn accept_codes(mut self, codes: &[i32]) -> Self {
+        self.accepted_codes = Some(codes.to_vec());
+        self
+    }
+
+    /// Sets the scheduling policy of the command
+    ///
+    /// Locally, the policy is set before the command is executed; remote commands are wrapped in
+    /// `chrt`. Real-time policies usually require privileges.
+    ///
+    /// * `scheduling` - the scheduling policy
+    ///
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = Some(scheduling);
+        self
+    }
+
+    /// Adjusts the likelihood of the command being killed when the system runs out of memory (Linux)
+    ///
+    /// Locally, the value is written to `/proc/self/oom_score_adj` before the command is executed;
+    /// remote commands are wrapped in `choom`. Lowering the value usually requires privileges.
+    ///
+    /// * `adjustment` - value from -1000 (never kill) to 1000 (kill first)
+    ///
+    pub fn oom_score_adj(mut self, adjustment: i16) -> Self {
+        self.oom_score_adj = Some(adjustment);
+        self
+    }
+
+    /// Sets the niceness, the I/O priority and resource limits of the command (unix)
+    ///
+    /// The settings only apply to local contexts; the limits are enforced by the kernel, e.g. by
+    /// terminating the command once its CPU time is used up.
+    ///
+    /// * `limits` - the priorities and limits
+    ///
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.resource_limits = Some(limits);
+        self
+    }
+
+    /// Runs the command in a private mount namespace with the given isolation (Linux)
+    ///
+    /// * `isolation` - the mounts set up for the command
+    ///
+    pub fn isolation(mut self, isolation: Isolation) -> Self {
+        self.isolation = Some(isolation);
+        self
+    }
+
+    /// Reads the standard input of the command from a file
+    ///
+    /// Locally, the file is opened by the calling process; for remote and container contexts, it is
+    /// opened by a shell within the context. In a pipeline, the output of the preceding command is
+    /// discarded.
+    /// Replaces a named pipe set by `stdin_from_fifo`.
+    ///
+    /// * `path` - the file; for remote contexts, a path on the remote host
+    ///
+    pub fn stdin_from(mut self, path: impl AsRef<Path>) -> Self {
+        self.stdin_fifo = None;
+        self.stdin = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Writes the standard output of the command to a file instead of capturing it
+    ///
+    /// * `path` - the file; for remote contexts, a path on the remote host
+    /// * `append` - append to the file instead of truncating it
+    ///
+    pub fn stdout_to(mut self, path: impl AsRef<Path>, append: bool) -> Self {
+        self.stdout_fifo = None;
+        self.stdout = Some(Redirect {
+            path: path.as_ref().to_path_buf(),
+            append,
+        });
+        self
+    }
+
+    /// Writes the standard error of the command to a file
+    ///
+    /// * `path` - the file; for remote contexts, a path on the remote host
+    /// * `append` - append to the file instead of truncating it
+    ///
+    pub fn stderr_to(mut self, path: impl AsRef<Path>, append: bool) -> Self {
+        self.stderr = Some(Redirect {
+            path: path.as_ref().to_path_buf(),
+            append,
+        });
+        self
+    }
+
+    /// Reads the standard input of the command from a named pipe
+    ///
+    /// The pipe is created before the command is started and removed after it terminated. The
+    /// command blocks until another process opens the pipe for writing. In a pipeline, the output
+    /// of the preceding command is discarded. Replaces a file set by `stdin_from`.
+    ///
+    /// * `path` - path of the pipe; for remote contexts, a path on the remote host
+    ///
+    pub fn stdin_from_fifo(mut self, path: impl AsRef<Path>) -> Self {
+        self.stdin = None;
+        self.stdin_fifo = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Writes the standard output of the command to a named pipe instead of capturing it
+    ///
+    /// The pipe is created before the command is started and removed after it terminated. The
+    /// command blocks until another process opens the pipe for reading. Replaces a redirection
+    /// set by `stdout_to`.
+    ///
+    /// * `path` - path of the pipe; for remote contexts, a path on the remote host
+    ///
+    pub fn stdout_to_fifo(mut self, path: impl AsRef<Path>) -> Self {
+        self.stdout = None;
+        self.stdout_fifo = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Registers a callback adjusting the command right before it is spawned
+    ///
+    /// The callback is invoked after the command was wrapped according to its context. Hence, it
+    /// operates on the command that is actually spawned (e.g. `ssh` for remote contexts).
+    ///
+    /// * `configure` - callback setting additional options, e.g. platform-specific creation flags
+    ///
+    pub fn configure(
+        mut self,
+        configure: impl Fn(&mut std::process::Command) + Send + Sync + 'static,
+    ) -> Self {
+        self.configurators.0.push(Arc::new(configure));
+        self
+    }
+
+    /// Registers a closure run in the child process after `fork` and before `exec` (unix only)
+    ///
+    /// This allows setting up the child, e.g. entering namespaces, dropping capabilities or
+    /// duplicating file descriptors. The closure runs in the child of the process that is actually
+    /// spawned, i.e. in `sudo` or `ssh` for wrapped contexts.
+    ///
+    /// * `f` - closure preparing the child; returning an error aborts the spawn with that error
+    ///
+    /// # Safety
+    ///
+    /// The closure runs in a forked copy of a possibly multi-threaded process. It must only call
+    /// async-signal-safe functions; in particular, it must not allocate, lock mutexes or access
+    /// state that another thread may have held at the time of the fork. See
+    /// `std::os::unix::process::CommandExt::pre_exec` for the complete contract.
+    #[cfg(unix)]
+    pub unsafe fn pre_exec(
+        self,
+        f: impl Fn() -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        let f = Arc::new(f);
+
+        self.configure(move |command| {
+            let f = f.clone();
+
+            // SAFETY: the contract is upheld by the caller of `pre_exec`
+            unsafe { std::os::unix::process::CommandExt::pre_exec(command, move || f()) };
+        })
+    }
+
+    /// Converts errors of ssh about host keys and the connection into typed errors for remote
+    /// contexts and errors of sudo about credentials for local ones
+    pub(crate) fn classify(&self, error: ExecError) -> ExecError {
+        match self.context.as_ref() {
+            Some(Context::Local { user }) => sudo::classify(user, error),
+            context => match context.and_then(Context::ssh_host) {
+                Some(host) => ssh::classify(host, error),
+                None => error,
+            },
+        }
+    }
+
+    /// Replaces the program and the arguments, keeping all other settings
+    pub(crate) fn command_line(mut self, program: OsString, args: Vec<OsString>) -> Self {
+        self.program = program;
+        self.args = args;
+        self
+    }
+
+    pub(crate) fn apply_configurators(&self, command: &mut std::process::Command) {
+        for configure in &self.configurators.0 {
+            configure(command);
+        }
+    }
+
+    pub fn get_program(&self) -> &OsStr {
+        &self.program
+    }
+
+    pub fn get_args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    /// Environment changes in the order they were made; `None` removes a variable
+    pub fn get_envs(&self) -> &[(OsString, Option<OsString>)] {
+        &self.env
+    }
+
+    pub fn get_current_dir(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
+    pub fn get_context(&self) -> Option<&Context> {
+        self.context.as_ref()
+    }
+
+    pub fn get_new_session(&self) -> bool {
+        self.new_session
+    }
+
+    pub fn get_quoting(&self) -> Quoting {
+        self.quoting
+    }
+
+    pub fn get_accepted_codes(&self) -> Option<&[i32]> {
+        self.accepted_codes.as_deref()
+    }
+
+    pub fn get_scheduling(&self) -> Option<Scheduling> {
+        self.scheduling
+    }
+
+    pub fn get_oom_score_adj(&self) -> Option<i16> {
+        self.oom_score_adj
+    }
+
+    pub fn get_resource_limits(&self) -> Option<&ResourceLimits> {
+        self.resource_limits.as_ref()
+    }
+
+    pub fn get_isolation(&self) -> Option<Isolation> {
+        self.isolation
+    }
+
+    pub fn get_stdin(&self) -> Option<&Path> {
+        self.stdin.as_deref()
+    }
+
+    pub fn get_stdout(&self) -> Option<&Redirect> {
+        self.stdout.as_ref()
+    }
+
+    pub fn get_stderr(&self) -> Option<&Redirect> {
+        self.stderr.as_ref()
+    }
+
+    pub fn get_stdin_fifo(&self) -> Option<&Path> {
+        self.stdin_fifo.as_deref()
+    }
+
+    pub fn get_stdout_fifo(&self) -> Option<&Path> {
+        self.stdout_fifo.as_deref()
+    }
+}
+
+impl From<&std::process::Command> for CommandSpec {
+    fn from(command: &std::process::Command) -> Self {
+        CommandSpec {
+            args: command.get_args().map(|arg| arg.to_os_string()).collect(),
+            env: command
+                .get_envs()
+                .map(|(key, value)| (key.to_os_string(), value.map(|v| v.to_os_string())))
+                .collect(),
+            cwd: command.get_current_dir().map(|dir| dir.to_path_buf()),
+            ..CommandSpec::new(command.get_program())
+        }
+    }
+}
+
+impl From<std::process::Command> for CommandSpec {
+    fn from(command: std::process::Command) -> Self {
+        CommandSpec::from(&command)
+    }
+}
+
+/// Shows the command line that is run for the spec by an executor with the default settings
+///
+/// The command line includes the wrappers of the context (e.g. `sudo` or `ssh`); see
+/// `CommandExec::render` for rendering it with the settings of a specific executor. Specs that
+/// cannot be rendered as shell command (e.g. with arguments that are not valid UTF-8) are shown
+/// as program and arguments only. Rendering neither runs the configurators nor opens files.
+impl std::fmt::Display for CommandSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match CommandExec::new().render(self) {
+            Ok(line) => write!(f, "{}", line),
+            Err(_) => {
+                let argv: Vec<String> = std::iter::once(self.program.as_os_str())
+                    .chain(self.args.iter().map(OsString::as_os_str))
+                    .map(|part| part.to_string_lossy().into_owned())
+                    .collect();
+
+                write!(f, "{}", quote::join(argv.iter().map(String::as_str)))
+            }
+        }
+    }
+}
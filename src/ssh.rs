@@ -0,0 +1,341 @@
+use crate::{known_hosts, quote, CommandExec, Context, ExecError, Forward};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Proxy the ssh connection to a remote host is established through
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProxyConfig {
+    /// Command connecting to the host, passed as `-o ProxyCommand=<command>`
+    ///
+    /// `%h` and `%p` are replaced by ssh with the host and the port of the target.
+    Command(String),
+    /// SOCKS5 proxy; the connection is made by `nc -X 5 -x <host>:<port> %h %p`
+    Socks5 { host: String, port: u16 },
+}
+
+impl ProxyConfig {
+    /// Returns the proxy command passed to ssh
+    pub fn command(&self) -> String {
+        match self {
+            ProxyConfig::Command(command) => command.clone(),
+            ProxyConfig::Socks5 { host, port } => format!("nc -X 5 -x {}:{} %h %p", host, port),
+        }
+    }
+
+    /// Returns the proxy as ssh command line arguments
+    pub fn args(&self) -> Vec<String> {
+        vec!["-o".to_string(), format!("ProxyCommand={}", self.command())]
+    }
+}
+
+/// Returns the arguments connecting through the proxy and the jump hosts
+///
+/// ssh does not combine `-J` with a proxy command; hence, with a proxy, every jump host is
+/// reached by an ssh proxy command connecting through the previous one, the first one through
+/// the proxy. Like with `-J`, the config file applies to the jump hosts, too.
+fn proxy_args(
+    config: Option<&str>,
+    proxy: Option<&ProxyConfig>,
+    jump_hosts: &[String],
+) -> Vec<String> {
+    match proxy {
+        None if jump_hosts.is_empty() => Vec::new(),
+        None => vec!["-J".to_string(), jump_hosts.join(",")],
+        Some(proxy) => {
+            // the outer ssh expands the tokens of the command once, so they are escaped
+            let escape = |s: &str| s.replace('%', "%%");
+            let config = match config {
+                Some(config) => format!("-F {} ", quote::quote(&escape(config))),
+                None => String::new(),
+            };
+            let command = jump_hosts.iter().fold(proxy.command(), |command, host| {
+                let option = format!("ProxyCommand={}", escape(&command));
+
+                format!(
+                    "ssh {}-o {} -W %h:%p {}",
+                    config,
+                    quote::quote(&option),
+                    destination(host)
+                )
+            });
+
+            vec!["-o".to_string(), format!("ProxyCommand={}", command)]
+        }
+    }
+}
+
+/// Returns the jump host as destination of ssh, which only accepts a port in the URI form
+fn destination(host: &str) -> Cow<'_, str> {
+    match host.starts_with("ssh://") {
+        true => Cow::Borrowed(host),
+        false => Cow::Owned(format!("ssh://{}", host)),
+    }
+}
+
+/// Options of the ssh connection, making an ssh config file unnecessary
+///
+/// All settings but the jump hosts are passed to ssh as `-o` options, which take precedence over the
+/// config files. The jump hosts are combined with the proxy of the context.
+///
+/// * `host_name` - real name or address of the host; the host of the context becomes an alias
+/// * `user` - user to log in as
+/// * `port` - port of the ssh server
+/// * `identity_file` - private key used for authentication
+/// * `ciphers` - ciphers allowed for the connection, in order of preference
+/// * `forwards` - port forwards established with every connection
+/// * `jump_hosts` - hosts the connection is forwarded through in order, given as
+///   `[user@]host[:port]` or as aliases of the ssh config file; passed as `-J <host1>,<host2>`
+/// * `options` - further options as key and value, e.g. `("ServerAliveInterval", "30")`
+/// * `windows_shell` - the host runs OpenSSH for Windows with `cmd` as shell; the command line is
+///   quoted following the Windows conventions
+///
+#[derive(Debug, Default, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SshSettings {
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<PathBuf>,
+    pub ciphers: Vec<String>,
+    pub forwards: Vec<Forward>,
+    pub jump_hosts: Vec<String>,
+    pub options: Vec<(String, String)>,
+    pub windows_shell: bool,
+}
+
+impl SshSettings {
+    /// Returns the settings as ssh command line arguments; the jump hosts are not included
+    pub fn args(&self) -> Vec<String> {
+        let mut options: Vec<String> = Vec::new();
+
+        if let Some(host_name) = &self.host_name {
+            options.push(format!("HostName={}", host_name));
+        }
+
+        if let Some(user) = &self.user {
+            options.push(format!("User={}", user));
+        }
+
+        if let Some(port) = self.port {
+            options.push(format!("Port={}", port));
+        }
+
+        if let Some(identity_file) = &self.identity_file {
+            options.push(format!("IdentityFile={}", identity_file.to_string_lossy()));
+        }
+
+        if !self.ciphers.is_empty() {
+            options.push(format!("Ciphers={}", self.ciphers.join(",")));
+        }
+
+        options.extend(self.forwards.iter().map(Forward::option));
+        options.extend(
+            self.options
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value)),
+        );
+        options
+            .into_iter()
+            .flat_map(|option| ["-o".to_string(), option])
+            .collect()
+    }
+}
+
+/// Builder of remote contexts, created by `Context::remote`
+///
+/// E.g. `Context::remote("db").jump_hosts(["bastion"]).build()`. Settings left at their defaults
+/// are not passed to ssh.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RemoteContext {
+    host: String,
+    config: Option<String>,
+    proxy: Option<ProxyConfig>,
+    settings: SshSettings,
+}
+
+impl Context {
+    /// Starts building a remote context
+    ///
+    /// * `host` - name of the remote host
+    ///
+    pub fn remote(host: impl Into<String>) -> RemoteContext {
+        RemoteContext {
+            host: host.into(),
+            config: None,
+            proxy: None,
+            settings: SshSettings::default(),
+        }
+    }
+}
+
+impl RemoteContext {
+    /// Sets the path and filename of the ssh config file
+    pub fn config(mut self, config: impl Into<String>) -> Self {
+        self.config = Some(config.into());
+        self
+    }
+
+    /// Sets the proxy the connection is established through
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Sets the jump hosts the connection is forwarded through in order
+    ///
+    /// * `hosts` - the hosts as `[user@]host[:port]` or as aliases of the ssh config file
+    ///
+    pub fn jump_hosts<I, S>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.settings.jump_hosts = hosts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the user logged in as
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.settings.user = Some(user.into());
+        self
+    }
+
+    /// Sets the port connected to
+    pub fn port(mut self, port: u16) -> Self {
+        self.settings.port = Some(port);
+        self
+    }
+
+    /// Sets the path and filename of the private key used for authentication
+    pub fn identity_file(mut self, identity_file: impl Into<PathBuf>) -> Self {
+        self.settings.identity_file = Some(identity_file.into());
+        self
+    }
+
+    /// Adds an option passed to ssh as `-o key=value`
+    ///
+    /// * `key` - name of the option, e.g. `ServerAliveInterval`
+    /// * `value` - value of the option
+    ///
+    pub fn option(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.settings.options.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the options of the connection, replacing the ones set so far
+    pub fn settings(mut self, settings: SshSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Returns the remote context
+    pub fn build(self) -> Context {
+        Context::Remote {
+            host: self.host,
+            config: self.config,
+            proxy: self.proxy,
+            settings: match self.settings == SshSettings::default() {
+                true => None,
+                false => Some(Box::new(self.settings)),
+            },
+        }
+    }
+}
+
+impl From<RemoteContext> for Context {
+    fn from(remote: RemoteContext) -> Self {
+        remote.build()
+    }
+}
+
+impl CommandExec {
+    /// Returns an ssh command with the options of the remote context; the host is appended by the caller
+    pub(crate) fn ssh_command(
+        &self,
+        config: Option<&str>,
+        proxy: Option<&ProxyConfig>,
+        settings: Option<&SshSettings>,
+    ) -> Command {
+        let mut com = Command::new("ssh");
+
+        if let Some(config) = config {
+            com.arg("-F").arg(config);
+        }
+
+        com.args(proxy_args(
+            config,
+            proxy,
+            settings.map_or(&[], |settings| settings.jump_hosts.as_slice()),
+        ));
+
+        if let Some(settings) = settings {
+            com.args(settings.args());
+        }
+
+        if let Some(known_hosts) = &self.known_hosts {
+            com.arg("-o")
+                .arg(format!(
+                    "UserKnownHostsFile={}",
+                    known_hosts.path().to_string_lossy()
+                ))
+                .args(["-o", "StrictHostKeyChecking=yes"]);
+        }
+
+        com
+    }
+}
+
+/// Messages of ssh indicating that the connection failed rather than the remote command
+const TRANSPORT_ERRORS: &[&str] = &[
+    "Connection reset",
+    "Connection refused",
+    "Connection timed out",
+    "Connection closed by",
+    "Could not resolve hostname",
+    "No route to host",
+    "Network is unreachable",
+    "Broken pipe",
+    "kex_exchange_identification",
+    "mux_client",
+    "Control socket",
+    "ControlSocket",
+];
+
+/// Converts errors of ssh itself into typed errors, leaving failures of the remote command as is
+///
+/// ssh reports its own failures with status code 255, which remote commands may use, too; hence,
+/// the stderr is checked for the messages of ssh.
+pub(crate) fn classify(host: &str, error: ExecError) -> ExecError {
+    let error = known_hosts::classify(host, error);
+
+    if stderr(&error).is_some_and(|stderr| stderr.contains("Permission denied (")) {
+        return ExecError::Authentication(host.to_string());
+    }
+
+    let classified = stderr(&error).and_then(|stderr| {
+        stderr
+            .lines()
+            .find(|line| {
+                TRANSPORT_ERRORS
+                    .iter()
+                    .any(|message| line.contains(message))
+            })
+            .map(|line| ExecError::Transport(host.to_string(), line.trim().to_string()))
+    });
+
+    classified.unwrap_or(error)
+}
+
+/// Returns the stderr of an error that may have been caused by ssh, i.e. with status code 255
+pub(crate) fn stderr(error: &ExecError) -> Option<Cow<'_, str>> {
+    match error {
+        ExecError::TerminationWithError(255, stderr) => Some(Cow::Borrowed(stderr)),
+        ExecError::Failed(report) if report.status == Some(255) => {
+            Some(String::from_utf8_lossy(&report.stderr))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,264 @@
+use crate::tree::ProcessTree;
+use crate::{CommandExec, CommandSpec, Context, Decoding, ExecError};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Output stream of a command
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Line of output as delivered by the streaming API
+///
+/// * `stream` - the stream the line was written to
+/// * `timestamp` - monotonic time at which the line was read from the child
+/// * `elapsed` - time between spawning the command and reading the line
+/// * `line` - content of the line without the line terminator
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputLine {
+    pub stream: Stream,
+    pub timestamp: Instant,
+    pub elapsed: Duration,
+    pub line: String,
+}
+
+/// Iterator over the output lines of a running command
+///
+/// The lines are stamped by the threads reading from the child, so delays in consuming the
+/// iterator do not distort the timing. After the last line, a non-zero exit status is reported
+/// as an error. Dropping the iterator before the end kills the command.
+pub struct LineStream {
+    rx: mpsc::Receiver<Result<OutputLine, ExecError>>,
+    tree: Option<ProcessTree>,
+}
+
+impl Iterator for LineStream {
+    type Item = Result<OutputLine, ExecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(line) = self.rx.recv() {
+            return Some(line);
+        }
+
+        // all readers are done; report the exit status once
+        let status = self.tree.take()?.wait();
+
+        match status.map(|status| status.code()) {
+            Ok(Some(0)) => None,
+            Ok(Some(code)) => Some(Err(ExecError::TerminationWithErrorCode(code))),
+            Ok(None) => Some(Err(ExecError::TerminationBySignal)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl Drop for LineStream {
+    fn drop(&mut self) {
+        if let Some(mut tree) = self.tree.take() {
+            tree.terminate();
+        }
+    }
+}
+
+impl CommandExec {
+    /// Runs the command and returns an iterator over its stdout and stderr lines as they appear
+    ///
+    /// * `spec` - the command to be executed
+    ///
+    pub fn stream(&mut self, spec: &CommandSpec) -> Result<LineStream, ExecError> {
+        let spec = &self.resolve_one(spec)?;
+        let mut com = self.command(spec)?;
+        let mut tree = self.process_tree()?;
+        let start = Instant::now();
+
+        CommandExec::pipe_output(spec, &mut com, true);
+        tree.prepare(spec, &mut com);
+        tree.push(com.spawn()?)?;
+
+        let child = tree.last_mut().ok_or(ExecError::Chaining)?;
+        let (tx, rx) = mpsc::channel();
+
+        // streams redirected to files are not piped
+        if let Some(stdout) = child.stdout.take() {
+            read_lines(
+                stdout,
+                Stream::Stdout,
+                tx.clone(),
+                start,
+                self.strip_ansi,
+                self.decoding,
+            );
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            read_lines(
+                stderr,
+                Stream::Stderr,
+                tx,
+                start,
+                self.strip_ansi,
+                self.decoding,
+            );
+        }
+
+        Ok(LineStream {
+            rx,
+            tree: Some(tree),
+        })
+    }
+
+    /// Runs the commands piped into each other and returns an iterator over the output lines
+    ///
+    /// The lines of stdout of the last command are delivered. The stderr of the commands is
+    /// passed through, except for remote commands and in strict mode; then, it is delivered, too.
+    ///
+    /// * `specs` - the commands to be executed
+    ///
+    pub fn stream_piped(&mut self, specs: &[CommandSpec]) -> Result<LineStream, ExecError> {
+        let specs = self.resolve(specs)?;
+        let mut tree = self.process_tree()?;
+        let start = Instant::now();
+        let (tx, rx) = mpsc::channel();
+
+        for spec in &specs {
+            let child = self
+                .run_single(spec, tree.last_mut(), self.strict_stderr, false)
+                .and_then(|child| tree.push(child));
+
+            if let Err(e) = child {
+                tree.terminate();
+                return Err(e);
+            }
+
+            if let Some(stderr) = tree.last_mut().and_then(|child| child.stderr.take()) {
+                read_lines(
+                    stderr,
+                    Stream::Stderr,
+                    tx.clone(),
+                    start,
+                    self.strip_ansi,
+                    self.decoding,
+                );
+            }
+        }
+
+        if let Some(stdout) = tree.last_mut().and_then(|child| child.stdout.take()) {
+            read_lines(
+                stdout,
+                Stream::Stdout,
+                tx,
+                start,
+                self.strip_ansi,
+                self.decoding,
+            );
+        }
+
+        Ok(LineStream {
+            rx,
+            tree: Some(tree),
+        })
+    }
+
+    /// Runs a command and returns an iterator over its stdout lines as they appear
+    ///
+    /// A non-zero exit status is reported as the last item. Captured stderr lines are written to
+    /// the own stderr.
+    ///
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    /// * `context` - either a local or a remote context
+    ///
+    pub fn exec_stream(
+        &mut self,
+        command: &str,
+        args: &[&str],
+        context: Option<&Context>,
+    ) -> Result<impl Iterator<Item = Result<String, ExecError>>, ExecError> {
+        self.exec_stream_piped(&[(command, args, context)])
+    }
+
+    /// Runs several commands piping stdout of one command into stdin of the next and returns an
+    /// iterator over the stdout lines of the last command as they appear
+    ///
+    /// Lines of stderr delivered by `stream_piped` are written to stderr of the calling process;
+    /// failing to write them yields an error. Use `stream_piped` for receiving them instead.
+    ///
+    /// * `commands` - a vector of tuples of arrays of string containing the command and arguments, and contexts
+    ///
+    pub fn exec_stream_piped(
+        &mut self,
+        commands: &[(&str, &[&str], Option<&Context>)],
+    ) -> Result<impl Iterator<Item = Result<String, ExecError>>, ExecError> {
+        let lines = self.stream_piped(&CommandExec::specs(commands))?;
+
+        Ok(lines.filter_map(|line| match line {
+            Ok(OutputLine {
+                stream: Stream::Stdout,
+                line,
+                ..
+            }) => Some(Ok(line)),
+            Ok(OutputLine { line, .. }) => {
+                let mut stderr = std::io::stderr().lock();
+
+                // the line breaks are removed from the lines
+                stderr
+                    .write_all(line.as_bytes())
+                    .and_then(|_| stderr.write_all(b"\n"))
+                    .err()
+                    .map(|e| Err(e.into()))
+            }
+            Err(e) => Some(Err(e)),
+        }))
+    }
+}
+
+fn read_lines<R: Read + Send + 'static>(
+    reader: R,
+    stream: Stream,
+    tx: mpsc::Sender<Result<OutputLine, ExecError>>,
+    start: Instant,
+    strip: bool,
+    decoding: Decoding,
+) {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut buffer = Vec::new();
+
+        loop {
+            buffer.clear();
+
+            let line = match reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => return,
+                Ok(_) => {
+                    let timestamp = Instant::now();
+
+                    if buffer.ends_with(b"\n") {
+                        buffer.pop();
+                    }
+
+                    decoding
+                        .decode(buffer.clone())
+                        .map(|line| match strip {
+                            true => crate::strip_ansi(&line),
+                            false => line,
+                        })
+                        .map(|line| OutputLine {
+                            stream,
+                            timestamp,
+                            elapsed: timestamp - start,
+                            line,
+                        })
+                }
+                Err(e) => Err(ExecError::Io(e)),
+            };
+
+            if tx.send(line).is_err() {
+                return;
+            }
+        }
+    });
+}
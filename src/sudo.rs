@@ -0,0 +1,232 @@
+use crate::{CommandExec, Context, Escalation, ExecError};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Keeps the sudo timestamp of the current user valid while it is alive
+///
+/// The credentials are validated once when the guard is created (`sudo -v`). A background thread
+/// then refreshes the timestamp in the given interval until the guard is dropped, so that long
+/// sequences of privileged commands do not fail midway because the sudo timeout lapsed.
+pub struct SudoKeepAlive {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SudoKeepAlive {
+    /// Default refresh interval; well below the default sudo timestamp timeout of five minutes
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// Validates the sudo credentials and starts refreshing them in the background
+    ///
+    /// * `interval` - time between two refreshes of the sudo timestamp
+    /// * `password` - optional callback providing the password; without it, validation is non-interactive
+    ///
+    pub fn start(
+        interval: Duration,
+        password: Option<&mut dyn FnMut() -> String>,
+    ) -> Result<Self, ExecError> {
+        match password {
+            Some(password) => SudoKeepAlive::validate_with_password(&password()),
+            None => SudoKeepAlive::validate(),
+        }?;
+
+        Ok(SudoKeepAlive::refreshing(interval, SudoKeepAlive::validate))
+    }
+
+    /// Starts the thread calling `refresh` in the given interval until the guard is dropped
+    ///
+    /// * `interval` - time between two refreshes
+    /// * `refresh` - refreshes the credentials
+    ///
+    pub(crate) fn refreshing(
+        interval: Duration,
+        refresh: impl Fn() -> Result<(), ExecError> + Send + 'static,
+    ) -> Self {
+        let (stop, rx) = mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                // a failed refresh is not fatal; the next privileged command will report the problem
+                let _ = refresh();
+            }
+        });
+
+        SudoKeepAlive {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    fn validate() -> Result<(), ExecError> {
+        let output = std::process::Command::new("sudo")
+            .arg("-nv")
+            .stdin(std::process::Stdio::null())
+            .output()?;
+
+        CommandExec::check_output(output).map(|_| ())
+    }
+
+    pub(crate) fn validate_with_password(password: &str) -> Result<(), ExecError> {
+        let mut child = std::process::Command::new("sudo")
+            .args(["-Sv", "-p", ""])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+
+        {
+            let mut stdin = child.stdin.take().ok_or(ExecError::Chaining)?;
+            writeln!(stdin, "{}", password)?;
+        }
+
+        CommandExec::check_output(child.wait_with_output()?).map(|_| ())
+    }
+
+    fn validate_with_askpass(askpass: &std::path::Path) -> Result<(), ExecError> {
+        let output = std::process::Command::new("sudo")
+            .arg("-Av")
+            .env("SUDO_ASKPASS", askpass)
+            .stdin(std::process::Stdio::null())
+            .output()?;
+
+        CommandExec::check_output(output).map(|_| ())
+    }
+}
+
+impl Drop for SudoKeepAlive {
+    fn drop(&mut self) {
+        // dropping the sender wakes up the refresh thread
+        self.stop.take();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Authentication of sudo for commands in local contexts
+///
+/// By default, sudo runs non-interactively (`sudo -n`) and fails on hosts without passwordless
+/// sudo. Either variant provides the password instead.
+#[derive(Clone)]
+pub enum SudoAuth {
+    /// Writes the password to the stdin of `sudo -S`; the credentials are validated before each command
+    Password(String),
+    /// Runs `sudo -A` with the program in `SUDO_ASKPASS`, which prints the password
+    Askpass(PathBuf),
+}
+
+impl SudoAuth {
+    /// Returns the command running the command as the given user with the credentials provided
+    ///
+    /// * `user` - name of the user who will execute the command
+    /// * `command` - the command to be executed
+    /// * `args` - the arguments of the command
+    ///
+    pub(crate) fn command(
+        &self,
+        user: &str,
+        command: &OsStr,
+        args: &[&OsStr],
+    ) -> std::process::Command {
+        // the password is validated by `CommandExec::validate_sudo`, so that sudo -n passes
+        let mode = match self {
+            SudoAuth::Password(_) => "-nu",
+            SudoAuth::Askpass(_) => "-Au",
+        };
+        let mut com = std::process::Command::new("sudo");
+
+        com.args([mode, user, "--"]).arg(command).args(args);
+
+        if let SudoAuth::Askpass(askpass) = self {
+            com.env("SUDO_ASKPASS", askpass);
+        }
+
+        com
+    }
+}
+
+impl CommandExec {
+    /// Validates the sudo credentials once and keeps them valid while the guard is alive
+    ///
+    /// The credentials are validated with the `SudoAuth` of the executor, non-interactively
+    /// without one. Keep the guard for the duration of a sequence of privileged commands.
+    ///
+    /// * `interval` - time between two refreshes of the sudo timestamp, e.g. `SudoKeepAlive::DEFAULT_INTERVAL`
+    ///
+    pub fn sudo_keep_alive(&self, interval: Duration) -> Result<SudoKeepAlive, ExecError> {
+        match &self.sudo_auth {
+            Some(SudoAuth::Password(password)) => SudoKeepAlive::validate_with_password(password),
+            Some(SudoAuth::Askpass(askpass)) => SudoKeepAlive::validate_with_askpass(askpass),
+            None => SudoKeepAlive::validate(),
+        }
+        // sudo -v validates the credentials for running commands as root
+        .map_err(|e| classify("root", e))?;
+
+        Ok(SudoKeepAlive::refreshing(interval, SudoKeepAlive::validate))
+    }
+
+    /// Validates the sudo password if the outermost context of a command escalates with it
+    ///
+    /// The validated timestamp lets the non-interactive sudo of the command pass.
+    pub(crate) fn validate_sudo(&self, context: Option<&Context>) -> Result<(), ExecError> {
+        let mut context = context;
+
+        // the inner contexts of a chain are entered without the credentials of the caller
+        while let Some(Context::Chain(contexts)) = context {
+            context = contexts.first();
+        }
+
+        match (context, &self.escalation, &self.sudo_auth) {
+            (
+                Some(Context::Local { user }),
+                Escalation::Sudo,
+                Some(SudoAuth::Password(password)),
+            ) if !self.is_direct(context) && !self.switches_user() => {
+                SudoKeepAlive::validate_with_password(password).map_err(|e| classify(user, e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Messages of sudo asking for credentials it could not obtain
+const REQUIRED_MESSAGES: &[&str] = &[
+    "sudo: a password is required",
+    "sudo: a terminal is required",
+    "sudo: no askpass program specified",
+    "sudo: no password was provided",
+];
+
+/// Messages of sudo rejecting the credentials provided
+const FAILED_MESSAGES: &[&str] = &["incorrect password attempt", "Sorry, try again."];
+
+/// Converts errors of sudo about missing or wrong credentials into typed errors
+///
+/// * `user` - name of the user the command was run as
+/// * `error` - the error of the command
+///
+pub(crate) fn classify(user: &str, error: ExecError) -> ExecError {
+    let stderr = match &error {
+        ExecError::TerminationWithError(_, stderr) => Cow::Borrowed(stderr.as_str()),
+        ExecError::Failed(report) => String::from_utf8_lossy(&report.stderr),
+        _ => return error,
+    };
+
+    if FAILED_MESSAGES
+        .iter()
+        .any(|message| stderr.contains(message))
+    {
+        ExecError::SudoAuthenticationFailed(user.to_string())
+    } else if REQUIRED_MESSAGES
+        .iter()
+        .any(|message| stderr.contains(message))
+    {
+        ExecError::SudoAuthenticationRequired(user.to_string())
+    } else {
+        error
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{quote, Context, Exec, ExecError};
+
+/// Runs the command and stores its exit status in an option of the pane
+const STATUS_WRAPPER: &str = "\"$@\"; tmux set-option -p @exec-rs-status $?";
+
+/// State of the command running in a tmux session
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SessionState {
+    /// The command is still running
+    Running,
+    /// The command finished; terminations by a signal are reported as in the shell (128 + signal)
+    /// and the exit code is missing if the session was terminated before recording it
+    Exited(Option<i32>),
+    /// There is no session with the name
+    Missing,
+}
+
+/// Handle of a named tmux session running a command, possibly on a remote host
+///
+/// The session outlives the connection it was started from, so that long-running commands
+/// survive dropped ssh connections. Any process knowing the name and the context can later check
+/// on the command, capture its output or kill it. After the command finished, the session is
+/// kept until it is killed, so that the output and the exit status remain available.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TmuxSession {
+    name: String,
+    socket: Option<String>,
+    context: Option<Context>,
+}
+
+impl TmuxSession {
+    /// Creates a handle; the session itself is created by `start`
+    ///
+    /// * `name` - name of the tmux session
+    /// * `context` - the context tmux runs in
+    ///
+    pub fn new(name: &str, context: Option<Context>) -> Self {
+        TmuxSession {
+            name: name.to_string(),
+            socket: None,
+            context,
+        }
+    }
+
+    /// Uses a separate tmux server identified by the socket name (`tmux -L`)
+    pub fn socket(mut self, socket: &str) -> Self {
+        self.socket = Some(socket.to_string());
+        self
+    }
+
+    /// Returns the name of the session
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Starts the command in a new detached session
+    ///
+    /// * `exec` - the executor running tmux
+    /// * `command` - the command to be executed in the session
+    /// * `args` - the arguments of the command
+    ///
+    pub fn start<E: Exec + ?Sized>(
+        &self,
+        exec: &mut E,
+        command: &str,
+        args: &[&str],
+    ) -> Result<(), ExecError> {
+        // the exit status is recorded in a pane option, as tmux may report the pane dead before
+        // it reaped the command
+        let line = quote::join(
+            ["sh", "-c", STATUS_WRAPPER, "sh", command]
+                .into_iter()
+                .chain(args.iter().copied()),
+        );
+
+        // the option is set in the same invocation, before the command can terminate
+        self.tmux(
+            exec,
+            &[
+                "new-session",
+                "-d",
+                "-s",
+                &self.name,
+                &line,
+                ";",
+                "set-option",
+                "-t",
+                &self.name,
+                "remain-on-exit",
+                "on",
+            ],
+        )
+        .map(|_| ())
+    }
+
+    /// Returns the state of the command
+    pub fn state<E: Exec + ?Sized>(&self, exec: &mut E) -> Result<SessionState, ExecError> {
+        let panes = match self.tmux(
+            exec,
+            &[
+                "list-panes",
+                "-t",
+                &self.name,
+                "-F",
+                "#{pane_dead}:#{@exec-rs-status}",
+            ],
+        ) {
+            Ok(panes) => panes,
+            Err(ExecError::TerminationWithError(_, _) | ExecError::TerminationWithErrorCode(_)) => {
+                return Ok(SessionState::Missing)
+            }
+            Err(e) => return Err(e),
+        };
+        let mut fields = panes.lines().next().unwrap_or_default().split(':');
+
+        Ok(match (fields.next(), fields.next()) {
+            (None | Some(""), _) => SessionState::Missing,
+            (Some("1"), status) => SessionState::Exited(status.and_then(|code| code.parse().ok())),
+            _ => SessionState::Running,
+        })
+    }
+
+    /// Returns the complete output of the command, including the scrollback history
+    pub fn capture<E: Exec + ?Sized>(&self, exec: &mut E) -> Result<String, ExecError> {
+        self.tmux(
+            exec,
+            &["capture-pane", "-p", "-J", "-S", "-", "-t", &self.name],
+        )
+    }
+
+    /// Kills the session and the command running in it
+    pub fn kill<E: Exec + ?Sized>(&self, exec: &mut E) -> Result<(), ExecError> {
+        self.tmux(exec, &["kill-session", "-t", &self.name])
+            .map(|_| ())
+    }
+
+    fn tmux<E: Exec + ?Sized>(&self, exec: &mut E, args: &[&str]) -> Result<String, ExecError> {
+        let mut full: Vec<&str> = Vec::with_capacity(args.len() + 2);
+
+        if let Some(socket) = &self.socket {
+            full.extend(["-L", socket.as_str()]);
+        }
+
+        full.extend(args);
+        exec.exec("tmux", &full, self.context.as_ref())
+    }
+}
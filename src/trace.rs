@@ -0,0 +1,57 @@
+use crate::{quote, CommandSpec};
+use std::time::Duration;
+use tracing::field::Empty;
+
+/// Opens the span of a command or pipeline; duration and status are recorded once it finished
+pub(crate) fn span(specs: &[CommandSpec]) -> tracing::Span {
+    let command = specs
+        .iter()
+        .map(|spec| {
+            let parts: Vec<String> = std::iter::once(spec.get_program())
+                .chain(spec.get_args().iter().map(|arg| arg.as_os_str()))
+                .map(|part| part.to_string_lossy().into_owned())
+                .collect();
+
+            quote::join(parts.iter().map(String::as_str))
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let context = specs
+        .iter()
+        .map(|spec| match spec.get_context() {
+            Some(context) => context.to_string(),
+            None => "local".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    tracing::info_span!(
+        "exec",
+        command = %command,
+        context = %context,
+        duration_ms = Empty,
+        status = Empty
+    )
+}
+
+/// Records the outcome in the span and emits the lines written to stderr as warnings
+pub(crate) fn finish<'a>(
+    span: &tracing::Span,
+    duration: Duration,
+    status: Option<i32>,
+    stderr: impl IntoIterator<Item = &'a [u8]>,
+) {
+    span.record("duration_ms", duration.as_millis() as u64);
+
+    if let Some(status) = status {
+        span.record("status", status);
+    }
+
+    for stderr in stderr {
+        for line in String::from_utf8_lossy(stderr).lines() {
+            if !line.trim().is_empty() {
+                tracing::warn!(parent: span, stderr = %line);
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{hook, ssh, CommandExec, CommandSpec, Context, ExecError};
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Copying of files into and out of contexts
+#[cfg_attr(feature = "mockall", mockall::automock)]
+pub trait Transfer {
+    /// Copies a local file into the context
+    ///
+    /// * `local_path` - the file to be copied
+    /// * `remote_path` - path of the copy within the context
+    /// * `context` - either a local or a remote context
+    ///
+    fn upload<'a>(
+        &mut self,
+        local_path: &Path,
+        remote_path: &'a Path,
+        context: Option<&'a Context>,
+    ) -> Result<(), ExecError>;
+
+    /// Copies a file from the context to the local file system
+    ///
+    /// * `remote_path` - the file within the context to be copied
+    /// * `local_path` - path of the local copy
+    /// * `context` - either a local or a remote context
+    ///
+    fn download<'a>(
+        &mut self,
+        remote_path: &'a Path,
+        local_path: &Path,
+        context: Option<&'a Context>,
+    ) -> Result<(), ExecError>;
+}
+
+/// Files are copied by `scp` for remote contexts and by `docker cp` for containers; the files of
+/// containers are owned by root. Within other contexts, the content is streamed through `cat`,
+/// e.g. as the user of a local context, while the local file is accessed by the calling process.
+impl Transfer for CommandExec {
+    fn upload<'a>(
+        &mut self,
+        local_path: &Path,
+        remote_path: &'a Path,
+        context: Option<&'a Context>,
+    ) -> Result<(), ExecError> {
+        match context {
+            _ if self.is_direct(context) => {
+                std::fs::copy(local_path, remote_path)?;
+                Ok(())
+            }
+            Some(Context::Remote { host, .. }) => {
+                let com =
+                    self.scp_command(context, local_path.into(), target(host, remote_path))?;
+
+                run(com).map_err(|e| ssh::classify(host, e))
+            }
+            Some(Context::Docker { container, .. }) => {
+                run(docker_cp(local_path.into(), target(container, remote_path)))
+            }
+            Some(context) => self.copy_through(
+                CommandSpec::new("sh")
+                    .args(["-c", "cat > \"$1\"", "sh"])
+                    .arg(remote_path)
+                    .context(context.clone()),
+                File::open(local_path)?.into(),
+                Stdio::null(),
+            ),
+            None => unreachable!("commands without context are direct"),
+        }
+    }
+
+    fn download<'a>(
+        &mut self,
+        remote_path: &'a Path,
+        local_path: &Path,
+        context: Option<&'a Context>,
+    ) -> Result<(), ExecError> {
+        match context {
+            _ if self.is_direct(context) => {
+                std::fs::copy(remote_path, local_path)?;
+                Ok(())
+            }
+            Some(Context::Remote { host, .. }) => {
+                let com =
+                    self.scp_command(context, target(host, remote_path), local_path.into())?;
+
+                run(com).map_err(|e| ssh::classify(host, e))
+            }
+            Some(Context::Docker { container, .. }) => {
+                run(docker_cp(target(container, remote_path), local_path.into()))
+            }
+            Some(context) => self.copy_through(
+                CommandSpec::new("cat")
+                    .arg("--")
+                    .arg(remote_path)
+                    .context(context.clone()),
+                Stdio::null(),
+                File::create(local_path)?.into(),
+            ),
+            None => unreachable!("commands without context are direct"),
+        }
+    }
+}
+
+impl CommandExec {
+    /// Returns the `scp` invocation copying the file with the connection settings of the context
+    pub(crate) fn scp_command(
+        &self,
+        context: Option<&Context>,
+        from: OsString,
+        to: OsString,
+    ) -> Result<Command, ExecError> {
+        let Some(
+            remote @ Context::Remote {
+                config,
+                proxy,
+                settings,
+                ..
+            },
+        ) = context
+        else {
+            return Err(ExecError::Execution("scp requires a remote context".into()));
+        };
+        // scp accepts the options of ssh, which it uses for the connection
+        let ssh = self.ssh_command(config.as_deref(), proxy.as_ref(), settings.as_deref());
+        let mut com = Command::new("scp");
+
+        com.args(ssh.get_args());
+
+        if let Some(option) = self.control_option(remote) {
+            com.arg("-o").arg(option);
+        }
+
+        com.arg("--").arg(from).arg(to);
+        Ok(com)
+    }
+
+    /// Runs the command of the spec with its stdin and stdout connected to the files
+    fn copy_through(
+        &mut self,
+        spec: CommandSpec,
+        stdin: Stdio,
+        stdout: Stdio,
+    ) -> Result<(), ExecError> {
+        self.hooked(
+            &spec,
+            |exec, spec| {
+                let output = exec
+                    .command(spec)?
+                    .stdin(stdin)
+                    .stdout(stdout)
+                    .stderr(Stdio::piped())
+                    .output()?;
+
+                exec.check_status(Some(spec), output).map(|_| ())
+            },
+            |_| hook::output(String::new(), String::new(), 0),
+        )
+    }
+}
+
+/// Returns the `<prefix>:<path>` notation of scp and docker cp
+fn target(prefix: &str, path: &Path) -> OsString {
+    let mut target = OsString::from(prefix);
+
+    target.push(":");
+    target.push(path);
+    target
+}
+
+fn docker_cp(from: OsString, to: OsString) -> Command {
+    let mut com = Command::new("docker");
+
+    com.arg("cp").arg(from).arg(to);
+    com
+}
+
+fn run(mut com: Command) -> Result<(), ExecError> {
+    let output = com
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()?;
+
+    CommandExec::check_output(output).map(|_| ())
+}
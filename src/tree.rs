@@ -0,0 +1,418 @@
+use crate::limits::{Limiter, OutputLimit};
+use crate::{Cancellation, CommandSpec, ExecError};
+use std::io::Read;
+use std::process::{Child, ExitStatus, Output};
+use std::time::{Duration, Instant};
+
+/// Children spawned for a single execution
+///
+/// All stages of a pipeline are tracked, so that terminating the tree stops every one of them.
+/// To cover grandchildren (e.g. the command run by `sudo` or `sh` starting another program), the
+/// children of a grouped tree are started in process groups of their own on unix, which are
+/// killed as a whole. On Windows, the children are assigned to a job object.
+pub(crate) struct ProcessTree {
+    children: Vec<Child>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    grouped: bool,
+    limiter: Limiter,
+    #[cfg(windows)]
+    job: job::JobObject,
+}
+
+impl ProcessTree {
+    /// Creates an empty tree
+    ///
+    /// * `grouped` - start the children in process groups of their own (unix); as such commands
+    ///   are not in the foreground, they cannot read from the terminal
+    /// * `limit` - maximum size of each output stream captured from the children
+    ///
+    pub(crate) fn new(
+        grouped: bool,
+        limit: Option<OutputLimit>,
+        #[cfg(windows)] limits: &JobLimits,
+    ) -> Result<Self, ExecError> {
+        Ok(ProcessTree {
+            children: Vec::new(),
+            grouped,
+            limiter: Limiter::new(limit),
+            #[cfg(windows)]
+            job: job::JobObject::new(limits)?,
+        })
+    }
+
+    /// Prepares the command for being a child of the tree
+    ///
+    /// * `spec` - the spec the command was created from
+    /// * `com` - the command to be spawned
+    ///
+    pub(crate) fn prepare(&self, spec: &CommandSpec, com: &mut std::process::Command) {
+        prepare(self.grouped, spec, com);
+    }
+
+    pub(crate) fn push(&mut self, child: Child) -> Result<(), ExecError> {
+        #[cfg(windows)]
+        self.job.assign(&child)?;
+
+        self.children.push(child);
+        Ok(())
+    }
+
+    pub(crate) fn last_mut(&mut self) -> Option<&mut Child> {
+        self.children.last_mut()
+    }
+
+    /// Returns the limit of the captured output, shared with the readers of the streams
+    pub(crate) fn limiter(&self) -> Limiter {
+        self.limiter.clone()
+    }
+
+    /// Returns the process ids of the children
+    pub(crate) fn pids(&self) -> Vec<u32> {
+        self.children.iter().map(Child::id).collect()
+    }
+
+    /// Checks whether all children exited; the exited ones are reaped
+    #[cfg(unix)]
+    pub(crate) fn try_wait_all(&mut self) -> Result<bool, ExecError> {
+        let mut exited = true;
+
+        for child in self.children.iter_mut() {
+            exited &= child.try_wait()?.is_some();
+        }
+
+        Ok(exited)
+    }
+
+    /// Sends the signal to all children that did not exit yet (unix only)
+    #[cfg(unix)]
+    pub(crate) fn signal(&mut self, signal: i32) -> Result<(), ExecError> {
+        for child in self.children.iter_mut() {
+            // reaped children are skipped, as their ids may have been reused
+            if child.try_wait()?.is_some() {
+                continue;
+            }
+
+            // SAFETY: kill has no memory safety requirements; the child is not reaped yet
+            if unsafe { libc::kill(child.id() as libc::pid_t, signal) } == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Kills all processes of the tree and reaps the direct children
+    pub(crate) fn terminate(&mut self) {
+        #[cfg(windows)]
+        self.job.terminate();
+
+        for child in self.children.iter_mut() {
+            // the group outlives its leader as long as grandchildren are left
+            #[cfg(unix)]
+            if self.grouped {
+                // SAFETY: killpg has no memory safety requirements
+                unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) };
+            }
+
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    /// Waits for all children and returns the status of the last one
+    pub(crate) fn wait(mut self) -> Result<std::process::ExitStatus, ExecError> {
+        let mut last = self.children.pop().ok_or(ExecError::Chaining)?;
+        let status = last.wait()?;
+
+        for child in self.children.iter_mut() {
+            child.wait()?;
+        }
+
+        Ok(status)
+    }
+
+    /// Waits for the last child and returns its output and the statuses of the preceding children
+    ///
+    /// The preceding children are reaped after the last one.
+    pub(crate) fn wait_with_output(
+        mut self,
+    ) -> Result<(Output, Vec<std::process::ExitStatus>), ExecError> {
+        let last = self.children.pop().ok_or(ExecError::Chaining)?;
+        let output = last.wait_with_output()?;
+        let statuses = self
+            .children
+            .iter_mut()
+            .map(|child| child.wait())
+            .collect::<Result<_, _>>()?;
+
+        Ok((output, statuses))
+    }
+
+    /// Terminates the tree if the execution was cancelled, exceeded the timeout or produced more
+    /// output than allowed
+    pub(crate) fn check_interrupted(
+        &mut self,
+        start: Instant,
+        timeout: Option<Duration>,
+        cancellation: Option<&Cancellation>,
+    ) -> Result<(), ExecError> {
+        if let Err(e) = self.limiter.check() {
+            self.terminate();
+            return Err(e);
+        }
+
+        if cancellation.is_some_and(Cancellation::is_cancelled) {
+            self.terminate();
+            return Err(ExecError::Cancelled);
+        }
+
+        match timeout {
+            Some(timeout) if start.elapsed() >= timeout => {
+                self.terminate();
+                Err(ExecError::Timeout(timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for the last child, terminating the tree if the execution is interrupted
+    pub(crate) fn wait_interruptible(
+        &mut self,
+        start: Instant,
+        timeout: Option<Duration>,
+        cancellation: Option<&Cancellation>,
+    ) -> Result<ExitStatus, ExecError> {
+        loop {
+            if let Some(status) = self.last_mut().ok_or(ExecError::Chaining)?.try_wait()? {
+                return Ok(status);
+            }
+
+            self.check_interrupted(start, timeout, cancellation)?;
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Like `wait_with_output`, but terminates the tree once the timeout elapsed, the execution
+    /// was cancelled or the output exceeded `OutputLimit::Fail`, and keeps the output up to the limit
+    ///
+    /// On timeout, the threads reading the output are left behind, as processes started by the
+    /// children may still hold the pipes open.
+    pub(crate) fn wait_with_output_timeout(
+        mut self,
+        timeout: Option<Duration>,
+        cancellation: Option<&Cancellation>,
+    ) -> Result<(Output, Vec<ExitStatus>), ExecError> {
+        let limit = self.limiter.limit();
+
+        if timeout.is_none() && cancellation.is_none() && limit.is_none() {
+            return self.wait_with_output();
+        }
+
+        let start = Instant::now();
+        let limiter = self.limiter();
+        let last = self.children.last_mut().ok_or(ExecError::Chaining)?;
+        let stdout = read_to_end(last.stdout.take(), limiter.clone());
+        let stderr = read_to_end(last.stderr.take(), limiter);
+        let mut statuses: Vec<Option<ExitStatus>> = vec![None; self.children.len()];
+
+        while statuses.iter().any(Option::is_none) {
+            for (child, status) in self.children.iter_mut().zip(statuses.iter_mut()) {
+                if status.is_none() {
+                    *status = child.try_wait()?;
+                }
+            }
+
+            if statuses.iter().any(Option::is_none) {
+                self.check_interrupted(start, timeout, cancellation)?;
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let mut statuses: Vec<ExitStatus> = statuses.into_iter().flatten().collect();
+        let status = statuses.pop().ok_or(ExecError::Chaining)?;
+        let join = |reader: std::thread::JoinHandle<std::io::Result<(Vec<u8>, bool)>>| match reader
+            .join()
+            .map_err(|_| ExecError::Chaining)??
+        {
+            (_, true) if matches!(limit, Some(OutputLimit::Fail(_))) => Err(
+                ExecError::OutputTooLarge(limit.map_or(0, |limit| limit.bytes())),
+            ),
+            (output, _) => Ok(output),
+        };
+
+        Ok((
+            Output {
+                status,
+                stdout: join(stdout)?,
+                stderr: join(stderr)?,
+            },
+            statuses,
+        ))
+    }
+}
+
+/// Starts the command in a process group of its own if requested (unix)
+///
+/// Commands starting a new session are the leaders of their own process group anyway.
+///
+/// * `grouped` - whether the command is started in its own process group
+/// * `spec` - the spec the command was created from
+/// * `com` - the command to be spawned
+///
+pub(crate) fn prepare(grouped: bool, spec: &CommandSpec, com: &mut std::process::Command) {
+    #[cfg(unix)]
+    if grouped && !spec.get_new_session() {
+        std::os::unix::process::CommandExt::process_group(com, 0);
+    }
+
+    #[cfg(not(unix))]
+    let _ = (grouped, spec, com);
+}
+
+/// Reads the pipe, if any, to the end in a thread; returns the kept output and whether some was discarded
+pub(crate) fn read_to_end<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    limiter: Limiter,
+) -> std::thread::JoinHandle<std::io::Result<(Vec<u8>, bool)>> {
+    std::thread::spawn(move || match pipe {
+        Some(mut pipe) => limiter.read(&mut pipe),
+        None => Ok((Vec::new(), false)),
+    })
+}
+
+/// Limits applied to the job object containing the children (Windows only)
+///
+/// * `cpu_time` - user-mode CPU time available to all processes of the job
+/// * `memory` - committed memory in bytes available to all processes of the job
+///
+#[cfg(windows)]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct JobLimits {
+    pub cpu_time: Option<std::time::Duration>,
+    pub memory: Option<usize>,
+}
+
+#[cfg(windows)]
+mod job {
+    use super::JobLimits;
+    use crate::ExecError;
+    use std::ffi::c_void;
+    use std::os::windows::io::AsRawHandle;
+
+    type Handle = *mut c_void;
+
+    const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS: i32 = 9;
+    const JOB_OBJECT_LIMIT_JOB_TIME: u32 = 0x0000_0004;
+    const JOB_OBJECT_LIMIT_JOB_MEMORY: u32 = 0x0000_0200;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct BasicLimitInformation {
+        per_process_user_time_limit: i64,
+        per_job_user_time_limit: i64,
+        limit_flags: u32,
+        minimum_working_set_size: usize,
+        maximum_working_set_size: usize,
+        active_process_limit: u32,
+        affinity: usize,
+        priority_class: u32,
+        scheduling_class: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct IoCounters {
+        read_operation_count: u64,
+        write_operation_count: u64,
+        other_operation_count: u64,
+        read_transfer_count: u64,
+        write_transfer_count: u64,
+        other_transfer_count: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ExtendedLimitInformation {
+        basic_limit_information: BasicLimitInformation,
+        io_info: IoCounters,
+        process_memory_limit: usize,
+        job_memory_limit: usize,
+        peak_process_memory_used: usize,
+        peak_job_memory_used: usize,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> Handle;
+        fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+        fn SetInformationJobObject(job: Handle, class: i32, info: *mut c_void, len: u32) -> i32;
+        fn TerminateJobObject(job: Handle, exit_code: u32) -> i32;
+        fn CloseHandle(handle: Handle) -> i32;
+    }
+
+    pub(crate) struct JobObject(Handle);
+
+    impl JobObject {
+        pub(crate) fn new(limits: &JobLimits) -> Result<Self, ExecError> {
+            // SAFETY: both arguments may be null; the returned handle is checked below
+            let handle = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+
+            if handle.is_null() {
+                return Err(ExecError::Io(std::io::Error::last_os_error()));
+            }
+
+            let job = JobObject(handle);
+            let mut info = ExtendedLimitInformation::default();
+
+            if let Some(cpu_time) = limits.cpu_time {
+                // the limit is given in 100 ns ticks
+                info.basic_limit_information.per_job_user_time_limit =
+                    (cpu_time.as_nanos() / 100) as i64;
+                info.basic_limit_information.limit_flags |= JOB_OBJECT_LIMIT_JOB_TIME;
+            }
+
+            if let Some(memory) = limits.memory {
+                info.job_memory_limit = memory;
+                info.basic_limit_information.limit_flags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+            }
+
+            if info.basic_limit_information.limit_flags != 0 {
+                // SAFETY: the handle is valid and info is a correctly sized extended limit structure
+                let res = unsafe {
+                    SetInformationJobObject(
+                        job.0,
+                        JOB_OBJECT_EXTENDED_LIMIT_INFORMATION_CLASS,
+                        &mut info as *mut ExtendedLimitInformation as *mut c_void,
+                        std::mem::size_of::<ExtendedLimitInformation>() as u32,
+                    )
+                };
+
+                if res == 0 {
+                    return Err(ExecError::Io(std::io::Error::last_os_error()));
+                }
+            }
+
+            Ok(job)
+        }
+
+        pub(crate) fn assign(&self, child: &std::process::Child) -> Result<(), ExecError> {
+            // SAFETY: both handles are valid for the duration of the call
+            match unsafe { AssignProcessToJobObject(self.0, child.as_raw_handle() as Handle) } {
+                0 => Err(ExecError::Io(std::io::Error::last_os_error())),
+                _ => Ok(()),
+            }
+        }
+
+        pub(crate) fn terminate(&self) {
+            // SAFETY: the handle is valid
+            unsafe { TerminateJobObject(self.0, 1) };
+        }
+    }
+
+    impl Drop for JobObject {
+        fn drop(&mut self) {
+            // SAFETY: the handle is valid and not used afterwards
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+}